@@ -0,0 +1,93 @@
+//! Replay throughput with decimal and fixed-point books, the fixed-point ones also kept in the
+//! array store, over a simulated Binance session: the book alone applying the parsed depth
+//! events, and the whole replay from the lines.
+//!
+//!     cargo bench --bench replay
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use shougoutaku::book_store::BookStore;
+use shougoutaku::engine::{self, ReplayOptions, Streams, TradeStreams};
+use shougoutaku::exchange::{BookEvent, Exchange};
+use shougoutaku::fixed_point::{FixedPoint, Representation};
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::OrderBook;
+use shougoutaku::simulation::{self, Simulation, SimulationConfig};
+
+/// Sizes of the simulated BTCUSDT, with the scale of the simulated messages.
+const FIXED_POINT: &str = "0.01000000:0.00001000";
+
+fn session() -> Simulation {
+    simulation::simulate(&SimulationConfig { duration: 600_000, action_rate: 50.0, ..SimulationConfig::default() })
+}
+
+fn variants() -> [(&'static str, Representation, BookStore); 3] {
+    let fixed = Representation::Fixed(FIXED_POINT.parse::<FixedPoint>().unwrap());
+    [
+        ("decimal", Representation::Decimal, BookStore::BTree),
+        ("fixed-point", fixed, BookStore::BTree),
+        ("fixed-point-array", fixed, BookStore::Array),
+    ]
+}
+
+fn snapshot(session: &Simulation) -> SnapShotUpdate {
+    Exchange::Binance.adapter().parse_snapshot(&session.snapshot).unwrap()
+}
+
+fn book(c: &mut Criterion) {
+    let session = session();
+    let mut adapter = Exchange::Binance.adapter();
+    let events: Vec<DepthUpdate> = session.depth.iter()
+        .flat_map(|line| adapter.parse_book(line).unwrap())
+        .filter_map(|event| match event {
+            BookEvent::Depth(update) => Some(update),
+            BookEvent::Snapshot(_) => None,
+        })
+        .collect();
+    let mut group = c.benchmark_group("book");
+    group.throughput(Throughput::Elements(events.len() as u64));
+    for (name, representation, store) in variants() {
+        group.bench_function(name, |b| b.iter_batched(
+            || {
+                let mut book = OrderBook::new().with_representation(representation).with_store(store);
+                book.try_update_with_snapshot(snapshot(&session)).unwrap();
+                (book, events.clone())
+            },
+            |(mut book, events)| {
+                for update in events {
+                    book.try_update(update).unwrap();
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        ));
+    }
+    group.finish();
+}
+
+fn replay(c: &mut Criterion) {
+    let session = session();
+    let mut group = c.benchmark_group("replay");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(session.depth.len() as u64));
+    for (name, representation, store) in variants() {
+        let mut options = ReplayOptions { book_store: store, ..ReplayOptions::new(Exchange::Binance) };
+        if let Representation::Fixed(fixed_point) = representation {
+            options.fixed_points.insert(None, fixed_point);
+        }
+        group.bench_function(name, |b| b.iter(|| {
+            let streams = Streams {
+                depth: engine::lines_of(session.depth.clone()),
+                trades: TradeStreams::BySide {
+                    ask: engine::lines_of(session.ask_trades.clone()),
+                    bid: engine::lines_of(session.bid_trades.clone()),
+                },
+            };
+            engine::reconcile(&options, "", Some(snapshot(&session)), streams).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, book, replay);
+criterion_main!(benches);
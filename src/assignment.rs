@@ -0,0 +1,104 @@
+/// Cost of leaving a row unassigned, above the cost of any admissible pair.
+const UNASSIGNED_COST: f64 = 1e6;
+
+/// Minimum-cost one-to-one assignment of rows to columns (Hungarian algorithm).
+///
+/// `costs[row][column]` is the cost of pairing them, `f64::INFINITY` when they cannot be paired.
+/// Rows may outnumber columns or the other way round; rows left without an admissible column
+/// get `None`.
+pub fn min_cost_assignment(costs: &[Vec<f64>]) -> Vec<Option<usize>> {
+    // Rows and columns without any admissible pair are left out, which keeps the problem
+    // small when most rows cannot be paired yet
+    let rows: Vec<usize> = (0..costs.len())
+        .filter(|row| costs[*row].iter().any(|cost| cost.is_finite()))
+        .collect();
+    let columns: Vec<usize> = (0..costs.first().map_or(0, Vec::len))
+        .filter(|column| rows.iter().any(|row| costs[*row][*column].is_finite()))
+        .collect();
+    let reduced: Vec<Vec<f64>> = rows.iter()
+        .map(|row| columns.iter().map(|column| costs[*row][*column]).collect())
+        .collect();
+    let mut assignment = vec![None; costs.len()];
+    for (row, column) in rows.iter().zip(assign(&reduced)) {
+        assignment[*row] = column.map(|column| columns[column]);
+    }
+    assignment
+}
+
+fn assign(costs: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    if rows == 0 {
+        return Vec::new();
+    }
+    let columns = costs[0].len();
+    // One dummy column per row lets every row go unassigned, so that rows <= all columns
+    let width = columns + rows;
+    let cost = |row: usize, column: usize| -> f64 {
+        match costs[row].get(column) {
+            Some(cost) if cost.is_finite() => *cost,
+            Some(_) => UNASSIGNED_COST * 2.0,
+            None => UNASSIGNED_COST,
+        }
+    };
+
+    // Potentials and matching are 1-based, index 0 being a virtual row and column
+    let mut row_potential = vec![0.0; rows + 1];
+    let mut column_potential = vec![0.0; width + 1];
+    let mut column_row = vec![0usize; width + 1];
+    let mut way = vec![0usize; width + 1];
+    for row in 1..=rows {
+        column_row[0] = row;
+        let mut column = 0;
+        let mut min_slack = vec![f64::INFINITY; width + 1];
+        let mut used = vec![false; width + 1];
+        loop {
+            used[column] = true;
+            let current_row = column_row[column];
+            let mut delta = f64::INFINITY;
+            let mut next_column = 0;
+            for candidate in 1..=width {
+                if used[candidate] {
+                    continue;
+                }
+                let slack = cost(current_row - 1, candidate - 1) - row_potential[current_row] - column_potential[candidate];
+                if slack < min_slack[candidate] {
+                    min_slack[candidate] = slack;
+                    way[candidate] = column;
+                }
+                if min_slack[candidate] < delta {
+                    delta = min_slack[candidate];
+                    next_column = candidate;
+                }
+            }
+            for candidate in 0..=width {
+                if used[candidate] {
+                    row_potential[column_row[candidate]] += delta;
+                    column_potential[candidate] -= delta;
+                } else {
+                    min_slack[candidate] -= delta;
+                }
+            }
+            column = next_column;
+            if column_row[column] == 0 {
+                break;
+            }
+        }
+        loop {
+            let previous = way[column];
+            column_row[column] = column_row[previous];
+            column = previous;
+            if column == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for column in 1..=columns {
+        let row = column_row[column];
+        if row != 0 && costs[row - 1][column - 1].is_finite() {
+            assignment[row - 1] = Some(column - 1);
+        }
+    }
+    assignment
+}
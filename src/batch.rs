@@ -0,0 +1,171 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use log::{info, error};
+
+use crate::book_state::{self, SNAPSHOTS_DIR};
+use crate::engine::{self, percentile, ReplayOptions, ReplayReport, Streams, SymbolReport, TradeStreams};
+use crate::error::EngineError;
+
+const SESSION_FILES: [&str; 4] = ["snapshot.txt", "depth.txt", "ask_trade.txt", "bid_trade.txt"];
+
+/// Outcome of reconciling one capture session folder.
+pub struct SessionSummary {
+    pub session: PathBuf,
+    pub outcome: Result<ReplayReport, String>,
+}
+
+/// Finds every `<symbol>/<session>/` folder under `root` holding a snapshot.
+/// Folders with missing capture files are still returned so that they fail loudly in the summary.
+pub fn discover_sessions(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut sessions = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if dir.join(SESSION_FILES[0]).is_file() {
+            sessions.push(dir);
+            continue;
+        }
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            }
+        }
+    }
+    sessions.sort();
+    Ok(sessions)
+}
+
+/// Reconciles a single session folder; the symbol is the name of its parent folder.
+/// The book is checked against the snapshots of the session's `snapshots/` folder, if any.
+pub fn reconcile_session(session: &Path, options: &ReplayOptions) -> Result<SymbolReport, EngineError> {
+    for file in SESSION_FILES {
+        if !session.join(file).is_file() {
+            return Err(EngineError::config(format!("missing {}", file)));
+        }
+    }
+    let symbol = session.parent()
+        .and_then(|parent| parent.file_name())
+        .map(|name| name.to_string_lossy().to_uppercase())
+        .unwrap_or_default();
+    let snapshot = engine::load_snapshot(options.exchange, &session.join("snapshot.txt"))?;
+    let snapshots = session.join(SNAPSHOTS_DIR);
+    let validation = if snapshots.is_dir() { book_state::load_dir(options.exchange, &snapshots)? } else { Vec::new() };
+    let streams = Streams {
+        depth: engine::open_lines(&session.join("depth.txt"))?,
+        trades: TradeStreams::BySide {
+            ask: engine::open_lines(&session.join("ask_trade.txt"))?,
+            bid: engine::open_lines(&session.join("bid_trade.txt"))?,
+        },
+    };
+    engine::reconcile_validated(options, &symbol, Some(snapshot), validation, streams)
+}
+
+/// Reconciles the sessions on at most `jobs` worker threads, keeping the input order.
+pub fn run_batch(sessions: &[PathBuf], options: &ReplayOptions, jobs: usize) -> Vec<SessionSummary> {
+    let next_session = AtomicUsize::new(0);
+    let outcomes: Mutex<Vec<Option<Result<ReplayReport, String>>>> = Mutex::new(sessions.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, sessions.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next_session.fetch_add(1, Ordering::Relaxed);
+                let Some(session) = sessions.get(index) else { break };
+                info!("Reconciling session {}", session.display());
+                let outcome = reconcile_session(session, options).map(ReplayReport::from).map_err(|e| e.to_string());
+                if let Err(e) = &outcome {
+                    error!("Session {} failed: {}", session.display(), e);
+                }
+                outcomes.lock().unwrap()[index] = Some(outcome);
+            });
+        }
+    });
+    sessions.iter()
+        .zip(outcomes.into_inner().unwrap())
+        .map(|(session, outcome)| SessionSummary {
+            session: session.clone(),
+            outcome: outcome.unwrap_or_else(|| Err(String::from("not reconciled"))),
+        })
+        .collect()
+}
+
+fn format_lag(lags: &[i64], p: f64) -> String {
+    percentile(lags, p).map(|lag| lag.to_string()).unwrap_or_else(|| String::from("-"))
+}
+
+/// Counters of one summary row.
+#[derive(Default)]
+struct Counters {
+    matched: usize,
+    dropped: usize,
+    purged: usize,
+    gaps: u64,
+    malformed: u64,
+    drifted: usize,
+}
+
+impl Counters {
+    fn of(report: &SymbolReport) -> Self {
+        Self {
+            matched: report.matched(),
+            dropped: report.dropped(),
+            purged: report.purged(),
+            gaps: report.sequence_gaps,
+            malformed: report.malformed_lines.total(),
+            drifted: report.drifted_snapshots(),
+        }
+    }
+
+    fn add(&mut self, other: &Counters) {
+        self.matched += other.matched;
+        self.dropped += other.dropped;
+        self.purged += other.purged;
+        self.gaps += other.gaps;
+        self.malformed += other.malformed;
+        self.drifted += other.drifted;
+    }
+}
+
+fn write_row(out: &mut impl Write, name: &str, counters: &Counters, lags: &[i64]) -> io::Result<()> {
+    let Counters { matched, dropped, purged, gaps, malformed, drifted } = *counters;
+    let total = matched + dropped + purged;
+    let match_rate = if total == 0 { 0.0 } else { 100.0 * matched as f64 / total as f64 };
+    writeln!(out, "{}\t{}\t{:.2}%\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        name, total, match_rate, matched, dropped, purged, gaps, malformed, drifted,
+        format_lag(lags, 50.0), format_lag(lags, 90.0), format_lag(lags, 99.0))
+}
+
+/// Writes one tab-separated row per session followed by the combined totals. Lag percentiles are in milliseconds between trade and
+/// matched depth event; malformed lines are those of every stream left out of the replay,
+/// the total also counting those routed to no symbol; drifted snapshots are those taken
+/// during the capture that the replayed book did not match.
+pub fn write_summary(out: &mut impl Write, summaries: &[SessionSummary]) -> io::Result<()> {
+    writeln!(out, "Session\tTrades\tMatch rate\tMatched\tDropped\tPurged\tGaps\tMalformed\tDrifted\tLag p50\tLag p90\tLag p99")?;
+    let mut totals = Counters::default();
+    let mut all_lags = Vec::new();
+    let mut failed = 0;
+    for summary in summaries {
+        let name = summary.session.display().to_string();
+        match &summary.outcome {
+            Ok(replay) => {
+                for report in &replay.reports {
+                    let lags = report.lags();
+                    let counters = Counters::of(report);
+                    write_row(out, &name, &counters, &lags)?;
+                    totals.add(&counters);
+                    all_lags.extend(lags);
+                }
+                totals.malformed += replay.unroutable.total();
+            }
+            Err(e) => {
+                failed += 1;
+                writeln!(out, "{}\tFAILED: {}", name, e)?;
+            }
+        }
+    }
+    all_lags.sort_unstable();
+    let combined = format!("TOTAL ({} sessions, {} failed)", summaries.len(), failed);
+    write_row(out, &combined, &totals, &all_lags)
+}
@@ -0,0 +1,406 @@
+//! Book state at a point in time: a reconstructed book saved as a snapshot in the Binance REST
+//! format or a compact binary one, and the levels where a book replayed from depth events
+//! differs from a snapshot the exchange took later, to validate the replay against the exchange.
+//! A replay given the snapshots taken periodically during a capture checks its book against
+//! each of them as it goes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use log::{debug, info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::LineSource;
+use crate::error::EngineError;
+use crate::exchange::{BookEvent, Exchange};
+use crate::messages::{DepthUpdate, SnapShotUpdate};
+use crate::orderbook::{CrossRepair, OrderBook};
+use crate::trade_matcher::TradeType;
+
+/// First bytes of a binary book, telling it from the exchanges' text snapshots.
+const MAGIC: &[u8; 4] = b"SGBK";
+/// Version of the binary format; books of other versions are refused.
+const VERSION: u8 = 1;
+
+/// Folder of a capture session holding the snapshots fetched during the capture.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// How a book is written to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BookFormat {
+    /// The Binance REST snapshot, `{"lastUpdateId":..,"bids":[["price","quantity"],..],"asks":..}`.
+    #[default]
+    Json,
+    /// The update id and levels, each decimal as its scale and varint mantissa.
+    Binary,
+}
+
+impl FromStr for BookFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(BookFormat::Json),
+            "binary" => Ok(BookFormat::Binary),
+            other => Err(format!("Unknown book format: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for BookFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BookFormat::Json => "json",
+            BookFormat::Binary => "binary",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Encodes a snapshot in a format. Both keep every price and quantity with its scale, so
+/// decoding gives back the same strings.
+pub fn encode(snapshot: &SnapShotUpdate, format: BookFormat) -> Vec<u8> {
+    match format {
+        BookFormat::Json => serde_json::to_vec(snapshot).expect("snapshots serialise to JSON"),
+        BookFormat::Binary => to_binary(snapshot),
+    }
+}
+
+/// Encodes a snapshot in the binary format: magic, version, little-endian update id and
+/// level counts, then each level's price and quantity, best level first.
+pub fn to_binary(snapshot: &SnapShotUpdate) -> Vec<u8> {
+    let levels = snapshot.bids.len() + snapshot.asks.len();
+    let mut bytes = Vec::with_capacity(21 + 16 * levels);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&snapshot.last_update_id.to_le_bytes());
+    bytes.extend_from_slice(&(snapshot.bids.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(snapshot.asks.len() as u32).to_le_bytes());
+    for (price, quantity) in snapshot.bids.iter().chain(&snapshot.asks) {
+        put_decimal(&mut bytes, *price);
+        put_decimal(&mut bytes, *quantity);
+    }
+    bytes
+}
+
+/// A decimal as its scale and its zigzag-encoded mantissa, seven bits per byte.
+fn put_decimal(bytes: &mut Vec<u8>, value: Decimal) {
+    bytes.push(value.scale() as u8);
+    let mantissa = value.mantissa();
+    let mut zigzag = ((mantissa << 1) ^ (mantissa >> 127)) as u128;
+    while zigzag >= 0x80 {
+        bytes.push(zigzag as u8 | 0x80);
+        zigzag >>= 7;
+    }
+    bytes.push(zigzag as u8);
+}
+
+/// Decodes a snapshot written by `to_binary`.
+pub fn from_binary(bytes: &[u8]) -> Result<SnapShotUpdate, EngineError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(EngineError::parse("Not a binary book"));
+    }
+    let version = reader.take(1)?[0];
+    if version != VERSION {
+        return Err(EngineError::parse(format!("Binary book format {} is not the supported format {}", version, VERSION)));
+    }
+    let last_update_id = u64::from_le_bytes(reader.array()?);
+    let bid_levels = u32::from_le_bytes(reader.array()?) as usize;
+    let ask_levels = u32::from_le_bytes(reader.array()?) as usize;
+    let mut levels = |count: usize| -> Result<Vec<(Decimal, Decimal)>, EngineError> {
+        // Each level takes at least four bytes, which bounds the counts of a corrupt book
+        let mut side = Vec::with_capacity(count.min(bytes.len() / 4));
+        for _ in 0..count {
+            side.push((reader.decimal()?, reader.decimal()?));
+        }
+        Ok(side)
+    };
+    let bids = levels(bid_levels)?;
+    let asks = levels(ask_levels)?;
+    if reader.position != bytes.len() {
+        return Err(EngineError::parse(format!("Binary book has {} trailing bytes", bytes.len() - reader.position)));
+    }
+    Ok(SnapShotUpdate { last_update_id, bids, asks })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], EngineError> {
+        let end = self.position + n;
+        let taken = self.bytes.get(self.position..end)
+            .ok_or_else(|| EngineError::parse(format!("Binary book truncated at byte {}", self.bytes.len())))?;
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], EngineError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn decimal(&mut self) -> Result<Decimal, EngineError> {
+        let scale = self.take(1)?[0] as u32;
+        let mut zigzag = 0u128;
+        for shift in (0..128).step_by(7) {
+            let byte = self.take(1)?[0];
+            zigzag |= ((byte & 0x7f) as u128) << shift;
+            if byte & 0x80 == 0 {
+                let mantissa = (zigzag >> 1) as i128 ^ -((zigzag & 1) as i128);
+                return Ok(Decimal::try_from_i128_with_scale(mantissa, scale)?);
+            }
+        }
+        Err(EngineError::parse(format!("Binary book has an overlong decimal at byte {}", self.position)))
+    }
+}
+
+/// Writes a book as a snapshot at its last update id.
+pub fn save(path: &Path, book: &OrderBook, format: BookFormat) -> Result<(), EngineError> {
+    fs::write(path, encode(&book.to_snapshot(), format)).map_err(|e| EngineError::io(path, e))
+}
+
+/// Reads a snapshot file, either binary or in the exchange's own format.
+pub fn load(exchange: Exchange, path: &Path) -> Result<SnapShotUpdate, EngineError> {
+    let bytes = fs::read(path).map_err(|e| EngineError::io(path, e))?;
+    let snapshot = if bytes.starts_with(MAGIC) {
+        from_binary(&bytes)
+    } else {
+        String::from_utf8(bytes)
+            .map_err(EngineError::parse)
+            .and_then(|raw| exchange.adapter().parse_snapshot(&raw))
+    };
+    snapshot.map_err(|e| e.at(path.display(), None))
+}
+
+/// Reads every snapshot file of a folder, e.g. the `snapshots/` a capture fetched
+/// periodically, in update id order.
+pub fn load_dir(exchange: Exchange, dir: &Path) -> Result<Vec<SnapShotUpdate>, EngineError> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| EngineError::io(dir, e))? {
+        let path = entry.map_err(|e| EngineError::io(dir, e))?.path();
+        if path.is_file() {
+            snapshots.push(load(exchange, &path)?);
+        }
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.last_update_id);
+    Ok(snapshots)
+}
+
+/// A book replayed from a snapshot up to an update id.
+pub struct BookAt {
+    pub book: OrderBook,
+    /// The depth event spanning the update id, left unapplied since a book cannot stop within an event.
+    pub straddling: Option<DepthUpdate>,
+}
+
+/// Replays the book stream over a snapshot, applying every depth event that ends at or before
+/// `update_id`. Checksum mismatches are logged rather than dropping the book, since the
+/// point is to see where it drifted.
+pub fn replay_to(exchange: Exchange, cross_repair: CrossRepair, snapshot: SnapShotUpdate, depth: LineSource, update_id: u64) -> Result<BookAt, EngineError> {
+    if update_id < snapshot.last_update_id {
+        return Err(EngineError::config(format!("Cannot replay to update {} from a snapshot at update {}", update_id, snapshot.last_update_id)));
+    }
+    let mut book = OrderBook::with_cross_repair(cross_repair);
+    book.update_with_snapshot(snapshot);
+    let mut adapter = exchange.adapter();
+    for (index, line) in depth.enumerate() {
+        let located = |e: EngineError| e.at("depth stream", Some(index as u64 + 1));
+        let line = line.map_err(|e| located(e.into()))?;
+        for event in adapter.parse_book(&line).map_err(located)? {
+            match event {
+                BookEvent::Snapshot(snapshot) if snapshot.last_update_id <= update_id => book.update_with_snapshot(snapshot),
+                BookEvent::Depth(update) if update.final_update_id_in_event <= update_id => book.update(update),
+                BookEvent::Depth(update) if update.first_update_id_in_event <= update_id => {
+                    return Ok(BookAt { book, straddling: Some(update) });
+                }
+                _ => return Ok(BookAt { book, straddling: None }),
+            }
+            if let Err(e) = adapter.verify_book(&mut book) {
+                warn!("{} at update {}", e, book.last_update_id());
+            }
+        }
+    }
+    Ok(BookAt { book, straddling: None })
+}
+
+/// A level whose quantity differs between the replayed book and the snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelMismatch {
+    pub side: TradeType,
+    pub price: Decimal,
+    /// Quantity in the replayed book, `None` when it has no level at the price.
+    pub book: Option<Decimal>,
+    /// Quantity in the snapshot, `None` when it has no level at the price.
+    pub snapshot: Option<Decimal>,
+    /// Whether the event straddling the snapshot's update id changes the level, in which
+    /// case the difference may be that event's rather than drift.
+    pub in_flight: bool,
+}
+
+/// How a replayed book compares with a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookDiff {
+    pub book_update_id: u64,
+    pub snapshot_update_id: u64,
+    /// Sequence gaps met while replaying the book.
+    pub sequence_gaps: u64,
+    /// Price levels compared, those of either book within the depth both cover.
+    pub compared_levels: usize,
+    /// Mismatched levels, bids then asks, best first.
+    pub mismatches: Vec<LevelMismatch>,
+}
+
+impl BookDiff {
+    /// Whether every compared level matches.
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Mismatched levels the straddling event does not account for.
+    pub fn drift(&self) -> usize {
+        self.mismatches.iter().filter(|mismatch| !mismatch.in_flight).count()
+    }
+
+    pub fn print(&self) {
+        let mut mismatches = String::from("Level mismatches\n\tSide\tPrice\tBook\tSnapshot\n");
+        let quantity = |quantity: Option<Decimal>| quantity.map(|quantity| quantity.to_string()).unwrap_or_else(|| String::from("-"));
+        for mismatch in &self.mismatches {
+            mismatches.push_str(&format!("\t{:?}\t{}\t{}\t{}{}\n", mismatch.side, mismatch.price, quantity(mismatch.book), quantity(mismatch.snapshot),
+                if mismatch.in_flight { "\t(in flight)" } else { "" }));
+        }
+        info!("{}", mismatches.trim_end());
+        let in_flight = self.mismatches.iter().filter(|mismatch| mismatch.in_flight).count();
+        info!("book at update {} snapshot at update {} sequence gaps: {}", self.book_update_id, self.snapshot_update_id, self.sequence_gaps);
+        info!("compared levels: {} mismatched: {} in flight: {}", self.compared_levels, self.mismatches.len(), in_flight);
+    }
+}
+
+/// Compares a replayed book with a snapshot, level by level, the levels changed by the event
+/// `straddling` the snapshot's update id being in flight. A REST snapshot only holds the best
+/// levels of each side, and a replayed book only knows the levels of its own snapshot and
+/// those updated since, so levels are compared down to the shallower of the two sides.
+pub fn diff(book: &OrderBook, straddling: Option<&DepthUpdate>, snapshot: &SnapShotUpdate) -> BookDiff {
+    let replayed = book.to_snapshot();
+    let (in_flight_bids, in_flight_asks) = match straddling {
+        Some(update) => (prices(&update.bids_to_update), prices(&update.asks_to_update)),
+        None => (BTreeSet::new(), BTreeSet::new()),
+    };
+    let mut mismatches = Vec::new();
+    let compared_levels = diff_side(TradeType::Bid, &replayed.bids, &snapshot.bids, &in_flight_bids, &mut mismatches)
+        + diff_side(TradeType::Ask, &replayed.asks, &snapshot.asks, &in_flight_asks, &mut mismatches);
+    BookDiff {
+        book_update_id: replayed.last_update_id,
+        snapshot_update_id: snapshot.last_update_id,
+        sequence_gaps: book.sequence_gaps(),
+        compared_levels,
+        mismatches,
+    }
+}
+
+fn prices(levels: &[(Decimal, Decimal)]) -> BTreeSet<Decimal> {
+    levels.iter().map(|(price, _)| *price).collect()
+}
+
+/// Adds the mismatched levels of one side, best first, and gives the number of levels compared.
+fn diff_side(side: TradeType, book: &[(Decimal, Decimal)], snapshot: &[(Decimal, Decimal)], in_flight: &BTreeSet<Decimal>, mismatches: &mut Vec<LevelMismatch>) -> usize {
+    let levels = |levels: &[(Decimal, Decimal)]| -> BTreeMap<Decimal, Decimal> {
+        levels.iter().filter(|(_, quantity)| !quantity.is_zero()).copied().collect()
+    };
+    let (book, snapshot) = (levels(book), levels(snapshot));
+    let worst = |levels: &BTreeMap<Decimal, Decimal>| match side {
+        TradeType::Bid => levels.keys().next().copied(),
+        TradeType::Ask => levels.keys().next_back().copied(),
+    };
+    // Deepest price both books reach, when both have levels
+    let bound = worst(&book).zip(worst(&snapshot)).map(|(book, snapshot)| match side {
+        TradeType::Bid => book.max(snapshot),
+        TradeType::Ask => book.min(snapshot),
+    });
+    let within = |price: &Decimal| match (side, bound) {
+        (_, None) => true,
+        (TradeType::Bid, Some(bound)) => *price >= bound,
+        (TradeType::Ask, Some(bound)) => *price <= bound,
+    };
+    let compared: BTreeSet<&Decimal> = book.keys().chain(snapshot.keys()).filter(|price| within(price)).collect();
+    let best_first: Box<dyn Iterator<Item = &&Decimal>> = match side {
+        TradeType::Bid => Box::new(compared.iter().rev()),
+        TradeType::Ask => Box::new(compared.iter()),
+    };
+    for &&price in best_first {
+        let (book, snapshot) = (book.get(&price).copied(), snapshot.get(&price).copied());
+        if book != snapshot {
+            mismatches.push(LevelMismatch { side, price, book, snapshot, in_flight: in_flight.contains(&price) });
+        }
+    }
+    compared.len()
+}
+
+/// Checks the book of a replay against the snapshots taken during its capture, each just
+/// before the first depth event ending past the snapshot's update id.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Validator {
+    /// Snapshots not reached yet, latest first; checkpoints leave them out since the replay
+    /// is given them again when it resumes.
+    #[serde(skip)]
+    pending: Vec<SnapShotUpdate>,
+    /// Final update id of the last depth event checked for snapshots it moves the book past.
+    passed: u64,
+    checks: Vec<BookDiff>,
+}
+
+impl Validator {
+    pub fn new(mut snapshots: Vec<SnapShotUpdate>) -> Self {
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.last_update_id));
+        Self { pending: snapshots, ..Self::default() }
+    }
+
+    /// Takes over the checks saved in a checkpoint, leaving out the snapshots they covered.
+    pub(crate) fn restore(&mut self, saved: Validator) {
+        self.pending.retain(|snapshot| snapshot.last_update_id >= saved.passed);
+        self.passed = saved.passed;
+        self.checks = saved.checks;
+    }
+
+    /// Compares the book with every snapshot `update` is about to move it past. Snapshots
+    /// older than the book, or met while the book waits for a resync, cannot be checked.
+    pub fn check_before(&mut self, symbol: &str, book: &OrderBook, update: &DepthUpdate) {
+        self.passed = update.final_update_id_in_event;
+        while self.pending.last().is_some_and(|snapshot| snapshot.last_update_id < self.passed) {
+            let Some(snapshot) = self.pending.pop() else { break };
+            if !book.is_synced() || snapshot.last_update_id < book.last_update_id() {
+                warn!("{} - Cannot check the book against the snapshot at update {} from update {}", symbol, snapshot.last_update_id, book.last_update_id());
+                continue;
+            }
+            let straddling = (update.first_update_id_in_event <= snapshot.last_update_id).then_some(update);
+            self.check(symbol, diff(book, straddling, &snapshot));
+        }
+    }
+
+    fn check(&mut self, symbol: &str, diff: BookDiff) {
+        if diff.drift() > 0 {
+            warn!("{} - Book drifted from the snapshot at update {}: {} of {} levels mismatched", symbol, diff.snapshot_update_id, diff.drift(), diff.compared_levels);
+        } else {
+            debug!("{} - Book matches the snapshot at update {}", symbol, diff.snapshot_update_id);
+        }
+        self.checks.push(diff);
+    }
+
+    /// Checks the snapshots at the final update id of the book once the book stream has
+    /// ended and gives every check; later snapshots were never reached.
+    pub fn finish(mut self, symbol: &str, book: &OrderBook) -> Vec<BookDiff> {
+        while let Some(snapshot) = self.pending.pop() {
+            if book.is_synced() && snapshot.last_update_id == book.last_update_id() {
+                self.check(symbol, diff(book, None, &snapshot));
+            } else {
+                warn!("{} - Book stream ended at update {} before the snapshot at update {}", symbol, book.last_update_id(), snapshot.last_update_id);
+            }
+        }
+        self.checks
+    }
+}
@@ -0,0 +1,560 @@
+//! Where a book keeps its price levels. `OrderBookStore` is what the book and the matchers
+//! need of them; `BTreeStore` keeps each side in a `BTreeMap`, and `ArrayStore` keeps the
+//! levels around the mid in a ring of slots indexed by tick, so that the best levels are
+//! found and changed without comparing keys. Which one a replay uses is chosen with
+//! `BookStore`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::fixed_point::Amount;
+use crate::trade_matcher::TradeType;
+
+/// Price levels of both sides of a book, bids being the `TradeType::Bid` side.
+pub trait OrderBookStore {
+    /// Quantity resting at a price level, `None` when there is no level.
+    fn quantity(&self, side: TradeType, price: Amount) -> Option<Amount>;
+
+    /// Final update id of the last event that changed a level; snapshot levels have none.
+    fn update_id(&self, side: TradeType, price: Amount) -> Option<u64>;
+
+    /// Sets a level to a quantity other than zero, with the update id of the event setting it.
+    fn insert(&mut self, side: TradeType, price: Amount, quantity: Amount, update_id: Option<u64>);
+
+    fn remove(&mut self, side: TradeType, price: Amount);
+
+    /// Price of the best level of a side.
+    fn best(&self, side: TradeType) -> Option<Amount>;
+
+    /// Number of levels of a side.
+    fn len(&self, side: TradeType) -> usize;
+
+    /// Levels of a side, best first.
+    fn levels(&self, side: TradeType) -> Box<dyn Iterator<Item = (Amount, Amount)> + '_>;
+
+    /// Removes every level of both sides.
+    fn clear(&mut self);
+
+    /// Keeps only the best `depth` levels of a side.
+    fn truncate(&mut self, side: TradeType, depth: usize) {
+        if self.len(side) <= depth {
+            return;
+        }
+        let worst: Vec<Amount> = self.levels(side).skip(depth).map(|(price, _)| price).collect();
+        for price in worst {
+            self.remove(side, price);
+        }
+    }
+}
+
+/// Which store a replay's books keep their levels in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BookStore {
+    /// A `BTreeMap` per side, for decimal and fixed-point books.
+    #[default]
+    BTree,
+    /// A ring of tick-indexed slots centred on the mid, for fixed-point books only.
+    Array,
+}
+
+impl FromStr for BookStore {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "btree" => Ok(BookStore::BTree),
+            "array" => Ok(BookStore::Array),
+            other => Err(format!("Unknown book store: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for BookStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            BookStore::BTree => "btree",
+            BookStore::Array => "array",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The store chosen for a book at runtime.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Levels {
+    BTree(BTreeStore),
+    Array(ArrayStore),
+}
+
+impl Levels {
+    pub fn new(store: BookStore) -> Self {
+        match store {
+            BookStore::BTree => Levels::BTree(BTreeStore::default()),
+            BookStore::Array => Levels::Array(ArrayStore::default()),
+        }
+    }
+
+    pub fn store(&self) -> BookStore {
+        match self {
+            Levels::BTree(_) => BookStore::BTree,
+            Levels::Array(_) => BookStore::Array,
+        }
+    }
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels::BTree(BTreeStore::default())
+    }
+}
+
+impl OrderBookStore for Levels {
+    fn quantity(&self, side: TradeType, price: Amount) -> Option<Amount> {
+        match self {
+            Levels::BTree(store) => store.quantity(side, price),
+            Levels::Array(store) => store.quantity(side, price),
+        }
+    }
+
+    fn update_id(&self, side: TradeType, price: Amount) -> Option<u64> {
+        match self {
+            Levels::BTree(store) => store.update_id(side, price),
+            Levels::Array(store) => store.update_id(side, price),
+        }
+    }
+
+    fn insert(&mut self, side: TradeType, price: Amount, quantity: Amount, update_id: Option<u64>) {
+        match self {
+            Levels::BTree(store) => store.insert(side, price, quantity, update_id),
+            Levels::Array(store) => store.insert(side, price, quantity, update_id),
+        }
+    }
+
+    fn remove(&mut self, side: TradeType, price: Amount) {
+        match self {
+            Levels::BTree(store) => store.remove(side, price),
+            Levels::Array(store) => store.remove(side, price),
+        }
+    }
+
+    fn best(&self, side: TradeType) -> Option<Amount> {
+        match self {
+            Levels::BTree(store) => store.best(side),
+            Levels::Array(store) => store.best(side),
+        }
+    }
+
+    fn len(&self, side: TradeType) -> usize {
+        match self {
+            Levels::BTree(store) => store.len(side),
+            Levels::Array(store) => store.len(side),
+        }
+    }
+
+    fn levels(&self, side: TradeType) -> Box<dyn Iterator<Item = (Amount, Amount)> + '_> {
+        match self {
+            Levels::BTree(store) => store.levels(side),
+            Levels::Array(store) => store.levels(side),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Levels::BTree(store) => store.clear(),
+            Levels::Array(store) => store.clear(),
+        }
+    }
+
+    fn truncate(&mut self, side: TradeType, depth: usize) {
+        match self {
+            Levels::BTree(store) => store.truncate(side, depth),
+            Levels::Array(store) => store.truncate(side, depth),
+        }
+    }
+}
+
+/// Serialises maps as sequences of pairs, since amounts are not all strings and cannot be
+/// JSON object keys.
+mod pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+/// Levels in a `BTreeMap` per side, with the update ids of the levels events changed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BTreeStore {
+    #[serde(with = "pairs")]
+    bids: BTreeMap<Amount, Amount>,
+    #[serde(with = "pairs")]
+    asks: BTreeMap<Amount, Amount>,
+    #[serde(with = "pairs")]
+    bid_update_ids: HashMap<Amount, u64>,
+    #[serde(with = "pairs")]
+    ask_update_ids: HashMap<Amount, u64>,
+}
+
+impl BTreeStore {
+    fn side(&self, side: TradeType) -> (&BTreeMap<Amount, Amount>, &HashMap<Amount, u64>) {
+        match side {
+            TradeType::Bid => (&self.bids, &self.bid_update_ids),
+            TradeType::Ask => (&self.asks, &self.ask_update_ids),
+        }
+    }
+
+    fn side_mut(&mut self, side: TradeType) -> (&mut BTreeMap<Amount, Amount>, &mut HashMap<Amount, u64>) {
+        match side {
+            TradeType::Bid => (&mut self.bids, &mut self.bid_update_ids),
+            TradeType::Ask => (&mut self.asks, &mut self.ask_update_ids),
+        }
+    }
+}
+
+impl OrderBookStore for BTreeStore {
+    fn quantity(&self, side: TradeType, price: Amount) -> Option<Amount> {
+        self.side(side).0.get(&price).copied()
+    }
+
+    fn update_id(&self, side: TradeType, price: Amount) -> Option<u64> {
+        self.side(side).1.get(&price).copied()
+    }
+
+    fn insert(&mut self, side: TradeType, price: Amount, quantity: Amount, update_id: Option<u64>) {
+        let (levels, update_ids) = self.side_mut(side);
+        levels.insert(price, quantity);
+        match update_id {
+            Some(update_id) => { update_ids.insert(price, update_id); }
+            None => { update_ids.remove(&price); }
+        }
+    }
+
+    fn remove(&mut self, side: TradeType, price: Amount) {
+        let (levels, update_ids) = self.side_mut(side);
+        levels.remove(&price);
+        update_ids.remove(&price);
+    }
+
+    fn best(&self, side: TradeType) -> Option<Amount> {
+        match side {
+            TradeType::Bid => self.bids.keys().next_back().copied(),
+            TradeType::Ask => self.asks.keys().next().copied(),
+        }
+    }
+
+    fn len(&self, side: TradeType) -> usize {
+        self.side(side).0.len()
+    }
+
+    fn levels(&self, side: TradeType) -> Box<dyn Iterator<Item = (Amount, Amount)> + '_> {
+        match side {
+            TradeType::Bid => Box::new(self.bids.iter().rev().map(|(price, quantity)| (*price, *quantity))),
+            TradeType::Ask => Box::new(self.asks.iter().map(|(price, quantity)| (*price, *quantity))),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.bid_update_ids.clear();
+        self.ask_update_ids.clear();
+    }
+
+    fn truncate(&mut self, side: TradeType, depth: usize) {
+        let (levels, update_ids) = self.side_mut(side);
+        while levels.len() > depth {
+            let worst = match side {
+                TradeType::Bid => levels.pop_first(),
+                TradeType::Ask => levels.pop_last(),
+            };
+            if let Some((price, _)) = worst {
+                update_ids.remove(&price);
+            }
+        }
+    }
+}
+
+/// Ticks the ring of an `ArrayStore` spans by default: 40.96 either side of the mid at a
+/// cent tick.
+pub const ARRAY_WIDTH: usize = 8192;
+
+/// Lots resting at a tick and the update id of the event that set them, 0 for a snapshot
+/// level. No lots is no level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Slot(i64, u64);
+
+impl Slot {
+    fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// One side of an `ArrayStore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ArraySide {
+    /// Levels of the ticks in the window, at the tick modulo the width.
+    slots: Vec<Slot>,
+    /// Levels of the ticks outside the window.
+    outside: BTreeMap<i64, Slot>,
+    /// Tick of the best level.
+    best: Option<i64>,
+    len: usize,
+}
+
+impl ArraySide {
+    fn new(width: usize) -> Self {
+        Self { slots: vec![Slot::default(); width], outside: BTreeMap::new(), best: None, len: 0 }
+    }
+}
+
+/// Levels of fixed-point books in a ring of slots indexed by tick, spanning a window of ticks
+/// kept centred on the mid; levels beyond the window, deep in the book, wait in a
+/// `BTreeMap` until the window reaches them. Prices are whole ticks, so the store holds no
+/// decimal amounts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArrayStore {
+    /// First tick of the window.
+    origin: i64,
+    bids: ArraySide,
+    asks: ArraySide,
+}
+
+impl Default for ArrayStore {
+    fn default() -> Self {
+        Self::with_width(ARRAY_WIDTH)
+    }
+}
+
+impl ArrayStore {
+    /// A store whose window spans `width` ticks.
+    pub fn with_width(width: usize) -> Self {
+        assert!(width > 0, "an array store spans at least one tick");
+        Self { origin: 0, bids: ArraySide::new(width), asks: ArraySide::new(width) }
+    }
+
+    fn width(&self) -> i64 {
+        self.bids.slots.len() as i64
+    }
+
+    /// Tick past the window, which `window_around` keeps from overflowing.
+    fn end(&self) -> i64 {
+        self.origin + self.width()
+    }
+
+    fn in_window(&self, tick: i64) -> bool {
+        tick >= self.origin && tick < self.end()
+    }
+
+    /// First tick of a window centred on `tick`.
+    fn window_around(&self, tick: i64) -> i64 {
+        tick.saturating_sub(self.width() / 2).min(i64::MAX - self.width())
+    }
+
+    fn index(&self, tick: i64) -> usize {
+        tick.rem_euclid(self.width()) as usize
+    }
+
+    fn side(&self, side: TradeType) -> &ArraySide {
+        match side {
+            TradeType::Bid => &self.bids,
+            TradeType::Ask => &self.asks,
+        }
+    }
+
+    fn side_mut(&mut self, side: TradeType) -> &mut ArraySide {
+        match side {
+            TradeType::Bid => &mut self.bids,
+            TradeType::Ask => &mut self.asks,
+        }
+    }
+
+    fn slot(&self, side: TradeType, tick: i64) -> Option<Slot> {
+        let slot = if self.in_window(tick) {
+            self.side(side).slots[self.index(tick)]
+        } else {
+            self.side(side).outside.get(&tick).copied()?
+        };
+        (!slot.is_empty()).then_some(slot)
+    }
+
+    /// Best level of a side once the best, at `removed`, is gone: the nearest level past it,
+    /// found by walking the window no further than the nearest level outside it.
+    fn next_best(&self, side: TradeType, removed: i64) -> Option<i64> {
+        let (origin, end) = (self.origin, self.end());
+        let levels = self.side(side);
+        let occupied = |tick: i64| !levels.slots[self.index(tick)].is_empty();
+        match side {
+            TradeType::Bid => {
+                let outside = levels.outside.range(..removed).next_back().map(|(tick, _)| *tick);
+                if outside.is_some_and(|tick| tick >= end) {
+                    return outside;
+                }
+                (origin..removed.min(end)).rev().find(|tick| occupied(*tick)).or(outside)
+            }
+            TradeType::Ask => {
+                let outside = levels.outside.range((Bound::Excluded(removed), Bound::Unbounded)).next().map(|(tick, _)| *tick);
+                if outside.is_some_and(|tick| tick < origin) {
+                    return outside;
+                }
+                (removed.saturating_add(1).max(origin)..end).find(|tick| occupied(*tick)).or(outside)
+            }
+        }
+    }
+
+    /// Moves the window to centre it on the mid once the mid has left its middle half.
+    fn follow_mid(&mut self) {
+        let mid = match (self.bids.best, self.asks.best) {
+            (Some(bid), Some(ask)) => ((bid as i128 + ask as i128) / 2) as i64,
+            (Some(best), None) | (None, Some(best)) => best,
+            (None, None) => return,
+        };
+        let (width, offset) = (self.width() as i128, mid as i128 - self.origin as i128);
+        if offset >= width / 4 && offset < width - width / 4 {
+            return;
+        }
+        self.move_window(self.window_around(mid));
+    }
+
+    /// Moves the window to start at `origin`, moving the levels of the ticks it leaves
+    /// outside and those of the ticks it reaches in.
+    fn move_window(&mut self, origin: i64) {
+        let (previous, width) = (self.origin, self.width());
+        let shift = (origin as i128 - previous as i128).clamp(-(width as i128), width as i128) as i64;
+        let leaving = if shift > 0 {
+            previous..previous + shift
+        } else {
+            previous + width + shift..previous + width
+        };
+        for levels in [&mut self.bids, &mut self.asks] {
+            for tick in leaving.clone() {
+                let slot = std::mem::take(&mut levels.slots[tick.rem_euclid(width) as usize]);
+                if !slot.is_empty() {
+                    levels.outside.insert(tick, slot);
+                }
+            }
+            let reached: Vec<i64> = levels.outside.range(origin..origin + width).map(|(tick, _)| *tick).collect();
+            for tick in reached {
+                if let Some(slot) = levels.outside.remove(&tick) {
+                    levels.slots[tick.rem_euclid(width) as usize] = slot;
+                }
+            }
+        }
+        self.origin = origin;
+    }
+}
+
+impl OrderBookStore for ArrayStore {
+    fn quantity(&self, side: TradeType, price: Amount) -> Option<Amount> {
+        self.slot(side, price.units()).map(|slot| Amount::Fixed(slot.0))
+    }
+
+    fn update_id(&self, side: TradeType, price: Amount) -> Option<u64> {
+        self.slot(side, price.units()).map(|slot| slot.1).filter(|update_id| *update_id != 0)
+    }
+
+    fn insert(&mut self, side: TradeType, price: Amount, quantity: Amount, update_id: Option<u64>) {
+        let (tick, lots) = (price.units(), quantity.units());
+        if lots == 0 {
+            return self.remove(side, price);
+        }
+        if self.bids.len == 0 && self.asks.len == 0 {
+            // Nothing to move, so the window starts centred on the first level
+            self.origin = self.window_around(tick);
+        }
+        let slot = Slot(lots, update_id.unwrap_or_default());
+        let (in_window, index) = (self.in_window(tick), self.index(tick));
+        let levels = self.side_mut(side);
+        let previous = if in_window {
+            std::mem::replace(&mut levels.slots[index], slot)
+        } else {
+            levels.outside.insert(tick, slot).unwrap_or_default()
+        };
+        if previous.is_empty() {
+            levels.len += 1;
+        }
+        let better = match (side, levels.best) {
+            (_, None) => true,
+            (TradeType::Bid, Some(best)) => tick > best,
+            (TradeType::Ask, Some(best)) => tick < best,
+        };
+        if better {
+            levels.best = Some(tick);
+            self.follow_mid();
+        }
+    }
+
+    fn remove(&mut self, side: TradeType, price: Amount) {
+        let tick = price.units();
+        let (in_window, index) = (self.in_window(tick), self.index(tick));
+        let levels = self.side_mut(side);
+        let previous = if in_window {
+            std::mem::take(&mut levels.slots[index])
+        } else {
+            levels.outside.remove(&tick).unwrap_or_default()
+        };
+        if previous.is_empty() {
+            return;
+        }
+        levels.len -= 1;
+        if levels.best == Some(tick) {
+            let best = self.next_best(side, tick);
+            self.side_mut(side).best = best;
+            self.follow_mid();
+        }
+    }
+
+    fn best(&self, side: TradeType) -> Option<Amount> {
+        self.side(side).best.map(Amount::Fixed)
+    }
+
+    fn len(&self, side: TradeType) -> usize {
+        self.side(side).len
+    }
+
+    fn levels(&self, side: TradeType) -> Box<dyn Iterator<Item = (Amount, Amount)> + '_> {
+        let (origin, end) = (self.origin, self.end());
+        let levels = self.side(side);
+        let window = move |tick: i64| {
+            let slot = levels.slots[tick.rem_euclid(end - origin) as usize];
+            (!slot.is_empty()).then_some((tick, slot))
+        };
+        let ticks: Box<dyn Iterator<Item = (i64, Slot)> + '_> = match side {
+            TradeType::Bid => Box::new(levels.outside.range(end..).rev().map(|(tick, slot)| (*tick, *slot))
+                .chain((origin..end).rev().filter_map(window))
+                .chain(levels.outside.range(..origin).rev().map(|(tick, slot)| (*tick, *slot)))),
+            TradeType::Ask => Box::new(levels.outside.range(..origin).map(|(tick, slot)| (*tick, *slot))
+                .chain((origin..end).filter_map(window))
+                .chain(levels.outside.range(end..).map(|(tick, slot)| (*tick, *slot)))),
+        };
+        Box::new(ticks.map(|(tick, slot)| (Amount::Fixed(tick), Amount::Fixed(slot.0))))
+    }
+
+    fn clear(&mut self) {
+        for levels in [&mut self.bids, &mut self.asks] {
+            levels.slots.fill(Slot::default());
+            levels.outside.clear();
+            levels.best = None;
+            levels.len = 0;
+        }
+    }
+}
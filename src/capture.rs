@@ -0,0 +1,153 @@
+//! Native capture of a Binance spot symbol, laid out as `scripts/capture-depth-trades.py` lays
+//! it out: the depth stream, the trade stream split by the side each trade hit, and the REST
+//! snapshot fetched a second after the streams open. Snapshots can also be fetched periodically
+//! into `snapshots/<milliseconds since the epoch>.txt`, which replays of the session check
+//! their book against.
+
+use std::fs::{self, File};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures_util::StreamExt;
+use log::{info, warn};
+use serde_json::Value;
+use tokio::time::{self, Instant};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::book_state::SNAPSHOTS_DIR;
+use crate::error::EngineError;
+
+/// Delay between opening the streams and fetching the first snapshot, so that the snapshot
+/// is covered by the first depth events.
+pub const SNAPSHOT_DELAY: Duration = Duration::from_secs(1);
+/// Levels per side of each snapshot by default, the most the REST endpoint gives.
+pub const SNAPSHOT_LIMIT: u32 = 5000;
+
+/// What to capture and where.
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    pub symbol: String,
+    /// Folder the `<symbol>/<session>/` folder is created under.
+    pub folder: PathBuf,
+    /// Time between two snapshots after the first one, `None` for the first one only.
+    pub snapshot_interval: Option<Duration>,
+    /// Levels per side of each snapshot.
+    pub snapshot_limit: u32,
+}
+
+impl CaptureConfig {
+    pub fn new(symbol: &str, folder: &Path) -> Self {
+        Self { symbol: symbol.to_string(), folder: folder.to_path_buf(), snapshot_interval: None, snapshot_limit: SNAPSHOT_LIMIT }
+    }
+
+    fn stream_url(&self, stream: &str) -> String {
+        format!("wss://stream.binance.com:9443/ws/{}@{}", self.symbol.to_lowercase(), stream)
+    }
+
+    fn snapshot_url(&self) -> String {
+        format!("https://api.binance.com/api/v3/depth?symbol={}&limit={}", self.symbol.to_uppercase(), self.snapshot_limit)
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// A capture file written line by line, so that a stopped capture loses no whole line.
+fn create(path: &Path) -> Result<LineWriter<File>, EngineError> {
+    File::create(path).map(LineWriter::new).map_err(|e| EngineError::io(path, e))
+}
+
+fn write_line(writer: &mut LineWriter<File>, path: &Path, line: &str) -> Result<(), EngineError> {
+    writeln!(writer, "{}", line).map_err(|e| EngineError::io(path, e))
+}
+
+fn network_error(source: &str, e: impl std::fmt::Display) -> EngineError {
+    EngineError::from(io::Error::other(format!("{}: {}", source, e)))
+}
+
+/// Captures until the process is interrupted, or a stream fails or is closed by the
+/// exchange, and gives the session folder.
+pub async fn capture(config: &CaptureConfig) -> Result<PathBuf, EngineError> {
+    let session = config.folder.join(config.symbol.to_lowercase()).join(now().as_micros().to_string());
+    let snapshots = session.join(SNAPSHOTS_DIR);
+    fs::create_dir_all(&snapshots).map_err(|e| EngineError::io(&snapshots, e))?;
+    info!("Capturing {} into {}", config.symbol, session.display());
+    tokio::select! {
+        result = capture_depth(config, &session) => result?,
+        result = capture_trades(config, &session) => result?,
+        result = capture_snapshots(config, &session) => result?,
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("Signal received, stopping...");
+        }
+    }
+    Ok(session)
+}
+
+/// Text messages of a WebSocket stream until the exchange closes it.
+async fn read_stream(url: &str, mut on_message: impl FnMut(&str) -> Result<(), EngineError>) -> Result<(), EngineError> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await.map_err(|e| network_error(url, e))?;
+    while let Some(message) = socket.next().await {
+        match message.map_err(|e| network_error(url, e))? {
+            Message::Text(text) => on_message(&text)?,
+            Message::Close(frame) => warn!("{} closed by the exchange: {:?}", url, frame),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn capture_depth(config: &CaptureConfig, session: &Path) -> Result<(), EngineError> {
+    let path = session.join("depth.txt");
+    let mut depth = create(&path)?;
+    read_stream(&config.stream_url("depth@100ms"), |text| write_line(&mut depth, &path, text)).await
+}
+
+/// Writes trades whose buyer is the market maker, which hit the bids, to `bid_trade.txt`
+/// and the others to `ask_trade.txt`.
+async fn capture_trades(config: &CaptureConfig, session: &Path) -> Result<(), EngineError> {
+    let (ask_path, bid_path) = (session.join("ask_trade.txt"), session.join("bid_trade.txt"));
+    let (mut ask, mut bid) = (create(&ask_path)?, create(&bid_path)?);
+    read_stream(&config.stream_url("trade"), |text| {
+        let trade: Value = serde_json::from_str(text)?;
+        match trade["m"].as_bool() {
+            Some(true) => write_line(&mut bid, &bid_path, text),
+            Some(false) => write_line(&mut ask, &ask_path, text),
+            None => {
+                warn!("Skipping trade message without a market maker flag: {}", text);
+                Ok(())
+            }
+        }
+    }).await
+}
+
+async fn fetch_snapshot(client: &reqwest::Client, url: &str) -> Result<String, reqwest::Error> {
+    client.get(url).send().await?.error_for_status()?.text().await
+}
+
+/// Fetches the session's snapshot, then one every interval into the snapshots folder. A
+/// periodic snapshot that fails to be fetched is skipped, since the capture goes on without it.
+async fn capture_snapshots(config: &CaptureConfig, session: &Path) -> Result<(), EngineError> {
+    let client = reqwest::Client::new();
+    let url = config.snapshot_url();
+    time::sleep(SNAPSHOT_DELAY).await;
+    let snapshot = fetch_snapshot(&client, &url).await.map_err(|e| network_error(&url, e))?;
+    let path = session.join("snapshot.txt");
+    fs::write(&path, snapshot).map_err(|e| EngineError::io(&path, e))?;
+    info!("Snapshot written to {}", path.display());
+    let Some(interval) = config.snapshot_interval else {
+        // Nothing left to fetch: the capture goes on until the streams stop
+        std::future::pending::<()>().await;
+        return Ok(());
+    };
+    let mut ticks = time::interval_at(Instant::now() + interval, interval);
+    loop {
+        ticks.tick().await;
+        let path = session.join(SNAPSHOTS_DIR).join(format!("{}.txt", now().as_millis()));
+        match fetch_snapshot(&client, &url).await {
+            Ok(snapshot) => fs::write(&path, snapshot).map_err(|e| EngineError::io(&path, e))?,
+            Err(e) => warn!("Skipping periodic snapshot - {}", network_error(&url, e)),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Checkpoints of a single-symbol replay, so that a long replay stopped midway resumes from
+//! its last checkpoint rather than from the start. A checkpoint holds everything the engine
+//! carries from one depth event to the next: the book and its update ids, the queues and
+//! results of both matchers, the state of the trade readers and exchange adapters, and the
+//! number of lines of each stream already consumed.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use log::info;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::EngineError;
+
+/// Version of the checkpoint format; checkpoints of other versions are refused.
+const VERSION: u32 = 7;
+
+/// Book stream lines between two checkpoints by default.
+pub const CHECKPOINT_EVERY: u64 = 100_000;
+
+/// Where and how often a replay is checkpointed.
+#[derive(Debug, Clone)]
+pub struct Checkpointing {
+    pub path: PathBuf,
+    /// Book stream lines between two checkpoints.
+    pub every: u64,
+    /// Whether the replay starts from the checkpoint at `path`, when there is one.
+    pub resume: bool,
+}
+
+impl Checkpointing {
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_path_buf(), every: CHECKPOINT_EVERY, resume: false }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<T> {
+    version: u32,
+    /// Replay settings the checkpoint was written with.
+    settings: String,
+    state: T,
+}
+
+/// Writes a checkpoint to a temporary file renamed over `path`, so that a crash while
+/// writing leaves the previous checkpoint whole.
+pub(crate) fn write<T: Serialize>(path: &Path, settings: &str, state: T) -> Result<(), EngineError> {
+    let checkpoint = Checkpoint { version: VERSION, settings: settings.to_string(), state };
+    let temporary = path.with_extension("tmp");
+    let file = File::create(&temporary).map_err(|e| EngineError::io(&temporary, e))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &checkpoint)
+        .map_err(io::Error::from)
+        .and_then(|()| writer.flush())
+        .map_err(|e| EngineError::io(&temporary, e))?;
+    fs::rename(&temporary, path).map_err(|e| EngineError::io(path, e))
+}
+
+/// Reads the checkpoint at `path`, `None` when none was written yet. Checkpoints of another
+/// format version or written with other settings are refused, since resuming from them
+/// would not give the results of an uninterrupted replay.
+pub(crate) fn read<T: DeserializeOwned>(path: &Path, settings: &str) -> Result<Option<T>, EngineError> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            info!("No checkpoint at {} yet - replaying from the start", path.display());
+            return Ok(None);
+        }
+        Err(e) => return Err(EngineError::io(path, e)),
+    };
+    let located = |e: serde_json::Error| EngineError::from(e).at(path.display(), None);
+    let checkpoint: Checkpoint<Value> = serde_json::from_str(&raw).map_err(located)?;
+    if checkpoint.version != VERSION {
+        return Err(EngineError::config(format!("{}: checkpoint format {} is not the supported format {}", path.display(), checkpoint.version, VERSION)));
+    }
+    if checkpoint.settings != settings {
+        return Err(EngineError::config(format!("{}: checkpoint written with {} cannot be resumed with {}", path.display(), checkpoint.settings, settings)));
+    }
+    info!("Resuming from checkpoint {}", path.display());
+    serde_json::from_value(checkpoint.state).map(Some).map_err(located)
+}
@@ -0,0 +1,1188 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::str::FromStr;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use log::{info, debug, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::book_state::{BookDiff, Validator};
+use crate::book_store::BookStore;
+use crate::checkpoint::{self, Checkpointing};
+use crate::exchange::{BookEvent, Exchange, ExchangeAdapter};
+use crate::fixed_point::{FixedPoints, Representation};
+use crate::lag::LagEstimator;
+use crate::malformed::{self, LinePolicy, MalformedLines, RejectFile, Rejects};
+use crate::messages::{SnapShotUpdate, DepthUpdate, TradeIds, TradeUpdate};
+use crate::orderbook::{CrossRepair, CrossedBooks, DepthTime, OrderBook};
+use crate::price_check::{PriceChecker, PriceChecks, TradePrice};
+use crate::trade_matcher::{Matching, MatchScores, MatchedUpdates, TradeType, TradeMatcher, TradeResults, DROPPED, MATCH_WINDOW, PURGED};
+
+/// A stream of JSON messages, one per line.
+pub type LineSource = Box<dyn Iterator<Item = io::Result<String>> + Send>;
+
+/// A stream of lines along with their line number in the input they were read from,
+/// kept when lines are routed to the symbols of a multi-symbol capture.
+type NumberedLines = Box<dyn Iterator<Item = (u64, io::Result<String>)> + Send>;
+
+fn numbered(lines: LineSource) -> NumberedLines {
+    Box::new((1..).zip(lines))
+}
+
+/// The lines after line `line`, e.g. those a resumed replay has not consumed yet.
+fn skip_to(lines: NumberedLines, line: u64) -> NumberedLines {
+    if line == 0 {
+        return lines;
+    }
+    Box::new(lines.skip_while(move |(line_number, _)| *line_number <= line))
+}
+
+/// Next read of a stream, telling the end of the stream from a failed read.
+enum StreamLine {
+    Line(u64, String),
+    /// A line that is not valid UTF-8, left to the line policy like any malformed line.
+    Malformed(u64, EngineError),
+    /// Reading failed; the replay stops since the rest of the stream can no longer be trusted.
+    Failed(u64, io::Error),
+    Eof,
+}
+
+fn next_line(lines: &mut NumberedLines) -> StreamLine {
+    match lines.next() {
+        None => StreamLine::Eof,
+        Some((line_number, Ok(line))) => StreamLine::Line(line_number, line),
+        Some((line_number, Err(e))) if e.kind() == io::ErrorKind::InvalidData => StreamLine::Malformed(line_number, EngineError::parse(e)),
+        Some((line_number, Err(e))) => StreamLine::Failed(line_number, e),
+    }
+}
+
+/// Error of a failed read of a line of `stream`, as named by `malformed::stream_name`.
+fn read_error(stream: &str, line_number: u64, e: io::Error) -> EngineError {
+    EngineError::from(io::Error::new(e.kind(), format!("{} line {}: {}", stream, line_number, e)))
+}
+
+pub use crate::error::EngineError;
+
+pub fn open_lines(path: &Path) -> Result<LineSource, EngineError> {
+    let file = File::open(path).map_err(|e| EngineError::io(path, e))?;
+    Ok(Box::new(io::BufReader::new(file).lines()))
+}
+
+pub fn load_snapshot(exchange: Exchange, path: &Path) -> Result<SnapShotUpdate, EngineError> {
+    let raw = fs::read_to_string(path).map_err(|e| EngineError::io(path, e))?;
+    let snapshot = exchange.adapter().parse_snapshot(&raw).map_err(|e| e.at(path.display(), None))?;
+    debug!("Snapshot loaded: {:?}", snapshot);
+    Ok(snapshot)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct PreviousTradeInfo {
+    prev_event_time: u64,
+    prev_price: Decimal,
+    prev_quantity: Decimal,
+    prev_trade_ids: Vec<String>,
+}
+
+impl PreviousTradeInfo {
+    pub fn new() -> Self {
+        Self {
+            prev_event_time: 0,
+            prev_price: Decimal::new(0, 0),
+            prev_quantity: Decimal::new(0, 0),
+            prev_trade_ids: Vec::new(),
+        }
+    }
+}
+
+/// How raw trades are merged before matching, since one taker order walking into a level
+/// shows up as several trades but as a single depth change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Aggregation {
+    /// Every trade is matched on its own, e.g. for aggTrade files already merged by the exchange.
+    None,
+    /// Consecutive trades with the same event time and price are also matched as a whole,
+    /// alongside the partial sums leading up to it.
+    #[default]
+    TimePrice,
+    /// Consecutive trades of the same aggressor order are matched as a whole, across every
+    /// price it walked through, once its last trade has been read. Feeds without order ids
+    /// are left unaggregated.
+    Aggressor,
+}
+
+impl FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Aggregation::None),
+            "time-price" => Ok(Aggregation::TimePrice),
+            "aggressor" => Ok(Aggregation::Aggressor),
+            other => Err(format!("Unknown aggregation: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Aggregation::None => "none",
+            Aggregation::TimePrice => "time-price",
+            Aggregation::Aggressor => "aggressor",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Settings of a replay shared by every symbol.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub exchange: Exchange,
+    pub aggregation: Aggregation,
+    pub cross_repair: CrossRepair,
+    pub matching: Matching,
+    /// Milliseconds a depth event may lag a trade and still be matched with it.
+    pub window: u64,
+    /// Percentile of the confident match lags each symbol's window adapts to, if any.
+    pub adaptive_window: Option<f64>,
+    /// What to do with lines of any stream that cannot be parsed.
+    pub line_policy: LinePolicy,
+    /// Where quarantined lines are written.
+    pub reject_file: Option<RejectFile>,
+    /// Where and how often a single-symbol replay is checkpointed, if it is.
+    pub checkpointing: Option<Checkpointing>,
+    /// Tick and lot sizes of the symbols whose books and trades are held in fixed point.
+    pub fixed_points: FixedPoints,
+    /// Where books keep their levels.
+    pub book_store: BookStore,
+}
+
+impl ReplayOptions {
+    pub fn new(exchange: Exchange) -> Self {
+        Self { exchange, ..Default::default() }
+    }
+
+    fn rejects(&self) -> Rejects {
+        Rejects::new(self.line_policy, self.reject_file.clone())
+    }
+
+    /// Settings the results of a replay depend on, which a checkpoint must have been written
+    /// with to be resumed.
+    fn settings(&self) -> String {
+        let adaptive_window = self.adaptive_window.map(|p| p.to_string()).unwrap_or_else(|| String::from("-"));
+        format!("exchange={} aggregation={} cross-repair={} matching={} window={} adaptive-window={} line-policy={} fixed-point={} book-store={}",
+            self.exchange, self.aggregation, self.cross_repair, self.matching, self.window, adaptive_window, self.line_policy, self.fixed_points, self.book_store)
+    }
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            exchange: Exchange::default(),
+            aggregation: Aggregation::default(),
+            cross_repair: CrossRepair::default(),
+            matching: Matching::default(),
+            window: MATCH_WINDOW,
+            adaptive_window: None,
+            line_policy: LinePolicy::default(),
+            reject_file: None,
+            checkpointing: None,
+            fixed_points: FixedPoints::default(),
+            book_store: BookStore::default(),
+        }
+    }
+}
+
+/// The input streams of one replay.
+pub struct Streams {
+    pub depth: LineSource,
+    pub trades: TradeStreams,
+}
+
+/// Where trades come from and how the book side each one hit is known.
+pub enum TradeStreams {
+    /// One stream per book side, e.g. as split by the capture script. The side each trade
+    /// hit is checked against its stream and mislabelled trades are reported.
+    BySide { ask: LineSource, bid: LineSource },
+    /// All trades in one stream; the side each trade hit is derived from its market maker flag.
+    Single(LineSource),
+}
+
+/// Book side a trade hit: a market maker buyer means the bids were sold into.
+pub fn hit_side(trade: &TradeUpdate) -> TradeType {
+    if trade.is_market_maker { TradeType::Bid } else { TradeType::Ask }
+}
+
+/// Lines read from a shared source but not yet consumed by one of the two readers.
+struct Tee {
+    lines: NumberedLines,
+    pending: [VecDeque<(u64, io::Result<String>)>; 2],
+}
+
+/// One of the two readers of a `Tee`; each sees every line of the source.
+struct TeeReader {
+    tee: Arc<Mutex<Tee>>,
+    index: usize,
+}
+
+impl Iterator for TeeReader {
+    type Item = (u64, io::Result<String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut tee = self.tee.lock().unwrap();
+        if let Some(line) = tee.pending[self.index].pop_front() {
+            return Some(line);
+        }
+        let (line_number, line) = tee.lines.next()?;
+        let copy = match &line {
+            Ok(line) => Ok(line.clone()),
+            Err(e) => Err(io::Error::new(e.kind(), e.to_string())),
+        };
+        tee.pending[1 - self.index].push_back((line_number, copy));
+        Some((line_number, line))
+    }
+}
+
+/// Duplicates a stream for two readers consuming it at their own pace.
+fn tee(lines: NumberedLines) -> (NumberedLines, NumberedLines) {
+    let tee = Arc::new(Mutex::new(Tee { lines, pending: Default::default() }));
+    (Box::new(TeeReader { tee: tee.clone(), index: 0 }), Box::new(TeeReader { tee, index: 1 }))
+}
+
+/// Feeds one side's trade stream into its `TradeMatcher`.
+pub struct TradeReader {
+    lines: NumberedLines,
+    adapter: Box<dyn ExchangeAdapter>,
+    side: TradeType,
+    /// Whether the stream only holds trades of `side`; otherwise trades of the other side are skipped.
+    labelled: bool,
+    symbol: String,
+    rejects: Rejects,
+    /// Number of the last line read.
+    line: u64,
+    /// Lines that failed to parse; in a stream shared with the other side's reader, only the ask reader counts them.
+    malformed_lines: u64,
+    mislabelled_trades: u64,
+    /// Raw trades read since the last call to `take_prices`.
+    prices: Vec<TradePrice>,
+    /// Lines read ahead of the matcher, for price checks to see every trade before the book
+    /// moves past it.
+    ahead: VecDeque<ReadAhead>,
+    /// Last trade read, of either side.
+    last_read: Option<TradePrice>,
+    aggregation: Aggregation,
+    prev_trade_info: PreviousTradeInfo,
+    /// Aggressor order still receiving trades.
+    pending_order: Option<TradeUpdate>,
+    /// Whether the end of the stream was reached.
+    eof: bool,
+}
+
+/// A line of a trade stream read ahead of the matcher.
+#[derive(Clone, Serialize, Deserialize)]
+enum ReadAhead {
+    /// The trades of the reader's side a line holds, with the line's number.
+    Trades(u64, Vec<TradeUpdate>),
+    /// The end of the stream.
+    Eof,
+}
+
+/// What reading one line of a trade stream gave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TradeRead {
+    /// The line's trades of the reader's side, if any, went to the matcher.
+    Trades,
+    /// The line could not be parsed and was handled by the line policy.
+    Malformed,
+    /// The stream has ended; the last aggressor order went to the matcher.
+    Eof,
+}
+
+impl TradeReader {
+    pub fn new(lines: LineSource, adapter: Box<dyn ExchangeAdapter>, side: TradeType, labelled: bool, aggregation: Aggregation) -> Self {
+        Self::numbered(numbered(lines), adapter, side, labelled, aggregation)
+    }
+
+    fn numbered(lines: NumberedLines, adapter: Box<dyn ExchangeAdapter>, side: TradeType, labelled: bool, aggregation: Aggregation) -> Self {
+        Self {
+            lines,
+            adapter,
+            side,
+            labelled,
+            symbol: String::new(),
+            rejects: Rejects::new(LinePolicy::default(), None),
+            line: 0,
+            malformed_lines: 0,
+            mislabelled_trades: 0,
+            prices: Vec::new(),
+            ahead: VecDeque::new(),
+            last_read: None,
+            aggregation,
+            prev_trade_info: PreviousTradeInfo::new(),
+            pending_order: None,
+            eof: false,
+        }
+    }
+
+    /// Applies a replay's line policy to the malformed lines of this stream.
+    pub fn with_rejects(mut self, symbol: &str, rejects: Rejects) -> Self {
+        self.symbol = symbol.to_string();
+        self.rejects = rejects;
+        self
+    }
+
+    fn stream(&self) -> &'static str {
+        match (self.labelled, self.side) {
+            (false, _) => "trades",
+            (true, TradeType::Ask) => "ask trades",
+            (true, TradeType::Bid) => "bid trades",
+        }
+    }
+
+    /// Reads the next line of trades into the matcher, from the lines read ahead first. Fails
+    /// on an I/O error, or on a malformed line when the line policy aborts.
+    fn next_trades(&mut self, trade_matcher: &mut TradeMatcher) -> Result<TradeRead, EngineError> {
+        if self.ahead.is_empty() {
+            self.read_line()?;
+        }
+        let (line_number, trade_updates) = match self.ahead.pop_front() {
+            Some(ReadAhead::Trades(line_number, trade_updates)) => (line_number, trade_updates),
+            Some(ReadAhead::Eof) => {
+                self.eof = true;
+                // The last aggressor order is complete at the end of the stream
+                if let Some(order) = self.pending_order.take() {
+                    self.add_trade(trade_matcher, order, self.line)?;
+                }
+                return Ok(TradeRead::Eof);
+            }
+            None => return Ok(TradeRead::Malformed),
+        };
+        for trade_update in trade_updates {
+            debug!("{:?} {:?}", trade_matcher.trade_type(), trade_update);
+            let trade_update = match self.aggregation {
+                Aggregation::None => Some(trade_update),
+                Aggregation::TimePrice => Some(self.merge_time_price(trade_update)),
+                Aggregation::Aggressor => self.merge_aggressor(trade_update),
+            };
+            if let Some(trade_update) = trade_update {
+                self.add_trade(trade_matcher, trade_update, line_number)?;
+            }
+        }
+        Ok(TradeRead::Trades)
+    }
+
+    /// Parses the next line of the stream into the lines read ahead, keeping the prices of
+    /// its trades. A malformed line is handled by the line policy and leaves nothing to read.
+    fn read_line(&mut self) -> Result<(), EngineError> {
+        let (line_number, line, parsed) = match next_line(&mut self.lines) {
+            StreamLine::Line(line_number, line) => {
+                self.line = line_number;
+                let parsed = self.adapter.parse_trades(&line);
+                (line_number, line, parsed)
+            }
+            StreamLine::Malformed(line_number, e) => {
+                self.line = line_number;
+                (line_number, String::new(), Err(e))
+            }
+            StreamLine::Failed(line_number, e) => {
+                return Err(read_error(&malformed::stream_name(&self.symbol, self.stream()), line_number, e));
+            }
+            StreamLine::Eof => {
+                self.ahead.push_back(ReadAhead::Eof);
+                return Ok(());
+            }
+        };
+        let trade_updates = match parsed {
+            Ok(trade_updates) => trade_updates,
+            // A stream shared by both sides has its lines rejected by the ask reader only
+            Err(_) if !self.labelled && self.side == TradeType::Bid => return Ok(()),
+            Err(e) => {
+                self.malformed_lines += 1;
+                return self.rejects.reject(&self.symbol, self.stream(), line_number, &line, e);
+            }
+        };
+        let mut trades = Vec::with_capacity(trade_updates.len());
+        for trade_update in trade_updates {
+            let side = hit_side(&trade_update);
+            self.last_read = Some(TradePrice::new(&trade_update, side));
+            if side != self.side {
+                if !self.labelled {
+                    continue;
+                }
+                warn!("{:?} - Trade ID {} hit the {:?} side according to its market maker flag", self.side, trade_update.trade_id, side);
+                self.mislabelled_trades += 1;
+            }
+            self.prices.extend(self.last_read.clone());
+            trades.push(trade_update);
+        }
+        self.ahead.push_back(ReadAhead::Trades(line_number, trades));
+        Ok(())
+    }
+
+    /// Reads lines ahead until a trade after the depth event or the end of the stream, so
+    /// that the prices of the trades that happened by the event are checked before it.
+    fn read_past(&mut self, depth_time: DepthTime) -> Result<(), EngineError> {
+        while !self.eof && !matches!(self.ahead.back(), Some(ReadAhead::Eof))
+            && self.last_read.as_ref().is_none_or(|trade| trade.happened_by(depth_time)) {
+            self.read_line()?;
+        }
+        Ok(())
+    }
+
+    /// Queues a trade, failing at its line when it is off the fixed-point grid.
+    fn add_trade(&self, trade_matcher: &mut TradeMatcher, trade_update: TradeUpdate, line_number: u64) -> Result<(), EngineError> {
+        trade_matcher.add_trade(trade_update)
+            .map_err(|e| e.at(malformed::stream_name(&self.symbol, self.stream()), Some(line_number)))
+    }
+
+    /// Adds a trade to the pending aggressor order, returning the previous order once a trade
+    /// of another one arrives.
+    fn merge_aggressor(&mut self, trade_update: TradeUpdate) -> Option<TradeUpdate> {
+        let aggressor = trade_update.aggressor_order_id();
+        match self.pending_order.take() {
+            Some(mut order) if aggressor != 0 && order.aggressor_order_id() == aggressor => {
+                let mut sweep = if order.sweep.is_empty() {
+                    vec![(order.price, order.quantity)]
+                } else {
+                    std::mem::take(&mut order.sweep)
+                };
+                match sweep.iter_mut().find(|(price, _)| *price == trade_update.price) {
+                    Some((_, quantity)) => *quantity += trade_update.quantity,
+                    None => sweep.push((trade_update.price, trade_update.quantity)),
+                }
+                if sweep.len() > 1 {
+                    order.sweep = sweep;
+                }
+                let mut trade_ids = order.raw_trade_ids();
+                trade_ids.extend(trade_update.raw_trade_ids());
+                order.trade_ids = TradeIds::Merged(trade_ids);
+                order.quantity += trade_update.quantity;
+                order.event_time = trade_update.event_time;
+                order.trade_time = trade_update.trade_time;
+                self.pending_order = Some(order);
+                None
+            }
+            previous => {
+                self.pending_order = Some(trade_update);
+                previous
+            }
+        }
+    }
+
+    /// Merges a trade into the previous one when they share event time and price.
+    fn merge_time_price(&mut self, mut trade_update: TradeUpdate) -> TradeUpdate {
+        let prev_trade_info = &mut self.prev_trade_info;
+        let mut trade_ids = trade_update.raw_trade_ids();
+        // Compare current and previous values
+        if trade_update.event_time == prev_trade_info.prev_event_time && trade_update.price == prev_trade_info.prev_price {
+            trade_update.quantity += prev_trade_info.prev_quantity;
+            trade_ids.splice(0..0, prev_trade_info.prev_trade_ids.drain(..));
+            trade_update.trade_ids = TradeIds::Merged(trade_ids.clone());
+        }
+        // Update the variables with current values for next iteration
+        prev_trade_info.prev_event_time = trade_update.event_time;
+        prev_trade_info.prev_price = trade_update.price;
+        prev_trade_info.prev_quantity = trade_update.quantity;
+        prev_trade_info.prev_trade_ids = trade_ids;
+        trade_update
+    }
+
+    /// Raw trades read since the last call, for price checks.
+    pub fn take_prices(&mut self) -> Vec<TradePrice> {
+        std::mem::take(&mut self.prices)
+    }
+
+    fn state(&self) -> ReaderState<'_> {
+        ReaderState {
+            line: self.line,
+            adapter: self.adapter.state(),
+            malformed_lines: self.malformed_lines,
+            mislabelled_trades: self.mislabelled_trades,
+            prices: Cow::Borrowed(&self.prices),
+            ahead: Cow::Borrowed(&self.ahead),
+            last_read: Cow::Borrowed(&self.last_read),
+            prev_trade_info: Cow::Borrowed(&self.prev_trade_info),
+            pending_order: Cow::Borrowed(&self.pending_order),
+            eof: self.eof,
+        }
+    }
+
+    /// Restores the state saved in a checkpoint; the lines are expected to start after `state.line`.
+    fn restore(&mut self, state: ReaderState) -> Result<(), EngineError> {
+        self.adapter.restore(state.adapter)?;
+        self.line = state.line;
+        self.malformed_lines = state.malformed_lines;
+        self.mislabelled_trades = state.mislabelled_trades;
+        self.prices = state.prices.into_owned();
+        self.ahead = state.ahead.into_owned();
+        self.last_read = state.last_read.into_owned();
+        self.prev_trade_info = state.prev_trade_info.into_owned();
+        self.pending_order = state.pending_order.into_owned();
+        self.eof = state.eof;
+        Ok(())
+    }
+
+    /// Reads trades until the matcher holds at least three distinct timestamps or the
+    /// stream ends, whether its queue was emptied by matches or by drops.
+    fn fill(&mut self, trade_matcher: &mut TradeMatcher) -> Result<(), EngineError> {
+        while !self.eof && trade_matcher.number_of_timestamps() < 3 {
+            if self.next_trades(trade_matcher)? == TradeRead::Eof {
+                debug!("{:?} - No more trades to read", self.side);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the rest of the stream into the matcher, so that the trades no depth event
+    /// was left to match are purged rather than missing from the results.
+    fn drain(&mut self, trade_matcher: &mut TradeMatcher) -> Result<(), EngineError> {
+        while !self.eof {
+            self.next_trades(trade_matcher)?;
+        }
+        Ok(())
+    }
+}
+
+/// What a trade reader carries from one line to the next, saved in checkpoints.
+#[derive(Serialize, Deserialize)]
+struct ReaderState<'a> {
+    line: u64,
+    adapter: Value,
+    malformed_lines: u64,
+    mislabelled_trades: u64,
+    prices: Cow<'a, [TradePrice]>,
+    ahead: Cow<'a, VecDeque<ReadAhead>>,
+    last_read: Cow<'a, Option<TradePrice>>,
+    prev_trade_info: Cow<'a, PreviousTradeInfo>,
+    pending_order: Cow<'a, Option<TradeUpdate>>,
+    eof: bool,
+}
+
+/// What a `SymbolEngine` carries from one depth event to the next, saved in checkpoints.
+/// It borrows the engine when written and owns its parts when read back.
+#[derive(Serialize, Deserialize)]
+struct EngineState<'a> {
+    symbol: Cow<'a, str>,
+    orderbook: Cow<'a, OrderBook>,
+    ask_matcher: Cow<'a, TradeMatcher>,
+    bid_matcher: Cow<'a, TradeMatcher>,
+    ask_trades: ReaderState<'a>,
+    bid_trades: ReaderState<'a>,
+    book_adapter: Value,
+    checksum_mismatches: u64,
+    desynced_updates: u64,
+    price_checker: Cow<'a, PriceChecker>,
+    lag_estimator: Cow<'a, LagEstimator>,
+    book_line: u64,
+    malformed_book_lines: u64,
+    validator: Cow<'a, Validator>,
+}
+
+/// Order book and trade matchers for a single symbol.
+pub struct SymbolEngine {
+    symbol: String,
+    orderbook: OrderBook,
+    ask_matcher: TradeMatcher,
+    bid_matcher: TradeMatcher,
+    ask_trades: TradeReader,
+    bid_trades: TradeReader,
+    book_adapter: Box<dyn ExchangeAdapter>,
+    checksum_mismatches: u64,
+    desynced_updates: u64,
+    price_checker: PriceChecker,
+    lag_estimator: LagEstimator,
+    rejects: Rejects,
+    /// Number of the last line of the book stream.
+    book_line: u64,
+    malformed_book_lines: u64,
+    validator: Validator,
+}
+
+/// Trade streams whose lines carry their line number.
+enum NumberedTrades {
+    BySide { ask: NumberedLines, bid: NumberedLines },
+    Single(NumberedLines),
+}
+
+impl From<TradeStreams> for NumberedTrades {
+    fn from(trades: TradeStreams) -> Self {
+        match trades {
+            TradeStreams::BySide { ask, bid } => NumberedTrades::BySide { ask: numbered(ask), bid: numbered(bid) },
+            TradeStreams::Single(lines) => NumberedTrades::Single(numbered(lines)),
+        }
+    }
+}
+
+impl SymbolEngine {
+    /// Loads the snapshot, if any, and preloads the first trades of each side.
+    /// Without a snapshot the book stays empty until the book stream sends one.
+    /// An empty `symbol` is taken from the first depth event.
+    pub fn new(options: &ReplayOptions, symbol: &str, snapshot: Option<SnapShotUpdate>, trades: TradeStreams) -> Result<Self, EngineError> {
+        Self::numbered(options, symbol, snapshot, trades.into())
+    }
+
+    fn numbered(options: &ReplayOptions, symbol: &str, snapshot: Option<SnapShotUpdate>, trades: NumberedTrades) -> Result<Self, EngineError> {
+        let representation = options.fixed_points.representation(symbol);
+        if options.book_store == BookStore::Array && representation == Representation::Decimal {
+            let symbol = if symbol.is_empty() { "the symbol" } else { symbol };
+            return Err(EngineError::config(format!("The array book store holds fixed-point books only - no tick and lot sizes given for {}", symbol)));
+        }
+        let mut orderbook = OrderBook::with_cross_repair(options.cross_repair)
+            .with_representation(representation)
+            .with_store(options.book_store);
+        match snapshot {
+            Some(snapshot) => orderbook.try_update_with_snapshot(snapshot).map_err(|e| e.at("snapshot", None))?,
+            None => warn!("{} - No snapshot given - waiting for one in the book stream", symbol),
+        }
+        let mut engine = Self::with_book(options, symbol, orderbook, trades, [0, 0]);
+        engine.ask_trades.fill(&mut engine.ask_matcher)?;
+        engine.bid_trades.fill(&mut engine.bid_matcher)?;
+        engine.orderbook.print_orderbook(5, "Before Run");
+        Ok(engine)
+    }
+
+    /// Engine over `orderbook` reading the ask and bid trades after the lines given by `read`,
+    /// before any trade is read.
+    fn with_book(options: &ReplayOptions, symbol: &str, orderbook: OrderBook, trades: NumberedTrades, read: [u64; 2]) -> Self {
+        let exchange = options.exchange;
+        let (ask_trades, bid_trades, labelled) = match trades {
+            NumberedTrades::BySide { ask, bid } => (skip_to(ask, read[0]), skip_to(bid, read[1]), true),
+            NumberedTrades::Single(lines) => {
+                let (ask, bid) = tee(skip_to(lines, read[0].min(read[1])));
+                (skip_to(ask, read[0]), skip_to(bid, read[1]), false)
+            }
+        };
+        let mut ask_matcher = TradeMatcher::with_matching(TradeType::Ask, options.matching);
+        let mut bid_matcher = TradeMatcher::with_matching(TradeType::Bid, options.matching);
+        ask_matcher.set_window(options.window);
+        bid_matcher.set_window(options.window);
+        ask_matcher.set_representation(orderbook.representation());
+        bid_matcher.set_representation(orderbook.representation());
+        Self {
+            symbol: symbol.to_string(),
+            orderbook,
+            ask_matcher,
+            bid_matcher,
+            ask_trades: TradeReader::numbered(ask_trades, exchange.adapter(), TradeType::Ask, labelled, options.aggregation)
+                .with_rejects(symbol, options.rejects()),
+            bid_trades: TradeReader::numbered(bid_trades, exchange.adapter(), TradeType::Bid, labelled, options.aggregation)
+                .with_rejects(symbol, options.rejects()),
+            book_adapter: exchange.adapter(),
+            checksum_mismatches: 0,
+            desynced_updates: 0,
+            price_checker: PriceChecker::new(),
+            lag_estimator: LagEstimator::new(options.window, options.adaptive_window),
+            rejects: options.rejects(),
+            book_line: 0,
+            malformed_book_lines: 0,
+            validator: Validator::default(),
+        }
+    }
+
+    /// Checks the book against snapshots taken during the capture as the replay goes. A
+    /// resumed engine leaves out the snapshots it already checked.
+    pub fn with_validation(mut self, snapshots: Vec<SnapShotUpdate>) -> Self {
+        let resumed = std::mem::take(&mut self.validator);
+        self.validator = Validator::new(snapshots);
+        self.validator.restore(resumed);
+        self
+    }
+
+    /// Engine picking a replay up from the state saved in a checkpoint, reading the trade
+    /// streams from their first line not consumed yet.
+    fn resume(options: &ReplayOptions, state: EngineState, trades: NumberedTrades) -> Result<Self, EngineError> {
+        let read = [state.ask_trades.line, state.bid_trades.line];
+        let mut engine = Self::with_book(options, &state.symbol, state.orderbook.into_owned(), trades, read);
+        engine.ask_matcher = state.ask_matcher.into_owned();
+        engine.bid_matcher = state.bid_matcher.into_owned();
+        engine.ask_trades.restore(state.ask_trades)?;
+        engine.bid_trades.restore(state.bid_trades)?;
+        engine.book_adapter.restore(state.book_adapter)?;
+        engine.checksum_mismatches = state.checksum_mismatches;
+        engine.desynced_updates = state.desynced_updates;
+        engine.price_checker = state.price_checker.into_owned();
+        engine.lag_estimator = state.lag_estimator.into_owned();
+        engine.book_line = state.book_line;
+        engine.malformed_book_lines = state.malformed_book_lines;
+        engine.validator = state.validator.into_owned();
+        Ok(engine)
+    }
+
+    fn state(&self) -> EngineState<'_> {
+        EngineState {
+            symbol: Cow::Borrowed(&self.symbol),
+            orderbook: Cow::Borrowed(&self.orderbook),
+            ask_matcher: Cow::Borrowed(&self.ask_matcher),
+            bid_matcher: Cow::Borrowed(&self.bid_matcher),
+            ask_trades: self.ask_trades.state(),
+            bid_trades: self.bid_trades.state(),
+            book_adapter: self.book_adapter.state(),
+            checksum_mismatches: self.checksum_mismatches,
+            desynced_updates: self.desynced_updates,
+            price_checker: Cow::Borrowed(&self.price_checker),
+            lag_estimator: Cow::Borrowed(&self.lag_estimator),
+            book_line: self.book_line,
+            malformed_book_lines: self.malformed_book_lines,
+            validator: Cow::Borrowed(&self.validator),
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// Parses the next line of the book stream and processes the events it holds.
+    /// A malformed line is handled by the line policy.
+    pub fn process_book_line(&mut self, line: &str) -> Result<(), EngineError> {
+        self.process_numbered_book_line(self.book_line + 1, line)
+    }
+
+    fn process_numbered_book_line(&mut self, line_number: u64, line: &str) -> Result<(), EngineError> {
+        self.book_line = line_number;
+        let events = match self.book_adapter.parse_book(line) {
+            Ok(events) => events,
+            Err(e) => return self.reject_book_line(line_number, line, e),
+        };
+        for event in events {
+            self.process_book_event(event)?;
+        }
+        Ok(())
+    }
+
+    fn reject_book_line(&mut self, line_number: u64, line: &str, error: EngineError) -> Result<(), EngineError> {
+        self.book_line = line_number;
+        self.malformed_book_lines += 1;
+        self.rejects.reject(&self.symbol, "depth", line_number, line, error)
+    }
+
+    pub fn process_book_event(&mut self, event: BookEvent) -> Result<(), EngineError> {
+        match event {
+            BookEvent::Snapshot(snapshot) => {
+                self.orderbook.try_update_with_snapshot(snapshot).map_err(|e| self.book_error(e))?;
+                self.verify_book();
+                Ok(())
+            }
+            BookEvent::Depth(depth_update) => self.process_depth(depth_update),
+        }
+    }
+
+    /// Places an error of the book at the current line of the book stream.
+    fn book_error(&self, error: EngineError) -> EngineError {
+        error.at(malformed::stream_name(&self.symbol, "depth"), Some(self.book_line))
+    }
+
+    /// Validates the book against the exchange's own check; on a mismatch the book is
+    /// dropped until the next snapshot resynchronises it, which a capture may never hold.
+    fn verify_book(&mut self) -> bool {
+        match self.book_adapter.verify_book(&mut self.orderbook) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("{} - {} - waiting for the next snapshot", self.symbol, e);
+                self.checksum_mismatches += 1;
+                self.orderbook.invalidate();
+                false
+            }
+        }
+    }
+
+    /// Tops up the trade queues and checks the prices of the trades that happened by the
+    /// depth event, then applies it and reconciles the sides whose best level moved.
+    pub fn process_depth(&mut self, depth_update: DepthUpdate) -> Result<(), EngineError> {
+        if self.symbol.is_empty() {
+            self.symbol = depth_update.symbol.clone();
+            self.ask_trades.symbol = self.symbol.clone();
+            self.bid_trades.symbol = self.symbol.clone();
+        }
+        self.ask_trades.fill(&mut self.ask_matcher)?;
+        self.bid_trades.fill(&mut self.bid_matcher)?;
+        let depth_time = DepthTime::of(&depth_update);
+        self.ask_trades.read_past(depth_time)?;
+        self.bid_trades.read_past(depth_time)?;
+        self.price_checker.add(self.ask_trades.take_prices());
+        self.price_checker.add(self.bid_trades.take_prices());
+        self.price_checker.check_before(&self.orderbook, &depth_update);
+        self.validator.check_before(&self.symbol, &self.orderbook, &depth_update);
+        debug!("{:?}", depth_update);
+        // Only a book dropped on a checksum mismatch waits for a snapshot past the first one
+        if self.checksum_mismatches > 0 && !self.orderbook.is_synced() {
+            self.desynced_updates += 1;
+        }
+        self.orderbook.try_update(depth_update).map_err(|e| self.book_error(e))?;
+        if !self.verify_book() {
+            return Ok(());
+        }
+        // Scored matching settles trades as the depth clock closes their window
+        if self.ask_matcher.matching() == Matching::Scored {
+            self.ask_matcher.match_scored(&self.orderbook, depth_time);
+            self.bid_matcher.match_scored(&self.orderbook, depth_time);
+        } else {
+            if self.orderbook.is_best_ask_updated() {
+                self.ask_matcher.match_trades(&mut self.orderbook);
+            }
+            if self.orderbook.is_best_bid_updated() {
+                self.bid_matcher.match_trades(&mut self.orderbook);
+            }
+        }
+        self.record_lags();
+        Ok(())
+    }
+
+    /// Feeds the lags of the new confident matches to the lag estimator and hands its
+    /// window to both matchers.
+    fn record_lags(&mut self) {
+        let ask_lags = self.ask_matcher.take_confident_lags();
+        let bid_lags = self.bid_matcher.take_confident_lags();
+        self.lag_estimator.record(ask_lags.into_iter().chain(bid_lags));
+        let window = self.lag_estimator.window();
+        if window != self.ask_matcher.window() {
+            debug!("{} - Matching window adapted to {} ms", self.symbol, window);
+            self.ask_matcher.set_window(window);
+            self.bid_matcher.set_window(window);
+        }
+    }
+
+    /// Reads the trades left in the streams once the book stream has ended, purges every
+    /// pending trade and returns the cleaned matching output.
+    pub fn finish(mut self) -> Result<SymbolReport, EngineError> {
+        self.ask_trades.drain(&mut self.ask_matcher)?;
+        self.bid_trades.drain(&mut self.bid_matcher)?;
+        self.ask_matcher.purge();
+        self.bid_matcher.purge();
+        self.record_lags();
+        self.price_checker.add(self.ask_trades.take_prices());
+        self.price_checker.add(self.bid_trades.take_prices());
+        self.orderbook.print_orderbook(5, "After Run");
+        if self.desynced_updates > 0 && !self.orderbook.is_synced() {
+            warn!("{} - book desynced by a checksum mismatch to the end of the replay, {} depth events skipped", self.symbol, self.desynced_updates);
+        }
+        let snapshot_checks = std::mem::take(&mut self.validator).finish(&self.symbol, &self.orderbook);
+        self.ask_matcher.clean_trade_results();
+        self.bid_matcher.clean_trade_results();
+        Ok(SymbolReport {
+            symbol: self.symbol,
+            ask_results: self.ask_matcher.trade_results().clone(),
+            bid_results: self.bid_matcher.trade_results().clone(),
+            ask_scores: self.ask_matcher.scores().clone(),
+            bid_scores: self.bid_matcher.scores().clone(),
+            updates: self.ask_matcher.updates().iter().chain(self.bid_matcher.updates())
+                .map(|(trade_id, update_id)| (trade_id.clone(), *update_id))
+                .collect(),
+            sequence_gaps: self.orderbook.sequence_gaps(),
+            crossed_books: self.orderbook.crossed_books(),
+            checksum_mismatches: self.checksum_mismatches,
+            desynced_updates: self.desynced_updates,
+            mislabelled_trades: self.ask_trades.mislabelled_trades + self.bid_trades.mislabelled_trades,
+            malformed_lines: MalformedLines {
+                depth: self.malformed_book_lines,
+                trades: self.ask_trades.malformed_lines + self.bid_trades.malformed_lines,
+            },
+            price_checks: self.price_checker.finish(&self.orderbook),
+            confident_lags: self.lag_estimator.lags().to_vec(),
+            window: self.lag_estimator.window(),
+            snapshot_checks,
+        })
+    }
+}
+
+/// Reconciliation output of a capture: a report per symbol, in symbol order, and the
+/// malformed lines that belong to none of them.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub reports: Vec<SymbolReport>,
+    /// Lines without a readable symbol, counted by no symbol's report.
+    pub unroutable: MalformedLines,
+}
+
+impl ReplayReport {
+    pub fn print(&self) {
+        for report in &self.reports {
+            report.print();
+        }
+        if self.unroutable.total() > 0 {
+            warn!("Malformed lines routed to no symbol: {} (depth: {} trades: {})", self.unroutable.total(), self.unroutable.depth, self.unroutable.trades);
+        }
+    }
+}
+
+impl From<SymbolReport> for ReplayReport {
+    fn from(report: SymbolReport) -> Self {
+        Self { reports: vec![report], unroutable: MalformedLines::default() }
+    }
+}
+
+/// Reconciliation output of one symbol.
+#[derive(Debug, Clone)]
+pub struct SymbolReport {
+    pub symbol: String,
+    pub ask_results: TradeResults,
+    pub bid_results: TradeResults,
+    /// Scores of the matched trades, when matching is scored.
+    pub ask_scores: MatchScores,
+    pub bid_scores: MatchScores,
+    /// Depth update ids of the matched trades of both sides.
+    pub updates: MatchedUpdates,
+    pub sequence_gaps: u64,
+    pub crossed_books: CrossedBooks,
+    pub checksum_mismatches: u64,
+    /// Depth events skipped while the book waited for a snapshot after a checksum mismatch;
+    /// without one in the stream, every event to the end of the replay.
+    pub desynced_updates: u64,
+    /// Trades of side-specific streams whose market maker flag points at the other side.
+    pub mislabelled_trades: u64,
+    /// Lines of the symbol's streams left out because they could not be parsed.
+    pub malformed_lines: MalformedLines,
+    pub price_checks: PriceChecks,
+    /// Sorted lags in milliseconds of the confident matches.
+    pub confident_lags: Vec<i64>,
+    /// Matching window in milliseconds at the end of the replay.
+    pub window: u64,
+    /// Comparisons of the book with the snapshots taken during the capture, in update id order.
+    pub snapshot_checks: Vec<BookDiff>,
+}
+
+impl SymbolReport {
+    fn count(&self, keep: impl Fn(u64) -> bool) -> usize {
+        self.ask_results.iter().chain(self.bid_results.iter())
+            .filter(|(_, _, event_time)| keep(*event_time))
+            .count()
+    }
+
+    pub fn matched(&self) -> usize {
+        self.count(|event_time| event_time > PURGED)
+    }
+
+    pub fn dropped(&self) -> usize {
+        self.count(|event_time| event_time == DROPPED)
+    }
+
+    pub fn purged(&self) -> usize {
+        self.count(|event_time| event_time == PURGED)
+    }
+
+    /// Snapshots taken during the capture the book drifted from.
+    pub fn drifted_snapshots(&self) -> usize {
+        self.snapshot_checks.iter().filter(|check| check.drift() > 0).count()
+    }
+
+    /// Sorted lags in milliseconds between each matched trade and its depth event.
+    pub fn lags(&self) -> Vec<i64> {
+        let mut lags: Vec<i64> = self.ask_results.iter().chain(self.bid_results.iter())
+            .filter(|(_, _, event_time)| *event_time > PURGED)
+            .map(|(_, trade_time, event_time)| *event_time as i64 - *trade_time as i64)
+            .collect();
+        lags.sort_unstable();
+        lags
+    }
+
+    pub fn print(&self) {
+        let sides = [(TradeType::Ask, &self.ask_results, &self.ask_scores), (TradeType::Bid, &self.bid_results, &self.bid_scores)];
+        for (trade_type, trade_results, scores) in sides {
+            let mut results = format!("{} {:?} - Matching output\n", self.symbol, trade_type);
+            if scores.is_empty() {
+                results.push_str("\tTrade ID\tTrade Time\tEvent Time\n");
+            } else {
+                results.push_str("\tTrade ID\tTrade Time\tEvent Time\tScore\tRunner-up\n");
+            }
+            for (trade_id, trade_event_time, event_time) in trade_results {
+                results.push_str(&format!("\t{}\t{}\t{}", trade_id, trade_event_time, event_time));
+                if let Some(score) = scores.get(trade_id) {
+                    let runner_up = score.runner_up.map(|cost| format!("{:.3}", cost)).unwrap_or_else(|| String::from("-"));
+                    results.push_str(&format!("\t{:.3}\t{}", score.score, runner_up));
+                }
+                results.push('\n');
+            }
+            // Log the concatenated result
+            info!("{}", results.trim_end()); // trim_end to remove the last newline character
+        }
+        info!("{} - matched: {} dropped: {} purged: {} sequence gaps: {} checksum mismatches: {} desynced updates: {} mislabelled trades: {}", self.symbol, self.matched(), self.dropped(), self.purged(), self.sequence_gaps, self.checksum_mismatches, self.desynced_updates, self.mislabelled_trades);
+        let crossed = &self.crossed_books;
+        info!("{} - crossed books: {} locked books: {} removed levels: {} resyncs: {} unrepaired: {}", self.symbol, crossed.crossed, crossed.locked, crossed.removed_levels, crossed.resyncs, crossed.unrepaired);
+        info!("{} - malformed lines: {} (depth: {} trades: {})", self.symbol, self.malformed_lines.total(), self.malformed_lines.depth, self.malformed_lines.trades);
+        info!("{} - out of book trades: {} unchecked trades: {}", self.symbol, self.price_checks.out_of_book_trades, self.price_checks.unchecked_trades);
+        let lag = |p: f64| percentile(&self.confident_lags, p).map(|lag| lag.to_string()).unwrap_or_else(|| String::from("-"));
+        info!("{} - confident matches: {} lag p50: {} p90: {} p99: {} p99.9: {} window: {} ms", self.symbol, self.confident_lags.len(), lag(50.0), lag(90.0), lag(99.0), lag(99.9), self.window);
+        if !self.snapshot_checks.is_empty() {
+            let mismatched: usize = self.snapshot_checks.iter().map(BookDiff::drift).sum();
+            info!("{} - snapshot checks: {} drifted: {} mismatched levels: {}", self.symbol, self.snapshot_checks.len(), self.drifted_snapshots(), mismatched);
+        }
+    }
+}
+
+/// Nearest-rank percentile `p` (0 to 100) of already sorted values.
+pub fn percentile(sorted: &[i64], p: f64) -> Option<i64> {
+    if sorted.is_empty() { return None; }
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// Replays one symbol's book stream against its snapshot and trade streams.
+pub fn reconcile(options: &ReplayOptions, symbol: &str, snapshot: Option<SnapShotUpdate>, streams: Streams) -> Result<SymbolReport, EngineError> {
+    reconcile_validated(options, symbol, snapshot, Vec::new(), streams)
+}
+
+/// Replays one symbol like `reconcile`, checking the book against the `validation` snapshots
+/// taken during the capture on the way.
+pub fn reconcile_validated(options: &ReplayOptions, symbol: &str, snapshot: Option<SnapShotUpdate>, validation: Vec<SnapShotUpdate>, streams: Streams) -> Result<SymbolReport, EngineError> {
+    reconcile_numbered(options, symbol, snapshot, validation, numbered(streams.depth), streams.trades.into())
+}
+
+/// Without checkpointing, or when resuming without a checkpoint yet, the replay starts from
+/// `snapshot` and the first line of every stream.
+fn reconcile_numbered(options: &ReplayOptions, symbol: &str, snapshot: Option<SnapShotUpdate>, validation: Vec<SnapShotUpdate>, depth: NumberedLines, trades: NumberedTrades) -> Result<SymbolReport, EngineError> {
+    let settings = options.settings();
+    let resumed = match &options.checkpointing {
+        Some(checkpointing) if checkpointing.resume => checkpoint::read::<EngineState>(&checkpointing.path, &settings)?,
+        _ => None,
+    };
+    let (engine, mut depth) = match resumed {
+        Some(state) => {
+            let depth = skip_to(depth, state.book_line);
+            (SymbolEngine::resume(options, state, trades)?, depth)
+        }
+        None => (SymbolEngine::numbered(options, symbol, snapshot, trades)?, depth),
+    };
+    let mut engine = engine.with_validation(validation);
+    loop {
+        match next_line(&mut depth) {
+            StreamLine::Line(line_number, line) => engine.process_numbered_book_line(line_number, &line)?,
+            StreamLine::Malformed(line_number, e) => engine.reject_book_line(line_number, "", e)?,
+            StreamLine::Failed(line_number, e) => return Err(read_error(&malformed::stream_name(engine.symbol(), "depth"), line_number, e)),
+            StreamLine::Eof => break,
+        }
+        if let Some(checkpointing) = &options.checkpointing {
+            if engine.book_line.is_multiple_of(checkpointing.every) {
+                checkpoint::write(&checkpointing.path, &settings, engine.state())?;
+            }
+        }
+    }
+    engine.finish()
+}
+
+/// Lines of one symbol, numbered as in the input streams they were routed from.
+#[derive(Default)]
+struct SymbolStreams {
+    depth: Vec<(u64, String)>,
+    /// Trade lines per input trade stream: ask and bid, or the single one.
+    trades: [Vec<(u64, String)>; 2],
+}
+
+/// A stream over lines already in memory.
+pub fn lines_of(lines: Vec<String>) -> LineSource {
+    Box::new(lines.into_iter().map(Ok))
+}
+
+fn numbered_lines_of(lines: Vec<(u64, String)>) -> NumberedLines {
+    Box::new(lines.into_iter().map(|(line_number, line)| (line_number, Ok(line))))
+}
+
+/// Replays a capture holding several symbols: every message is routed by its symbol
+/// to the symbol's own book and matchers, and the symbols run in parallel.
+/// Snapshot keys are symbols, compared case-insensitively. When snapshots are given only
+/// their symbols are replayed; otherwise every symbol of the book stream is, relying on
+/// the snapshots it carries. Malformed lines without a readable symbol are returned next
+/// to the reports.
+pub fn reconcile_symbols(options: &ReplayOptions, snapshots: HashMap<String, SnapShotUpdate>, streams: Streams) -> Result<ReplayReport, EngineError> {
+    if options.checkpointing.is_some() {
+        return Err(EngineError::config("Checkpoints are only supported for single-symbol replays"));
+    }
+    let route_all = snapshots.is_empty();
+    let mut snapshots: HashMap<String, SnapShotUpdate> = snapshots.into_iter()
+        .map(|(symbol, snapshot)| (symbol.to_uppercase(), snapshot))
+        .collect();
+    let mut symbol_streams: HashMap<String, SymbolStreams> = snapshots.keys()
+        .map(|symbol| (symbol.clone(), SymbolStreams::default()))
+        .collect();
+    let adapter = options.exchange.adapter();
+    // Lines without a readable symbol belong to no symbol's report
+    let rejects = options.rejects();
+    let mut unroutable = MalformedLines::default();
+
+    let mut depth = numbered(streams.depth);
+    loop {
+        let (line_number, line) = match next_line(&mut depth) {
+            StreamLine::Line(line_number, line) => (line_number, line),
+            StreamLine::Malformed(line_number, e) => {
+                unroutable.depth += 1;
+                rejects.reject("", "depth", line_number, "", e)?;
+                continue;
+            }
+            StreamLine::Failed(line_number, e) => return Err(read_error(&malformed::stream_name("", "depth"), line_number, e)),
+            StreamLine::Eof => break,
+        };
+        let symbol = match adapter.symbol_of(&line) {
+            Ok(Some(symbol)) => symbol.to_uppercase(),
+            Ok(None) => continue,
+            Err(e) => {
+                unroutable.depth += 1;
+                rejects.reject("", "depth", line_number, &line, e)?;
+                continue;
+            }
+        };
+        if route_all {
+            symbol_streams.entry(symbol.clone()).or_default();
+        }
+        match symbol_streams.get_mut(&symbol) {
+            Some(routed) => routed.depth.push((line_number, line)),
+            None => warn!("No snapshot for {} - skipping depth event", symbol),
+        }
+    }
+    let (trade_streams, single) = match streams.trades {
+        TradeStreams::BySide { ask, bid } => (vec![("ask trades", ask), ("bid trades", bid)], false),
+        TradeStreams::Single(lines) => (vec![("trades", lines)], true),
+    };
+    for (index, (stream, lines)) in trade_streams.into_iter().enumerate() {
+        let mut lines = numbered(lines);
+        loop {
+            let (line_number, line) = match next_line(&mut lines) {
+                StreamLine::Line(line_number, line) => (line_number, line),
+                StreamLine::Malformed(line_number, e) => {
+                    unroutable.trades += 1;
+                    rejects.reject("", stream, line_number, "", e)?;
+                    continue;
+                }
+                StreamLine::Failed(line_number, e) => return Err(read_error(&malformed::stream_name("", stream), line_number, e)),
+                StreamLine::Eof => break,
+            };
+            let symbol = match adapter.symbol_of(&line) {
+                Ok(Some(symbol)) => symbol.to_uppercase(),
+                Ok(None) => continue,
+                Err(e) => {
+                    unroutable.trades += 1;
+                    rejects.reject("", stream, line_number, &line, e)?;
+                    continue;
+                }
+            };
+            match symbol_streams.get_mut(&symbol) {
+                Some(routed) => routed.trades[index].push((line_number, line)),
+                None => warn!("No book for {} - skipping trade", symbol),
+            }
+        }
+    }
+
+    let mut reports = thread::scope(|scope| {
+        let handles: Vec<_> = symbol_streams.into_iter()
+            .map(|(symbol, routed)| {
+                let snapshot = snapshots.remove(&symbol);
+                let [ask, bid] = routed.trades;
+                let trades = if single {
+                    NumberedTrades::Single(numbered_lines_of(ask))
+                } else {
+                    NumberedTrades::BySide { ask: numbered_lines_of(ask), bid: numbered_lines_of(bid) }
+                };
+                let depth = numbered_lines_of(routed.depth);
+                scope.spawn(move || reconcile_numbered(options, &symbol, snapshot, Vec::new(), depth, trades))
+            })
+            .collect();
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect::<Result<Vec<_>, EngineError>>()
+    })?;
+    reports.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+    Ok(ReplayReport { reports, unroutable })
+}
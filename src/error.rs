@@ -0,0 +1,115 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::path::{Path, PathBuf};
+
+/// Where a parse error happened: a file or stream, and the line when known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub file: String,
+    pub line: Option<u64>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.file, line),
+            None => write!(f, "{}", self.file),
+        }
+    }
+}
+
+/// Errors of the library API.
+#[derive(Debug)]
+pub enum EngineError {
+    /// Reading or writing a file or stream failed, at a line of it when known.
+    Io { path: Option<PathBuf>, line: Option<u64>, source: io::Error },
+    /// A message or file could not be parsed.
+    Parse { location: Option<Location>, message: String },
+    /// Depth events are missing between the last applied update id and the next event.
+    SequenceGap { last_update_id: u64, first_update_id: u64 },
+    /// The reconstructed book no longer matches the feed, e.g. on a checksum mismatch.
+    Desync(String),
+    /// Invalid settings or inputs, e.g. a missing capture file.
+    Config(String),
+}
+
+impl EngineError {
+    pub fn parse(message: impl fmt::Display) -> Self {
+        EngineError::Parse { location: None, message: message.to_string() }
+    }
+
+    pub fn config(message: impl fmt::Display) -> Self {
+        EngineError::Config(message.to_string())
+    }
+
+    pub fn desync(reason: impl fmt::Display) -> Self {
+        EngineError::Desync(reason.to_string())
+    }
+
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        EngineError::Io { path: Some(path.to_path_buf()), line: None, source }
+    }
+
+    /// Places a parse error, or an I/O error without a path, in a file or stream line.
+    /// Errors already located are left as they are.
+    pub fn at(self, file: impl fmt::Display, line: Option<u64>) -> Self {
+        match self {
+            EngineError::Parse { location: None, message } => EngineError::Parse { location: Some(Location { file: file.to_string(), line }), message },
+            EngineError::Io { path: None, source, .. } => EngineError::Io { path: Some(PathBuf::from(file.to_string())), line, source },
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Io { path: Some(path), line: Some(line), source } => write!(f, "{}:{}: {}", path.display(), line, source),
+            EngineError::Io { path: Some(path), line: None, source } => write!(f, "{}: {}", path.display(), source),
+            EngineError::Io { path: None, source, .. } => write!(f, "{}", source),
+            EngineError::Parse { location: Some(location), message } => write!(f, "{}: {}", location, message),
+            EngineError::Parse { location: None, message } => write!(f, "{}", message),
+            EngineError::SequenceGap { last_update_id, first_update_id } => {
+                write!(f, "Sequence gap: update id {} followed by an event starting at {}", last_update_id, first_update_id)
+            }
+            EngineError::Desync(reason) => write!(f, "{}", reason),
+            EngineError::Config(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// The I/O error of `Io` is part of its message rather than its source, so that printing
+/// an error is enough to see why it happened.
+impl Error for EngineError {}
+
+impl From<io::Error> for EngineError {
+    fn from(source: io::Error) -> Self {
+        EngineError::Io { path: None, line: None, source }
+    }
+}
+
+impl From<serde_json::Error> for EngineError {
+    fn from(e: serde_json::Error) -> Self {
+        EngineError::parse(e)
+    }
+}
+
+impl From<rust_decimal::Error> for EngineError {
+    fn from(e: rust_decimal::Error) -> Self {
+        EngineError::parse(e)
+    }
+}
+
+impl From<ParseIntError> for EngineError {
+    fn from(e: ParseIntError) -> Self {
+        EngineError::parse(e)
+    }
+}
+
+impl From<ParseFloatError> for EngineError {
+    fn from(e: ParseFloatError) -> Self {
+        EngineError::parse(e)
+    }
+}
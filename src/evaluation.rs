@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use log::info;
+use serde::Deserialize;
+
+use crate::engine::SymbolReport;
+use crate::error::EngineError;
+
+/// A labelled trade: its upper-case symbol, empty when the label names none, and its trade id.
+pub type LabelKey = (String, String);
+
+/// Ground truth: for each labelled trade, the final update id of the depth event that
+/// should be matched with it, `None` when no depth event shows the trade. The trades of a
+/// single-symbol capture may be labelled without their symbol.
+pub type Labels = BTreeMap<LabelKey, Option<u64>>;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LabelId {
+    Text(String),
+    Number(u64),
+}
+
+/// One line of a label file, e.g. `{"symbol":"BTCUSDT","trade_id":"3323461","update_id":40884727045}`.
+#[derive(Deserialize)]
+struct Label {
+    #[serde(default)]
+    symbol: String,
+    trade_id: LabelId,
+    update_id: Option<u64>,
+}
+
+/// Reads a label file: one JSON object per line giving a symbol (optional for a single-symbol
+/// capture), a trade id (string or number) and the expected depth update id (`u`), or `null`
+/// when the trade should stay unmatched.
+pub fn load_labels(path: &Path) -> Result<Labels, EngineError> {
+    let mut labels = Labels::new();
+    let labels_file = fs::read_to_string(path).map_err(|e| EngineError::io(path, e))?;
+    for (index, line) in labels_file.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let label: Label = serde_json::from_str(line)
+            .map_err(|e| EngineError::from(e).at(path.display(), Some(index as u64 + 1)))?;
+        let trade_id = match label.trade_id {
+            LabelId::Text(trade_id) => trade_id,
+            LabelId::Number(trade_id) => trade_id.to_string(),
+        };
+        labels.insert((label.symbol.to_uppercase(), trade_id), label.update_id);
+    }
+    Ok(labels)
+}
+
+/// Writes labels in the format read by `load_labels`.
+pub fn save_labels(path: &Path, labels: &Labels) -> Result<(), EngineError> {
+    let lines: String = labels.iter()
+        .map(|((symbol, trade_id), update_id)| {
+            let mut label = serde_json::json!({ "trade_id": trade_id, "update_id": update_id });
+            if !symbol.is_empty() {
+                label["symbol"] = symbol.as_str().into();
+            }
+            format!("{}\n", label)
+        })
+        .collect();
+    fs::write(path, lines).map_err(|e| EngineError::io(path, e))?;
+    Ok(())
+}
+
+/// How a labelled trade's match differs from its label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Matched with another depth event than the expected one.
+    WrongEvent,
+    /// Left unmatched although a depth event shows it.
+    Missed,
+    /// Matched although no depth event shows it.
+    Spurious,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::WrongEvent => "wrong event",
+            ErrorKind::Missed => "missed",
+            ErrorKind::Spurious => "spurious",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelError {
+    pub symbol: String,
+    pub trade_id: String,
+    pub kind: ErrorKind,
+    pub expected: Option<u64>,
+    pub predicted: Option<u64>,
+}
+
+/// Matching output scored against ground-truth labels.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Evaluation {
+    /// Labelled trades matched with their expected depth event.
+    pub correct: u64,
+    pub wrong_event: u64,
+    pub missed: u64,
+    pub spurious: u64,
+    /// Labelled trades rightly left unmatched.
+    pub unmatched: u64,
+    /// Trades of the matching output without a label, left out of the scores.
+    pub unlabelled: u64,
+    /// Every wrong event, missed and spurious trade, by symbol and trade id.
+    pub errors: Vec<LabelError>,
+}
+
+impl Evaluation {
+    /// Share of the matches of labelled trades that are correct.
+    pub fn precision(&self) -> Option<f64> {
+        ratio(self.correct, self.correct + self.wrong_event + self.spurious)
+    }
+
+    /// Share of the labelled trades shown by a depth event that are matched with it.
+    pub fn recall(&self) -> Option<f64> {
+        ratio(self.correct, self.correct + self.wrong_event + self.missed)
+    }
+
+    pub fn print(&self) {
+        let mut errors = String::from("Matching errors\n\tSymbol\tTrade ID\tError\tExpected\tPredicted\n");
+        let update = |update_id: Option<u64>| update_id.map(|id| id.to_string()).unwrap_or_else(|| String::from("-"));
+        for error in &self.errors {
+            errors.push_str(&format!("\t{}\t{}\t{}\t{}\t{}\n", error.symbol, error.trade_id, error.kind, update(error.expected), update(error.predicted)));
+        }
+        info!("{}", errors.trim_end());
+        let percent = |value: Option<f64>| value.map(|value| format!("{:.2}%", 100.0 * value)).unwrap_or_else(|| String::from("-"));
+        info!("precision: {} recall: {}", percent(self.precision()), percent(self.recall()));
+        info!("correct: {} wrong event: {} missed: {} spurious: {} rightly unmatched: {} unlabelled: {}", self.correct, self.wrong_event, self.missed, self.spurious, self.unmatched, self.unlabelled);
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> Option<f64> {
+    (denominator > 0).then(|| numerator as f64 / denominator as f64)
+}
+
+/// Compares the matched depth update of every labelled trade with its label, trades being
+/// told apart by symbol and trade id. Labels without a symbol stand for the trades of the
+/// report when there is a single one. Labelled trades missing from the output count as
+/// unmatched.
+pub fn evaluate(labels: &Labels, reports: &[SymbolReport]) -> Evaluation {
+    let unnamed = match reports {
+        [report] => report.symbol.to_uppercase(),
+        _ => String::new(),
+    };
+    let labels: BTreeMap<(String, &str), Option<u64>> = labels.iter()
+        .map(|((symbol, trade_id), update_id)| {
+            let symbol = if symbol.is_empty() { unnamed.clone() } else { symbol.clone() };
+            ((symbol, trade_id.as_str()), *update_id)
+        })
+        .collect();
+    let mut predictions = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    for report in reports {
+        let symbol = report.symbol.to_uppercase();
+        for (trade_id, _, _) in report.ask_results.iter().chain(report.bid_results.iter()) {
+            seen.insert((symbol.clone(), trade_id.as_str()));
+        }
+        predictions.extend(report.updates.iter().map(|(trade_id, update_id)| ((symbol.clone(), trade_id.as_str()), *update_id)));
+    }
+    let mut evaluation = Evaluation {
+        unlabelled: seen.iter().filter(|trade| !labels.contains_key(*trade)).count() as u64,
+        ..Default::default()
+    };
+    for (trade, expected) in &labels {
+        let expected = *expected;
+        let predicted = predictions.get(trade).copied();
+        let kind = match (expected, predicted) {
+            (Some(expected), Some(predicted)) if expected == predicted => {
+                evaluation.correct += 1;
+                continue;
+            }
+            (None, None) => {
+                evaluation.unmatched += 1;
+                continue;
+            }
+            (Some(_), Some(_)) => {
+                evaluation.wrong_event += 1;
+                ErrorKind::WrongEvent
+            }
+            (Some(_), None) => {
+                evaluation.missed += 1;
+                ErrorKind::Missed
+            }
+            (None, Some(_)) => {
+                evaluation.spurious += 1;
+                ErrorKind::Spurious
+            }
+        };
+        let (symbol, trade_id) = trade;
+        evaluation.errors.push(LabelError { symbol: symbol.clone(), trade_id: trade_id.to_string(), kind, expected, predicted });
+    }
+    evaluation
+}
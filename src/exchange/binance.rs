@@ -0,0 +1,58 @@
+use serde::Deserialize;
+
+use crate::error::EngineError;
+use crate::exchange::{BookEvent, ExchangeAdapter};
+use crate::messages::{AggTradeUpdate, DepthUpdate, SnapShotUpdate, TradeUpdate};
+
+/// Raw trades one aggTrade may stand for; a wider id range is taken for a corrupt message
+/// rather than expanded into its ids.
+const MAX_AGG_TRADE_IDS: u64 = 100_000;
+
+/// Only the routing key of a depth or trade message.
+#[derive(Deserialize)]
+struct SymbolKey {
+    #[serde(rename = "s")]
+    symbol: String,
+}
+
+/// Only the event type of a stream message.
+#[derive(Deserialize)]
+struct EventKey {
+    #[serde(rename = "e")]
+    event_type: String,
+}
+
+/// Binance spot and futures (USDⓈ-M / COIN-M) depth streams, `@trade` and `@aggTrade`
+/// streams and REST depth snapshots, which the engine's message types mirror.
+/// Futures depth events chain through `pu` and carry the transaction time `T`.
+pub struct BinanceAdapter;
+
+impl ExchangeAdapter for BinanceAdapter {
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError> {
+        let key: SymbolKey = serde_json::from_str(line)?;
+        Ok(Some(key.symbol))
+    }
+
+    fn parse_snapshot(&mut self, raw: &str) -> Result<SnapShotUpdate, EngineError> {
+        Ok(serde_json::from_str(raw)?)
+    }
+
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError> {
+        let depth_update: DepthUpdate = serde_json::from_str(line)?;
+        Ok(vec![BookEvent::Depth(depth_update)])
+    }
+
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError> {
+        let key: EventKey = serde_json::from_str(line)?;
+        if key.event_type == "aggTrade" {
+            let agg_trade: AggTradeUpdate = serde_json::from_str(line)?;
+            let (first, last) = (agg_trade.first_trade_id, agg_trade.last_trade_id);
+            if last < first || last - first >= MAX_AGG_TRADE_IDS {
+                return Err(EngineError::parse(format!("Invalid aggTrade trade id range: {}..={}", first, last)));
+            }
+            return Ok(vec![agg_trade.into()]);
+        }
+        let trade_update: TradeUpdate = serde_json::from_str(line)?;
+        Ok(vec![trade_update])
+    }
+}
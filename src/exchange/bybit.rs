@@ -0,0 +1,149 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::EngineError;
+use crate::exchange::{BookEvent, ExchangeAdapter};
+use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
+use crate::orderbook::OrderBook;
+
+/// Push message envelope; operation replies carry no topic.
+#[derive(Deserialize)]
+struct BybitMessage<T> {
+    topic: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    ts: Option<u64>,
+    /// Matching engine time of book messages.
+    cts: Option<u64>,
+    data: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct BybitBook {
+    s: String,
+    b: Vec<(Decimal, Decimal)>,
+    a: Vec<(Decimal, Decimal)>,
+    /// Update id, contiguous within a book and restarting with a snapshot.
+    u: u64,
+}
+
+#[derive(Deserialize)]
+struct BybitTrade {
+    #[serde(rename = "T")]
+    trade_time: u64,
+    s: String,
+    /// Side of the taker.
+    #[serde(rename = "S")]
+    side: String,
+    v: Decimal,
+    p: Decimal,
+    i: String,
+}
+
+/// Bybit v5 `orderbook.{depth}.{symbol}` and `publicTrade.{symbol}` topics.
+///
+/// Deltas chain through the update id `u`, mapped onto both update ids of the event; a
+/// `snapshot` message (including the `u = 1` one sent after a service restart) reloads the
+/// book. Matching engine times `cts` are used as transaction times, compared with trade times `T`.
+/// Trade sides are taker sides: `Sell` hit the bids.
+#[derive(Serialize, Deserialize)]
+pub struct BybitAdapter {
+    depth: usize,
+}
+
+impl BybitAdapter {
+    pub fn new() -> Self {
+        Self { depth: 0 }
+    }
+}
+
+impl Default for BybitAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExchangeAdapter for BybitAdapter {
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError> {
+        let message: BybitMessage<serde::de::IgnoredAny> = serde_json::from_str(line)?;
+        Ok(message.topic.and_then(|topic| topic.rsplit('.').next().map(String::from)))
+    }
+
+    fn parse_snapshot(&mut self, _raw: &str) -> Result<SnapShotUpdate, EngineError> {
+        Err(EngineError::config("Bybit orderbook sends its snapshot in the stream - no snapshot file is needed"))
+    }
+
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError> {
+        let message: BybitMessage<BybitBook> = serde_json::from_str(line)?;
+        let (Some(topic), Some(book)) = (message.topic, message.data) else { return Ok(Vec::new()) };
+        let mut topic_parts = topic.split('.');
+        if topic_parts.next() != Some("orderbook") {
+            return Ok(Vec::new());
+        }
+        self.depth = topic_parts.next().unwrap_or_default().parse()?;
+        match message.kind.as_deref() {
+            Some("snapshot") => Ok(vec![BookEvent::Snapshot(SnapShotUpdate {
+                last_update_id: book.u,
+                bids: book.b,
+                asks: book.a,
+            })]),
+            Some("delta") => Ok(vec![BookEvent::Depth(DepthUpdate {
+                event_type: String::from("delta"),
+                event_time: message.ts.unwrap_or_default(),
+                symbol: book.s,
+                first_update_id_in_event: book.u,
+                final_update_id_in_event: book.u,
+                transaction_time: message.cts,
+                bids_to_update: book.b,
+                asks_to_update: book.a,
+                ..Default::default()
+            })]),
+            other => Err(EngineError::parse(format!("Unknown Bybit orderbook message type: {:?}", other))),
+        }
+    }
+
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError> {
+        let message: BybitMessage<Vec<BybitTrade>> = serde_json::from_str(line)?;
+        let (Some(topic), Some(trades)) = (message.topic, message.data) else { return Ok(Vec::new()) };
+        if !topic.starts_with("publicTrade.") {
+            return Ok(Vec::new());
+        }
+        trades.into_iter()
+            .map(|trade| {
+                let is_market_maker = match trade.side.as_str() {
+                    "Sell" => true,
+                    "Buy" => false,
+                    other => return Err(EngineError::parse(format!("Unknown Bybit trade side: {}", other))),
+                };
+                Ok(TradeUpdate {
+                    event_type: String::from("publicTrade"),
+                    event_time: message.ts.unwrap_or(trade.trade_time),
+                    symbol: trade.s,
+                    trade_id: trade.i,
+                    price: trade.p,
+                    quantity: trade.v,
+                    trade_time: trade.trade_time,
+                    is_market_maker,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn verify_book(&mut self, book: &mut OrderBook) -> Result<(), EngineError> {
+        if book.is_synced() && self.depth > 0 {
+            book.truncate(self.depth);
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: Value) -> Result<(), EngineError> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
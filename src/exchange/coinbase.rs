@@ -0,0 +1,169 @@
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::EngineError;
+use crate::exchange::{parse_rfc3339_millis, BookEvent, ExchangeAdapter};
+use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
+
+#[derive(Deserialize)]
+struct ProductKey {
+    product_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseMatch {
+    trade_id: u64,
+    sequence: u64,
+    maker_order_id: String,
+    taker_order_id: String,
+    time: String,
+    product_id: String,
+    size: Decimal,
+    price: Decimal,
+    /// Side of the maker order.
+    side: String,
+}
+
+/// Messages of the Coinbase Exchange `level2` and `matches` channels.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CoinbaseMessage {
+    Snapshot {
+        bids: Vec<(Decimal, Decimal)>,
+        asks: Vec<(Decimal, Decimal)>,
+    },
+    L2update {
+        product_id: String,
+        time: String,
+        /// Each change is (side, price, new size), with side `buy` or `sell`.
+        changes: Vec<(String, Decimal, Decimal)>,
+    },
+    Match(CoinbaseMatch),
+    LastMatch(CoinbaseMatch),
+    #[serde(other)]
+    Other,
+}
+
+/// Stable 64-bit id for a Coinbase order UUID (FNV-1a).
+fn order_id(uuid: &str) -> u64 {
+    uuid.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Coinbase Exchange feed: `level2` for the book and `matches` for trades.
+///
+/// `level2` carries no sequence numbers and opens with its own snapshot, so update ids are
+/// assigned in stream order. Matches are checked against their `sequence`, dropping replays.
+/// A match's `side` is the maker's side: a `buy` maker means the taker sold into the bids.
+#[derive(Serialize, Deserialize)]
+pub struct CoinbaseAdapter {
+    update_id: u64,
+    last_sequence: Option<u64>,
+}
+
+impl CoinbaseAdapter {
+    pub fn new() -> Self {
+        Self {
+            update_id: 0,
+            last_sequence: None,
+        }
+    }
+}
+
+impl Default for CoinbaseAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExchangeAdapter for CoinbaseAdapter {
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError> {
+        let key: ProductKey = serde_json::from_str(line)?;
+        Ok(key.product_id)
+    }
+
+    fn parse_snapshot(&mut self, _raw: &str) -> Result<SnapShotUpdate, EngineError> {
+        Err(EngineError::config("Coinbase level2 sends its snapshot in the stream - no snapshot file is needed"))
+    }
+
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError> {
+        match serde_json::from_str(line)? {
+            CoinbaseMessage::Snapshot { bids, asks } => {
+                self.update_id += 1;
+                Ok(vec![BookEvent::Snapshot(SnapShotUpdate {
+                    last_update_id: self.update_id,
+                    bids,
+                    asks,
+                })])
+            }
+            CoinbaseMessage::L2update { product_id, time, changes } => {
+                self.update_id += 1;
+                let mut depth_update = DepthUpdate {
+                    event_type: String::from("l2update"),
+                    event_time: parse_rfc3339_millis(&time)?,
+                    symbol: product_id,
+                    first_update_id_in_event: self.update_id,
+                    final_update_id_in_event: self.update_id,
+                    ..Default::default()
+                };
+                for (side, price, size) in changes {
+                    match side.as_str() {
+                        "buy" => depth_update.bids_to_update.push((price, size)),
+                        "sell" => depth_update.asks_to_update.push((price, size)),
+                        other => return Err(EngineError::parse(format!("Unknown l2update side: {}", other))),
+                    }
+                }
+                Ok(vec![BookEvent::Depth(depth_update)])
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError> {
+        let trade = match serde_json::from_str(line)? {
+            CoinbaseMessage::Match(trade) | CoinbaseMessage::LastMatch(trade) => trade,
+            _ => return Ok(Vec::new()),
+        };
+        if let Some(last_sequence) = self.last_sequence {
+            if trade.sequence <= last_sequence {
+                warn!("Coinbase match {} replayed - sequence {} <= {}", trade.trade_id, trade.sequence, last_sequence);
+                return Ok(Vec::new());
+            }
+        }
+        self.last_sequence = Some(trade.sequence);
+        let time = parse_rfc3339_millis(&trade.time)?;
+        let is_market_maker = match trade.side.as_str() {
+            "buy" => true,
+            "sell" => false,
+            other => return Err(EngineError::parse(format!("Unknown match side: {}", other))),
+        };
+        let (buyer_order_id, seller_order_id) = if is_market_maker {
+            (order_id(&trade.maker_order_id), order_id(&trade.taker_order_id))
+        } else {
+            (order_id(&trade.taker_order_id), order_id(&trade.maker_order_id))
+        };
+        Ok(vec![TradeUpdate {
+            event_type: String::from("match"),
+            event_time: time,
+            symbol: trade.product_id,
+            trade_id: trade.trade_id.to_string(),
+            price: trade.price,
+            quantity: trade.size,
+            buyer_order_id,
+            seller_order_id,
+            trade_time: time,
+            is_market_maker,
+            ..Default::default()
+        }])
+    }
+
+    fn state(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: Value) -> Result<(), EngineError> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
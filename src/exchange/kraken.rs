@@ -0,0 +1,213 @@
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::EngineError;
+use crate::exchange::{BookEvent, ExchangeAdapter};
+use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
+use crate::orderbook::OrderBook;
+
+/// Number of levels per side covered by the book checksum.
+const CHECKSUM_LEVELS: usize = 10;
+
+fn as_str<'a>(value: &'a Value, what: &str) -> Result<&'a str, EngineError> {
+    value.as_str().ok_or_else(|| EngineError::parse(format!("Kraken {} is not a string: {}", what, value)))
+}
+
+/// Converts Kraken's `seconds.micros` timestamps to milliseconds.
+fn seconds_to_millis(seconds: &str) -> Result<u64, EngineError> {
+    let seconds: Decimal = seconds.parse()?;
+    seconds.checked_mul(Decimal::new(1000, 0)).and_then(|millis| millis.trunc().to_u64())
+        .ok_or_else(|| EngineError::parse(format!("Invalid Kraken timestamp: {}", seconds)))
+}
+
+/// Parses `[price, volume, timestamp, (flag)]` levels, returning them with the latest timestamp.
+fn parse_levels(levels: &Value) -> Result<(Vec<(Decimal, Decimal)>, u64), EngineError> {
+    let levels = levels.as_array().ok_or_else(|| EngineError::parse("Kraken book levels are not an array"))?;
+    let mut parsed = Vec::with_capacity(levels.len());
+    let mut latest = 0;
+    for level in levels {
+        let price = as_str(&level[0], "price")?.parse::<Decimal>()?;
+        let volume = as_str(&level[1], "volume")?.parse::<Decimal>()?;
+        latest = latest.max(seconds_to_millis(as_str(&level[2], "timestamp")?)?);
+        parsed.push((price, volume));
+    }
+    Ok((parsed, latest))
+}
+
+/// A checksum field: the decimal string without its point and leading zeros.
+fn checksum_field(value: &Decimal) -> String {
+    value.to_string().replace('.', "").trim_start_matches('0').to_string()
+}
+
+/// CRC32 of the top ten asks then the top ten bids, as Kraken computes it.
+/// Prices and volumes keep the scale they were received with.
+pub fn book_checksum(book: &OrderBook) -> u32 {
+    let mut payload = String::new();
+    for (price, volume) in book.top_asks(CHECKSUM_LEVELS).chain(book.top_bids(CHECKSUM_LEVELS)) {
+        payload.push_str(&checksum_field(&price));
+        payload.push_str(&checksum_field(&volume));
+    }
+    crc32fast::hash(payload.as_bytes())
+}
+
+/// Kraken websocket (v1) `book-N` and `trade` channels.
+///
+/// Messages are arrays `[channelID, payload..., channelName, pair]`; book updates carry no
+/// sequence numbers, so update ids are assigned in stream order. Every update holding a
+/// checksum `c` is validated against the reconstructed book, truncated to the subscribed
+/// depth as Kraken expects. Trade sides are taker sides: `s` sold into the bids.
+/// Trades carry no id, so theirs is built from their own fields: time, price, volume, side
+/// and index in the message, the same whichever reader parses them.
+#[derive(Serialize, Deserialize)]
+pub struct KrakenAdapter {
+    update_id: u64,
+    depth: usize,
+    pending_checksum: Option<u32>,
+}
+
+impl KrakenAdapter {
+    pub fn new() -> Self {
+        Self {
+            update_id: 0,
+            depth: 0,
+            pending_checksum: None,
+        }
+    }
+}
+
+impl Default for KrakenAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A channel message `[channelID, payload..., channelName, pair]`.
+struct ChannelMessage<'a> {
+    channel_name: &'a str,
+    pair: &'a str,
+    payloads: &'a [Value],
+}
+
+/// Splits a channel message; event objects such as heartbeats give `None`.
+fn split_message(value: &Value) -> Result<Option<ChannelMessage<'_>>, EngineError> {
+    let Some(items) = value.as_array() else { return Ok(None) };
+    if items.len() < 4 {
+        return Err(EngineError::parse(format!("Kraken message too short: {}", value)));
+    }
+    Ok(Some(ChannelMessage {
+        channel_name: as_str(&items[items.len() - 2], "channel name")?,
+        pair: as_str(&items[items.len() - 1], "pair")?,
+        payloads: &items[1..items.len() - 2],
+    }))
+}
+
+impl ExchangeAdapter for KrakenAdapter {
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError> {
+        let value: Value = serde_json::from_str(line)?;
+        Ok(split_message(&value)?.map(|message| message.pair.to_string()))
+    }
+
+    fn parse_snapshot(&mut self, _raw: &str) -> Result<SnapShotUpdate, EngineError> {
+        Err(EngineError::config("Kraken book sends its snapshot in the stream - no snapshot file is needed"))
+    }
+
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError> {
+        let value: Value = serde_json::from_str(line)?;
+        let Some(ChannelMessage { channel_name, pair, payloads }) = split_message(&value)? else { return Ok(Vec::new()) };
+        let Some(depth) = channel_name.strip_prefix("book-") else { return Ok(Vec::new()) };
+        self.depth = depth.parse()?;
+        self.update_id += 1;
+        self.pending_checksum = None;
+
+        let mut snapshot = SnapShotUpdate { last_update_id: self.update_id, ..Default::default() };
+        let mut depth_update = DepthUpdate {
+            event_type: String::from("book"),
+            symbol: pair.to_string(),
+            first_update_id_in_event: self.update_id,
+            final_update_id_in_event: self.update_id,
+            ..Default::default()
+        };
+        let mut is_snapshot = false;
+        for payload in payloads {
+            let payload = payload.as_object().ok_or_else(|| EngineError::parse("Kraken book payload is not an object"))?;
+            for (key, levels) in payload {
+                match key.as_str() {
+                    "as" | "bs" => {
+                        is_snapshot = true;
+                        let (levels, _) = parse_levels(levels)?;
+                        if key == "as" { snapshot.asks = levels } else { snapshot.bids = levels }
+                    }
+                    "a" | "b" => {
+                        let (levels, latest) = parse_levels(levels)?;
+                        depth_update.event_time = depth_update.event_time.max(latest);
+                        if key == "a" { depth_update.asks_to_update.extend(levels) } else { depth_update.bids_to_update.extend(levels) }
+                    }
+                    "c" => self.pending_checksum = Some(as_str(levels, "checksum")?.parse()?),
+                    other => return Err(EngineError::parse(format!("Unknown Kraken book field: {}", other))),
+                }
+            }
+        }
+        if is_snapshot {
+            return Ok(vec![BookEvent::Snapshot(snapshot)]);
+        }
+        Ok(vec![BookEvent::Depth(depth_update)])
+    }
+
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError> {
+        let value: Value = serde_json::from_str(line)?;
+        let Some(ChannelMessage { channel_name, pair, payloads }) = split_message(&value)? else { return Ok(Vec::new()) };
+        if channel_name != "trade" {
+            return Ok(Vec::new());
+        }
+        let mut trades = Vec::new();
+        for (index, trade) in payloads.iter().filter_map(Value::as_array).flatten().enumerate() {
+            let (price, volume) = (as_str(&trade[0], "trade price")?, as_str(&trade[1], "trade volume")?);
+            let (seconds, side) = (as_str(&trade[2], "trade time")?, as_str(&trade[3], "trade side")?);
+            let time = seconds_to_millis(seconds)?;
+            let is_market_maker = match side {
+                "s" => true,
+                "b" => false,
+                other => return Err(EngineError::parse(format!("Unknown Kraken trade side: {}", other))),
+            };
+            trades.push(TradeUpdate {
+                event_type: String::from("trade"),
+                event_time: time,
+                symbol: pair.to_string(),
+                trade_id: format!("{}-{}-{}-{}-{}", seconds, price, volume, side, index),
+                price: price.parse()?,
+                quantity: volume.parse()?,
+                trade_time: time,
+                is_market_maker,
+                ..Default::default()
+            });
+        }
+        Ok(trades)
+    }
+
+    fn verify_book(&mut self, book: &mut OrderBook) -> Result<(), EngineError> {
+        if !book.is_synced() {
+            return Ok(());
+        }
+        if self.depth > 0 {
+            book.truncate(self.depth);
+        }
+        if let Some(expected) = self.pending_checksum.take() {
+            let computed = book_checksum(book);
+            if computed != expected {
+                return Err(EngineError::desync(format!("Kraken checksum mismatch at update {} - expected {} computed {}", self.update_id, expected, computed)));
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: Value) -> Result<(), EngineError> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
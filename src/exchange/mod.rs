@@ -0,0 +1,209 @@
+use std::fmt;
+use std::str::FromStr;
+use serde_json::Value;
+
+use crate::error::EngineError;
+use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
+use crate::orderbook::OrderBook;
+
+pub mod binance;
+pub mod bybit;
+pub mod coinbase;
+pub mod kraken;
+pub mod okx;
+
+/// Order book event normalised into the engine's representation.
+#[derive(Debug)]
+pub enum BookEvent {
+    /// Full book replacing the current one, e.g. a snapshot sent on subscription.
+    Snapshot(SnapShotUpdate),
+    Depth(DepthUpdate),
+}
+
+/// Normalises one exchange's wire format into the engine's depth, trade and snapshot events.
+/// Each input stream gets its own adapter, so implementations may keep per-stream state
+/// such as sequence numbers.
+pub trait ExchangeAdapter: Send {
+    /// Symbol a book or trade line belongs to, used to route multi-symbol captures.
+    /// Control messages tied to no symbol give `None`.
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError>;
+
+    /// Parses a snapshot file.
+    fn parse_snapshot(&mut self, raw: &str) -> Result<SnapShotUpdate, EngineError>;
+
+    /// Parses one line of the book stream; lines carrying no book change yield no event.
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError>;
+
+    /// Parses one line of the trade stream; lines carrying no trade yield no trade.
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError>;
+
+    /// Conforms the book reconstructed from the last parsed line to the feed, e.g. its depth,
+    /// and checks it against what the exchange sent with it, e.g. a checksum.
+    /// Feeds without such a check accept every book.
+    fn verify_book(&mut self, _book: &mut OrderBook) -> Result<(), EngineError> {
+        Ok(())
+    }
+
+    /// State carried from one line to the next, saved in replay checkpoints.
+    /// Stateless adapters have none.
+    fn state(&self) -> Value {
+        Value::Null
+    }
+
+    /// Restores the state saved by `state`.
+    fn restore(&mut self, _state: Value) -> Result<(), EngineError> {
+        Ok(())
+    }
+}
+
+/// Exchanges whose feeds the engine can replay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Exchange {
+    #[default]
+    Binance,
+    Coinbase,
+    Kraken,
+    Okx,
+    Bybit,
+}
+
+impl Exchange {
+    /// An adapter for the exchange's feeds, refusing negative prices and quantities.
+    pub fn adapter(&self) -> Box<dyn ExchangeAdapter> {
+        let adapter: Box<dyn ExchangeAdapter> = match self {
+            Exchange::Binance => Box::new(binance::BinanceAdapter),
+            Exchange::Coinbase => Box::new(coinbase::CoinbaseAdapter::new()),
+            Exchange::Kraken => Box::new(kraken::KrakenAdapter::new()),
+            Exchange::Okx => Box::new(okx::OkxAdapter::new()),
+            Exchange::Bybit => Box::new(bybit::BybitAdapter::new()),
+        };
+        Box::new(NonNegative(adapter))
+    }
+}
+
+/// Fails lines whose prices or quantities are negative as parse errors, whichever exchange
+/// sent them, before they reach a book or a matcher.
+struct NonNegative(Box<dyn ExchangeAdapter>);
+
+impl ExchangeAdapter for NonNegative {
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError> {
+        self.0.symbol_of(line)
+    }
+
+    fn parse_snapshot(&mut self, raw: &str) -> Result<SnapShotUpdate, EngineError> {
+        let snapshot = self.0.parse_snapshot(raw)?;
+        snapshot.check()?;
+        Ok(snapshot)
+    }
+
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError> {
+        let events = self.0.parse_book(line)?;
+        for event in &events {
+            match event {
+                BookEvent::Snapshot(snapshot) => snapshot.check()?,
+                BookEvent::Depth(update) => update.check()?,
+            }
+        }
+        Ok(events)
+    }
+
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError> {
+        let trades = self.0.parse_trades(line)?;
+        for trade in &trades {
+            trade.check()?;
+        }
+        Ok(trades)
+    }
+
+    fn verify_book(&mut self, book: &mut OrderBook) -> Result<(), EngineError> {
+        self.0.verify_book(book)
+    }
+
+    fn state(&self) -> Value {
+        self.0.state()
+    }
+
+    fn restore(&mut self, state: Value) -> Result<(), EngineError> {
+        self.0.restore(state)
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "binance" => Ok(Exchange::Binance),
+            "coinbase" => Ok(Exchange::Coinbase),
+            "kraken" => Ok(Exchange::Kraken),
+            "okx" => Ok(Exchange::Okx),
+            "bybit" => Ok(Exchange::Bybit),
+            other => Err(format!("Unknown exchange: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Exchange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Converts an RFC 3339 UTC timestamp such as `2019-08-14T20:42:27.265Z` to milliseconds since the epoch.
+/// Timestamps with another offset than `Z`, before 1970, after 9999 or naming a day or time
+/// that does not exist are refused.
+pub fn parse_rfc3339_millis(timestamp: &str) -> Result<u64, EngineError> {
+    let invalid = || EngineError::parse(format!("Invalid timestamp: {}", timestamp));
+    let (date, time) = timestamp.strip_suffix('Z').and_then(|utc| utc.split_once('T')).ok_or_else(invalid)?;
+    let mut date_parts = date.splitn(3, '-').map(field);
+    let (year, month, day) = match (date_parts.next(), date_parts.next(), date_parts.next()) {
+        (Some(Some(year)), Some(Some(month)), Some(Some(day))) => (year, month, day),
+        _ => return Err(invalid()),
+    };
+    let (hms, fraction) = time.split_once('.').unwrap_or((time, "0"));
+    let mut time_parts = hms.splitn(3, ':').map(field);
+    let (hour, minute, second) = match (time_parts.next(), time_parts.next(), time_parts.next()) {
+        (Some(Some(hour)), Some(Some(minute)), Some(Some(second))) => (hour, minute, second),
+        _ => return Err(invalid()),
+    };
+    // Seconds up to 60 for leap seconds
+    if !(1970..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day)
+        || hour >= 24 || minute >= 60 || second >= 61 {
+        return Err(invalid());
+    }
+    // Digits only, so that the fraction can be cut at its third byte
+    if fraction.is_empty() || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let millis = format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse::<i64>().map_err(|_| invalid())?;
+    // Days since 1970-01-01 in the proleptic Gregorian calendar
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let total = days.checked_mul(24).and_then(|hours| hours.checked_add(hour))
+        .and_then(|hours| hours.checked_mul(60)).and_then(|minutes| minutes.checked_add(minute))
+        .and_then(|minutes| minutes.checked_mul(60)).and_then(|seconds| seconds.checked_add(second))
+        .and_then(|seconds| seconds.checked_mul(1000)).and_then(|millis_total| millis_total.checked_add(millis))
+        .ok_or_else(invalid)?;
+    u64::try_from(total).map_err(|_| invalid())
+}
+
+/// A date or time field of a timestamp: digits only, without a sign.
+fn field(digits: &str) -> Option<i64> {
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
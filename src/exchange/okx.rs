@@ -0,0 +1,214 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::EngineError;
+use crate::exchange::{BookEvent, ExchangeAdapter};
+use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
+use crate::orderbook::OrderBook;
+
+/// Number of levels per side covered by the book checksum.
+const CHECKSUM_LEVELS: usize = 25;
+
+#[derive(Deserialize)]
+struct OkxArg {
+    channel: String,
+    #[serde(rename = "instId")]
+    inst_id: String,
+}
+
+/// Push message envelope; subscription events carry no `data`.
+#[derive(Deserialize)]
+struct OkxMessage<T> {
+    arg: Option<OkxArg>,
+    action: Option<String>,
+    #[serde(default = "Vec::new")]
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxBook {
+    /// Each level is [price, size, deprecated, number of orders].
+    asks: Vec<Vec<String>>,
+    bids: Vec<Vec<String>>,
+    ts: String,
+    checksum: Option<i64>,
+    prev_seq_id: Option<i64>,
+    seq_id: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxTrade {
+    inst_id: String,
+    trade_id: String,
+    px: Decimal,
+    sz: Decimal,
+    /// Side of the taker.
+    side: String,
+    ts: String,
+}
+
+fn parse_levels(levels: &[Vec<String>]) -> Result<Vec<(Decimal, Decimal)>, EngineError> {
+    levels.iter()
+        .map(|level| match level.as_slice() {
+            [price, size, ..] => Ok((price.parse()?, size.parse()?)),
+            _ => Err(EngineError::parse(format!("Invalid OKX book level: {:?}", level))),
+        })
+        .collect()
+}
+
+/// CRC32 of the top 25 bids and asks interleaved as `bid:ask:...`, each level `price:size`,
+/// read as a signed integer as OKX sends it. Prices and sizes keep the scale they were received with.
+pub fn book_checksum(book: &OrderBook) -> i32 {
+    let bids: Vec<_> = book.top_bids(CHECKSUM_LEVELS).collect();
+    let asks: Vec<_> = book.top_asks(CHECKSUM_LEVELS).collect();
+    let mut fields = Vec::new();
+    for level in 0..CHECKSUM_LEVELS {
+        for side in [&bids, &asks] {
+            if let Some((price, size)) = side.get(level) {
+                fields.push(format!("{}:{}", price, size));
+            }
+        }
+    }
+    crc32fast::hash(fields.join(":").as_bytes()) as i32
+}
+
+/// Number of levels maintained by an OKX order book channel.
+fn channel_depth(channel: &str) -> usize {
+    match channel {
+        "books5" => 5,
+        "bbo-tbt" => 1,
+        "books50-l2-tbt" => 50,
+        _ => 400,
+    }
+}
+
+/// OKX `books` family and `trades` channels.
+///
+/// Updates chain through `prevSeqId`/`seqId`, mapped onto the previous and final update ids;
+/// every update's `checksum` is validated against the reconstructed book, truncated to the
+/// channel depth. Trade sides are taker sides: `sell` hit the bids.
+#[derive(Serialize, Deserialize)]
+pub struct OkxAdapter {
+    depth: usize,
+    pending_checksum: Option<i32>,
+}
+
+impl OkxAdapter {
+    pub fn new() -> Self {
+        Self {
+            depth: 0,
+            pending_checksum: None,
+        }
+    }
+}
+
+impl Default for OkxAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExchangeAdapter for OkxAdapter {
+    fn symbol_of(&self, line: &str) -> Result<Option<String>, EngineError> {
+        let message: OkxMessage<serde::de::IgnoredAny> = serde_json::from_str(line)?;
+        Ok(message.arg.map(|arg| arg.inst_id))
+    }
+
+    fn parse_snapshot(&mut self, _raw: &str) -> Result<SnapShotUpdate, EngineError> {
+        Err(EngineError::config("OKX books sends its snapshot in the stream - no snapshot file is needed"))
+    }
+
+    fn parse_book(&mut self, line: &str) -> Result<Vec<BookEvent>, EngineError> {
+        let message: OkxMessage<OkxBook> = serde_json::from_str(line)?;
+        let Some(arg) = message.arg else { return Ok(Vec::new()) };
+        if !arg.channel.starts_with("books") && arg.channel != "bbo-tbt" {
+            return Ok(Vec::new());
+        }
+        self.depth = channel_depth(&arg.channel);
+        let mut events = Vec::new();
+        for book in message.data {
+            self.pending_checksum = book.checksum.map(|checksum| checksum as i32);
+            let seq_id = book.seq_id.unwrap_or_default();
+            // Channels without an action push a full book every time
+            if message.action.as_deref() != Some("update") {
+                events.push(BookEvent::Snapshot(SnapShotUpdate {
+                    last_update_id: seq_id.max(1),
+                    bids: parse_levels(&book.bids)?,
+                    asks: parse_levels(&book.asks)?,
+                }));
+                continue;
+            }
+            let prev_seq_id = book.prev_seq_id.and_then(|id| u64::try_from(id).ok())
+                .ok_or_else(|| EngineError::parse("OKX update without prevSeqId"))?;
+            events.push(BookEvent::Depth(DepthUpdate {
+                event_type: String::from("books"),
+                event_time: book.ts.parse()?,
+                symbol: arg.inst_id.clone(),
+                first_update_id_in_event: prev_seq_id + 1,
+                final_update_id_in_event: seq_id,
+                previous_final_update_id: Some(prev_seq_id),
+                bids_to_update: parse_levels(&book.bids)?,
+                asks_to_update: parse_levels(&book.asks)?,
+                ..Default::default()
+            }));
+        }
+        Ok(events)
+    }
+
+    fn parse_trades(&mut self, line: &str) -> Result<Vec<TradeUpdate>, EngineError> {
+        let message: OkxMessage<OkxTrade> = serde_json::from_str(line)?;
+        match message.arg {
+            Some(arg) if arg.channel == "trades" => {}
+            _ => return Ok(Vec::new()),
+        }
+        message.data.into_iter()
+            .map(|trade| {
+                let time = trade.ts.parse()?;
+                let is_market_maker = match trade.side.as_str() {
+                    "sell" => true,
+                    "buy" => false,
+                    other => return Err(EngineError::parse(format!("Unknown OKX trade side: {}", other))),
+                };
+                Ok(TradeUpdate {
+                    event_type: String::from("trades"),
+                    event_time: time,
+                    symbol: trade.inst_id,
+                    trade_id: trade.trade_id,
+                    price: trade.px,
+                    quantity: trade.sz,
+                    trade_time: time,
+                    is_market_maker,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn verify_book(&mut self, book: &mut OrderBook) -> Result<(), EngineError> {
+        if !book.is_synced() {
+            return Ok(());
+        }
+        if self.depth > 0 {
+            book.truncate(self.depth);
+        }
+        if let Some(expected) = self.pending_checksum.take() {
+            let computed = book_checksum(book);
+            if computed != expected {
+                return Err(EngineError::desync(format!("OKX checksum mismatch - expected {} computed {}", expected, computed)));
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    fn restore(&mut self, state: Value) -> Result<(), EngineError> {
+        *self = serde_json::from_value(state)?;
+        Ok(())
+    }
+}
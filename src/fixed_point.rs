@@ -0,0 +1,282 @@
+//! Fixed-point prices and quantities: whole numbers of a symbol's tick and lot sizes held in
+//! an `i64`, which books compare and subtract faster than decimals. Values are scaled back
+//! with the tick and lot sizes as given, so a tick size of `0.01000000` writes prices with
+//! eight decimals as Binance sends them.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Sub, SubAssign};
+use std::str::FromStr;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::error::EngineError;
+
+/// Tick and lot sizes of a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FixedPoint {
+    tick_size: Decimal,
+    lot_size: Decimal,
+}
+
+impl FixedPoint {
+    pub fn new(tick_size: Decimal, lot_size: Decimal) -> Result<Self, EngineError> {
+        if tick_size <= Decimal::ZERO || lot_size <= Decimal::ZERO {
+            return Err(EngineError::config(format!("Tick and lot sizes must be positive, got {}:{}", tick_size, lot_size)));
+        }
+        Ok(Self { tick_size, lot_size })
+    }
+
+    pub fn tick_size(&self) -> Decimal {
+        self.tick_size
+    }
+
+    pub fn lot_size(&self) -> Decimal {
+        self.lot_size
+    }
+
+    /// Whole number of ticks of a price, failing when it is off the tick grid.
+    pub fn ticks(&self, price: Decimal) -> Result<i64, EngineError> {
+        units(price, self.tick_size)
+            .ok_or_else(|| EngineError::parse(format!("Price {} is not a whole number of ticks of {}", price, self.tick_size)))
+    }
+
+    /// Whole number of lots of a quantity, failing when it is off the lot grid.
+    pub fn lots(&self, quantity: Decimal) -> Result<i64, EngineError> {
+        units(quantity, self.lot_size)
+            .ok_or_else(|| EngineError::parse(format!("Quantity {} is not a whole number of lots of {}", quantity, self.lot_size)))
+    }
+
+    /// Price of a number of ticks, with the scale of the tick size.
+    pub fn price(&self, ticks: i64) -> Decimal {
+        self.tick_size * Decimal::from(ticks)
+    }
+
+    /// Quantity of a number of lots, with the scale of the lot size.
+    pub fn quantity(&self, lots: i64) -> Decimal {
+        self.lot_size * Decimal::from(lots)
+    }
+}
+
+/// `value / unit` when it is a whole number fitting an `i64`, from the mantissas alone.
+fn units(value: Decimal, unit: Decimal) -> Option<i64> {
+    let (scale, unit_scale) = (value.scale(), unit.scale());
+    let (numerator, denominator) = if unit_scale >= scale {
+        (value.mantissa().checked_mul(10i128.checked_pow(unit_scale - scale)?)?, unit.mantissa())
+    } else {
+        (value.mantissa(), unit.mantissa().checked_mul(10i128.checked_pow(scale - unit_scale)?)?)
+    };
+    if numerator % denominator != 0 {
+        return None;
+    }
+    i64::try_from(numerator / denominator).ok()
+}
+
+impl FromStr for FixedPoint {
+    type Err = String;
+
+    /// Parses `TICK:LOT`, e.g. `0.01000000:0.00001000`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tick_size, lot_size) = s.split_once(':')
+            .ok_or_else(|| format!("Expected TICK:LOT, got {}", s))?;
+        let decimal = |value: &str| value.parse::<Decimal>().map_err(|e| format!("Invalid size {}: {}", value, e));
+        FixedPoint::new(decimal(tick_size)?, decimal(lot_size)?).map_err(|e| e.to_string())
+    }
+}
+
+impl fmt::Display for FixedPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.tick_size, self.lot_size)
+    }
+}
+
+/// Fixed-point scales of the symbols of a replay; symbols without one are replayed with
+/// decimals.
+#[derive(Debug, Clone, Default)]
+pub struct FixedPoints {
+    /// Scale of every symbol not given its own.
+    any: Option<FixedPoint>,
+    /// Scales by upper-case symbol.
+    symbols: HashMap<String, FixedPoint>,
+}
+
+impl FixedPoints {
+    /// Adds a scale for `symbol`, or for every other symbol when `None`.
+    pub fn insert(&mut self, symbol: Option<&str>, fixed_point: FixedPoint) {
+        match symbol {
+            Some(symbol) => { self.symbols.insert(symbol.to_uppercase(), fixed_point); }
+            None => self.any = Some(fixed_point),
+        }
+    }
+
+    /// Scale of a symbol, compared case-insensitively.
+    pub fn get(&self, symbol: &str) -> Option<FixedPoint> {
+        self.symbols.get(&symbol.to_uppercase()).copied().or(self.any)
+    }
+
+    /// Representation a book of `symbol` holds its levels in.
+    pub fn representation(&self, symbol: &str) -> Representation {
+        self.get(symbol).map_or(Representation::Decimal, Representation::Fixed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.any.is_none() && self.symbols.is_empty()
+    }
+}
+
+impl fmt::Display for FixedPoints {
+    /// Scales in a stable order, `-` when there are none.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "-");
+        }
+        let mut scales: Vec<String> = self.symbols.iter().map(|(symbol, fixed_point)| format!("{}={}", symbol, fixed_point)).collect();
+        scales.sort();
+        if let Some(any) = self.any {
+            scales.insert(0, any.to_string());
+        }
+        write!(f, "{}", scales.join(","))
+    }
+}
+
+/// How a book and its matchers hold prices and quantities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Representation {
+    /// As the decimals of the messages.
+    #[default]
+    Decimal,
+    /// As whole numbers of the symbol's ticks and lots.
+    Fixed(FixedPoint),
+}
+
+impl Representation {
+    pub fn price(&self, price: Decimal) -> Result<Amount, EngineError> {
+        match self {
+            Representation::Decimal => Ok(Amount::Decimal(price)),
+            Representation::Fixed(fixed_point) => fixed_point.ticks(price).map(Amount::Fixed),
+        }
+    }
+
+    pub fn quantity(&self, quantity: Decimal) -> Result<Amount, EngineError> {
+        match self {
+            Representation::Decimal => Ok(Amount::Decimal(quantity)),
+            Representation::Fixed(fixed_point) => fixed_point.lots(quantity).map(Amount::Fixed),
+        }
+    }
+
+    /// A price level of a message.
+    pub fn level(&self, (price, quantity): (Decimal, Decimal)) -> Result<(Amount, Amount), EngineError> {
+        Ok((self.price(price)?, self.quantity(quantity)?))
+    }
+
+    /// Decimal price of an amount held in this representation.
+    pub fn to_price(&self, amount: Amount) -> Decimal {
+        match (self, amount) {
+            (_, Amount::Decimal(price)) => price,
+            (Representation::Fixed(fixed_point), Amount::Fixed(ticks)) => fixed_point.price(ticks),
+            (Representation::Decimal, Amount::Fixed(_)) => mixed(),
+        }
+    }
+
+    /// Decimal quantity of an amount held in this representation.
+    pub fn to_quantity(&self, amount: Amount) -> Decimal {
+        match (self, amount) {
+            (_, Amount::Decimal(quantity)) => quantity,
+            (Representation::Fixed(fixed_point), Amount::Fixed(lots)) => fixed_point.quantity(lots),
+            (Representation::Decimal, Amount::Fixed(_)) => mixed(),
+        }
+    }
+
+    /// A price level in decimals.
+    pub fn to_level(&self, (price, quantity): (Amount, Amount)) -> (Decimal, Decimal) {
+        (self.to_price(price), self.to_quantity(quantity))
+    }
+}
+
+/// A price or quantity as a book holds it. The amounts of a book all share its
+/// representation; mixing them is a bug.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Amount {
+    /// Ticks or lots.
+    Fixed(i64),
+    Decimal(Decimal),
+}
+
+#[cold]
+fn mixed() -> ! {
+    panic!("fixed-point and decimal amounts mixed")
+}
+
+impl Amount {
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Amount::Fixed(units) => *units == 0,
+            Amount::Decimal(value) => value.is_zero(),
+        }
+    }
+
+    /// Ticks or lots of a fixed-point amount; a decimal amount is a bug where only fixed-point
+    /// ones are held.
+    pub fn units(self) -> i64 {
+        match self {
+            Amount::Fixed(units) => units,
+            Amount::Decimal(_) => mixed(),
+        }
+    }
+
+    /// Sign of the amount against zero.
+    pub fn signum(&self) -> Ordering {
+        match self {
+            Amount::Fixed(units) => units.cmp(&0),
+            Amount::Decimal(value) => value.cmp(&Decimal::ZERO),
+        }
+    }
+
+    /// `self / of` as a float, `None` when it cannot be computed.
+    pub fn ratio(self, of: Amount) -> Option<f64> {
+        match (self, of) {
+            (Amount::Fixed(units), Amount::Fixed(of)) if of != 0 => Some(units as f64 / of as f64),
+            (Amount::Decimal(value), Amount::Decimal(of)) => value.checked_div(of)?.to_f64(),
+            (Amount::Fixed(_), Amount::Fixed(_)) => None,
+            _ => mixed(),
+        }
+    }
+}
+
+/// Hashes the value alone, as the amounts of a book share their variant.
+impl Hash for Amount {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Amount::Fixed(units) => units.hash(state),
+            Amount::Decimal(value) => value.hash(state),
+        }
+    }
+}
+
+impl From<Decimal> for Amount {
+    fn from(value: Decimal) -> Self {
+        Amount::Decimal(value)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        match (self, other) {
+            (Amount::Fixed(units), Amount::Fixed(other)) => Amount::Fixed(units - other),
+            (Amount::Decimal(value), Amount::Decimal(other)) => Amount::Decimal(value - other),
+            _ => mixed(),
+        }
+    }
+}
+
+impl SubAssign for Amount {
+    fn sub_assign(&mut self, other: Amount) {
+        *self = *self - other;
+    }
+}
@@ -0,0 +1,122 @@
+//! Entry points of the fuzz targets under `fuzz/`. Each takes the raw fuzzer input, so that
+//! `tests/fuzz_regressions.rs` can replay the crash reproducers kept under
+//! `tests/fixtures/fuzz/<target>/` through the same code.
+//!
+//! Inputs that fail to parse are fine; only panics, hangs and runaway allocations are bugs.
+
+use crate::engine::{self, Aggregation, ReplayOptions, Streams, TradeStreams};
+use crate::exchange::{BookEvent, Exchange};
+use crate::messages::{DepthUpdate, SnapShotUpdate};
+use crate::orderbook::{CrossRepair, OrderBook};
+use crate::trade_matcher::{Matching, MATCH_WINDOW};
+
+const EXCHANGES: [Exchange; 5] = [Exchange::Binance, Exchange::Coinbase, Exchange::Kraken, Exchange::Okx, Exchange::Bybit];
+const CROSS_REPAIRS: [CrossRepair; 4] = [CrossRepair::None, CrossRepair::DropStaleSide, CrossRepair::TrustNewest, CrossRepair::Resync];
+
+/// Book the depth and trade targets apply their messages to.
+const SNAPSHOT: &str = r#"{"lastUpdateId":100,"bids":[["41999.99","1.5"],["41999.98","2"]],"asks":[["42000.00","0.5"],["42000.01","3"]]}"#;
+/// Depth event taking volume off both best levels, leaving level deltas for trades to match.
+const DEPTH: &str = r#"{"e":"depthUpdate","E":1702798596110,"s":"BTCUSDT","U":101,"u":102,"b":[["41999.99","1"]],"a":[["42000.00","0.25"]]}"#;
+
+fn book() -> OrderBook {
+    let mut book = OrderBook::new();
+    book.update_with_snapshot(serde_json::from_str(SNAPSHOT).expect("valid snapshot"));
+    book
+}
+
+/// A Binance depth event, applied to a small book.
+pub fn depth_update(data: &[u8]) {
+    let Ok(line) = std::str::from_utf8(data) else { return };
+    let Ok(depth_update) = serde_json::from_str::<DepthUpdate>(line) else { return };
+    let mut book = book();
+    book.update(depth_update);
+}
+
+/// A Binance trade or aggTrade event, matched against a small book.
+pub fn trade(data: &[u8]) {
+    let Ok(line) = std::str::from_utf8(data) else { return };
+    let Ok(trades) = Exchange::Binance.adapter().parse_trades(line) else { return };
+    let mut book = book();
+    book.update(serde_json::from_str(DEPTH).expect("valid depth event"));
+    for trade in trades {
+        let _ = trade.raw_trade_ids();
+        book.match_and_process_trade(&trade, engine::hit_side(&trade), MATCH_WINDOW);
+    }
+}
+
+/// A Binance REST depth snapshot, loaded into a book.
+pub fn snapshot(data: &[u8]) {
+    let Ok(raw) = std::str::from_utf8(data) else { return };
+    let Ok(snapshot) = Exchange::Binance.adapter().parse_snapshot(raw) else { return };
+    let mut book = OrderBook::new();
+    book.update_with_snapshot(snapshot);
+}
+
+/// Book and trade lines of every exchange: the first byte picks the exchange, each following
+/// line goes through its adapter's book and trade parsers and the book events are applied.
+pub fn exchange_messages(data: &[u8]) {
+    let Some((selector, rest)) = data.split_first() else { return };
+    let Ok(text) = std::str::from_utf8(rest) else { return };
+    let exchange = EXCHANGES[*selector as usize % EXCHANGES.len()];
+    let mut book_adapter = exchange.adapter();
+    let mut trade_adapter = exchange.adapter();
+    let mut book = OrderBook::new();
+    for line in text.lines() {
+        let _ = book_adapter.symbol_of(line);
+        if let Ok(events) = book_adapter.parse_book(line) {
+            for event in events {
+                match event {
+                    BookEvent::Snapshot(snapshot) => book.update_with_snapshot(snapshot),
+                    BookEvent::Depth(depth_update) => book.update(depth_update),
+                }
+            }
+            let _ = book_adapter.verify_book(&mut book);
+        }
+        let _ = trade_adapter.parse_trades(line);
+    }
+}
+
+/// A whole Binance replay. The first byte picks the matching, aggregation and cross repair;
+/// the first line is the snapshot and every other line goes to the stream its first
+/// character names: `d` depth, `a` ask trades, `b` bid trades.
+pub fn replay(data: &[u8]) {
+    let Some((selector, rest)) = data.split_first() else { return };
+    let Ok(text) = std::str::from_utf8(rest) else { return };
+    let mut lines = text.lines();
+    let snapshot: Option<SnapShotUpdate> = lines.next().and_then(|raw| serde_json::from_str(raw).ok());
+    let (mut depth, mut ask, mut bid) = (Vec::new(), Vec::new(), Vec::new());
+    for line in lines {
+        let mut chars = line.chars();
+        let stream = match chars.next() {
+            Some('d') => &mut depth,
+            Some('a') => &mut ask,
+            Some('b') => &mut bid,
+            _ => continue,
+        };
+        stream.push(chars.as_str().to_string());
+    }
+    let options = ReplayOptions {
+        matching: if selector & 1 == 0 { Matching::First } else { Matching::Scored },
+        aggregation: [Aggregation::None, Aggregation::TimePrice, Aggregation::Aggressor][(selector >> 1) as usize % 3],
+        cross_repair: CROSS_REPAIRS[(selector >> 3) as usize % CROSS_REPAIRS.len()],
+        ..ReplayOptions::new(Exchange::Binance)
+    };
+    let streams = Streams {
+        depth: engine::lines_of(depth),
+        trades: TradeStreams::BySide { ask: engine::lines_of(ask), bid: engine::lines_of(bid) },
+    };
+    let _ = engine::reconcile(&options, "", snapshot, streams);
+}
+
+/// Harness of a fuzz target, given the raw fuzzer input.
+pub type Target = fn(&[u8]);
+
+/// Fuzz targets by name, as laid out under `fuzz/fuzz_targets/` and `tests/fixtures/fuzz/`.
+pub const TARGETS: [(&str, Target); 5] = [
+    ("depth_update", depth_update),
+    ("trade", trade),
+    ("snapshot", snapshot),
+    ("exchange_messages", exchange_messages),
+    ("replay", replay),
+];
+
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::percentile;
+
+/// Confident matches needed before an adaptive window moves away from its initial value.
+const MIN_SAMPLES: usize = 50;
+/// Margin over the lag percentile, so that the window can still grow when lags sit at its edge.
+const HEADROOM: f64 = 1.25;
+/// Bounds of an adaptive window, in milliseconds.
+const MIN_WINDOW: u64 = 10;
+const MAX_WINDOW: u64 = 1000;
+
+/// Empirical distribution of the lag between trades and the depth events they were confidently
+/// matched with, optionally driving the matching window of a symbol.
+///
+/// With an adaptive percentile, once enough lags are known the window becomes that percentile
+/// of the lags plus a quarter of headroom, kept between 10 ms and 1 s.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LagEstimator {
+    /// Lags in milliseconds (depth event time minus trade event time), sorted.
+    lags: Vec<i64>,
+    window: u64,
+    adaptive: Option<f64>,
+}
+
+impl LagEstimator {
+    pub fn new(window: u64, adaptive: Option<f64>) -> Self {
+        Self { lags: Vec::new(), window, adaptive }
+    }
+
+    /// Adds the lags of new confident matches and adapts the window.
+    pub fn record(&mut self, lags: impl IntoIterator<Item = i64>) {
+        for lag in lags {
+            let index = self.lags.partition_point(|known| *known <= lag);
+            self.lags.insert(index, lag);
+        }
+        let Some(p) = self.adaptive else { return };
+        if self.lags.len() < MIN_SAMPLES {
+            return;
+        }
+        if let Some(lag) = self.percentile(p) {
+            self.window = ((lag.max(0) as f64 * HEADROOM).ceil() as u64).clamp(MIN_WINDOW, MAX_WINDOW);
+        }
+    }
+
+    /// Current matching window in milliseconds.
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn lags(&self) -> &[i64] {
+        &self.lags
+    }
+
+    pub fn percentile(&self, p: f64) -> Option<i64> {
+        percentile(&self.lags, p)
+    }
+}
@@ -0,0 +1,20 @@
+pub mod error;
+pub mod messages;
+pub mod orderbook;
+pub mod trade_matcher;
+pub mod engine;
+pub mod batch;
+pub mod exchange;
+pub mod sequencing;
+pub mod price_check;
+pub mod assignment;
+pub mod lag;
+pub mod evaluation;
+pub mod simulation;
+pub mod fuzzing;
+pub mod malformed;
+pub mod checkpoint;
+pub mod book_state;
+pub mod capture;
+pub mod fixed_point;
+pub mod book_store;
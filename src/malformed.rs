@@ -0,0 +1,139 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use log::{debug, warn};
+
+use crate::error::EngineError;
+
+/// What to do with a line of an input stream that cannot be parsed. Every policy but
+/// `Abort` leaves the line out of the replay and counts it in the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinePolicy {
+    /// Skip the line with a warning giving its stream and line number.
+    #[default]
+    Skip,
+    /// Skip the line and only count it, for captures known to hold many bad lines.
+    Count,
+    /// Stop the replay with an error giving the stream and line number.
+    Abort,
+    /// Skip the line and write it with its stream, line number and error to the reject file.
+    Quarantine,
+}
+
+impl FromStr for LinePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(LinePolicy::Skip),
+            "count" => Ok(LinePolicy::Count),
+            "abort" => Ok(LinePolicy::Abort),
+            "quarantine" => Ok(LinePolicy::Quarantine),
+            other => Err(format!("Unknown line policy: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for LinePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LinePolicy::Skip => "skip",
+            LinePolicy::Count => "count",
+            LinePolicy::Abort => "abort",
+            LinePolicy::Quarantine => "quarantine",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// File receiving the quarantined lines of every stream and symbol of a replay, one per line:
+/// `symbol`, `stream`, `line number`, `error` and the raw line, separated by tabs.
+#[derive(Clone)]
+pub struct RejectFile {
+    path: PathBuf,
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl RejectFile {
+    pub fn create(path: &Path) -> Result<Self, EngineError> {
+        let writer = BufWriter::new(File::create(path).map_err(|e| EngineError::io(path, e))?);
+        Ok(Self { path: path.to_path_buf(), writer: Arc::new(Mutex::new(writer)) })
+    }
+
+    /// Opens a reject file keeping the lines already in it, e.g. for a resumed replay.
+    pub fn append(path: &Path) -> Result<Self, EngineError> {
+        let file = OpenOptions::new().create(true).append(true).open(path).map_err(|e| EngineError::io(path, e))?;
+        Ok(Self { path: path.to_path_buf(), writer: Arc::new(Mutex::new(BufWriter::new(file))) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write(&self, symbol: &str, stream: &str, line_number: u64, error: &str, line: &str) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", symbol, stream, line_number, error, line)?;
+        writer.flush()
+    }
+}
+
+impl fmt::Debug for RejectFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RejectFile").field("path", &self.path).finish()
+    }
+}
+
+/// Name of a symbol's stream in errors, e.g. `BTCUSDT ask trades stream`.
+pub(crate) fn stream_name(symbol: &str, stream: &str) -> String {
+    if symbol.is_empty() { format!("{} stream", stream) } else { format!("{} {} stream", symbol, stream) }
+}
+
+/// Lines left out of a replay because they could not be parsed, by stream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MalformedLines {
+    /// Lines of the book stream.
+    pub depth: u64,
+    /// Lines of the trade streams.
+    pub trades: u64,
+}
+
+impl MalformedLines {
+    pub fn total(&self) -> u64 {
+        self.depth + self.trades
+    }
+}
+
+/// Applies a replay's line policy to the malformed lines of one symbol.
+#[derive(Debug, Clone)]
+pub struct Rejects {
+    policy: LinePolicy,
+    reject_file: Option<RejectFile>,
+}
+
+impl Rejects {
+    pub fn new(policy: LinePolicy, reject_file: Option<RejectFile>) -> Self {
+        Self { policy, reject_file }
+    }
+
+    /// Handles a line of `stream` that failed to parse: the error to stop the replay with
+    /// under `Abort`, otherwise nothing once the line is logged or quarantined.
+    /// Without a reject file quarantined lines are only counted.
+    pub fn reject(&self, symbol: &str, stream: &str, line_number: u64, line: &str, error: EngineError) -> Result<(), EngineError> {
+        match self.policy {
+            LinePolicy::Skip => warn!("{} - Skipping line {} of the {} stream: {}", symbol, line_number, stream, error),
+            LinePolicy::Count => debug!("{} - Skipping line {} of the {} stream: {}", symbol, line_number, stream, error),
+            LinePolicy::Abort => return Err(error.at(stream_name(symbol, stream), Some(line_number))),
+            LinePolicy::Quarantine => {
+                debug!("{} - Quarantining line {} of the {} stream: {}", symbol, line_number, stream, error);
+                if let Some(reject_file) = &self.reject_file {
+                    reject_file.write(symbol, stream, line_number, &error.to_string(), line)
+                        .map_err(|e| EngineError::io(reject_file.path(), e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@ use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::error::EngineError;
+
 /// Custom deserialization function for Decimal
 /// Converts a string to a Decimal, returning an error if the string is not a valid representation.
 fn deserialize_decimal<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
@@ -39,8 +41,16 @@ where
     }
 }
 
+/// Refuses price levels with a negative price or quantity, which no exchange sends.
+pub fn check_levels(levels: &[(Decimal, Decimal)]) -> Result<(), EngineError> {
+    match levels.iter().find(|(price, quantity)| *price < Decimal::ZERO || *quantity < Decimal::ZERO) {
+        Some((price, quantity)) => Err(EngineError::parse(format!("Negative price level {} {}", price, quantity))),
+        None => Ok(()),
+    }
+}
+
 /// Represents the JSON message format for depth updates via WebSockets.
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct DepthUpdate {
     #[serde(rename = "e")]
     pub event_type: String,
@@ -52,6 +62,12 @@ pub struct DepthUpdate {
     pub first_update_id_in_event: u64,
     #[serde(rename = "u")]
     pub final_update_id_in_event: u64,
+    /// Final update id of the previous event, sent by futures streams only.
+    #[serde(rename = "pu", default, skip_serializing_if = "Option::is_none")]
+    pub previous_final_update_id: Option<u64>,
+    /// Matching engine transaction time, sent by futures streams only.
+    #[serde(rename = "T", default, skip_serializing_if = "Option::is_none")]
+    pub transaction_time: Option<u64>,
     /// List of bid values to update; each bid is represented as a pair of Decimals [price, quantity].
     #[serde(rename = "b")]
     pub bids_to_update: Vec<(Decimal, Decimal)>,
@@ -61,7 +77,7 @@ pub struct DepthUpdate {
 }
 
 /// Represents the JSON message format for an order book snapshot update via HTTP.
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct SnapShotUpdate {
     #[serde(rename = "lastUpdateId")]
     pub last_update_id: u64,
@@ -72,7 +88,7 @@ pub struct SnapShotUpdate {
 }
 
 /// Represents the JSON message format for trade updates via WebSockets.
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct TradeUpdate {
     #[serde(rename = "e")]
     pub event_type: String,
@@ -94,4 +110,114 @@ pub struct TradeUpdate {
     pub trade_time: u64,
     #[serde(rename = "m")]
     pub is_market_maker: bool,
+    /// Raw trades covered when the trade is an aggregate; only serialised when it is one,
+    /// e.g. in replay checkpoints.
+    #[serde(default, skip_serializing_if = "TradeIds::is_single")]
+    pub trade_ids: TradeIds,
+    /// Quantity taken at each price, in fill order, when an aggregate walked several levels;
+    /// empty when all of it traded at `price`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sweep: Vec<(Decimal, Decimal)>,
+}
+
+/// Raw trades an aggregated trade stands for.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TradeIds {
+    /// Not aggregated: the trade is its own `trade_id`.
+    #[default]
+    Single,
+    /// The contiguous ids `first..=last`, as sent with a Binance aggTrade.
+    Range { first: u64, last: u64 },
+    /// Trades merged by the engine, in stream order.
+    Merged(Vec<String>),
+}
+
+impl TradeIds {
+    pub fn is_single(&self) -> bool {
+        *self == TradeIds::Single
+    }
+}
+
+impl DepthUpdate {
+    /// Refuses an event with a negative price or quantity.
+    pub fn check(&self) -> Result<(), EngineError> {
+        check_levels(&self.bids_to_update)?;
+        check_levels(&self.asks_to_update)
+    }
+}
+
+impl SnapShotUpdate {
+    /// Refuses a snapshot with a negative price or quantity.
+    pub fn check(&self) -> Result<(), EngineError> {
+        check_levels(&self.bids)?;
+        check_levels(&self.asks)
+    }
+}
+
+impl TradeUpdate {
+    /// Refuses a trade with a negative price or quantity, at any price of its sweep.
+    pub fn check(&self) -> Result<(), EngineError> {
+        if self.price < Decimal::ZERO || self.quantity < Decimal::ZERO {
+            return Err(EngineError::parse(format!("Negative trade price or quantity {} {} in trade {}", self.price, self.quantity, self.trade_id)));
+        }
+        check_levels(&self.sweep)
+    }
+
+    /// Order id of the taker: the buyer unless the buyer was the market maker.
+    pub fn aggressor_order_id(&self) -> u64 {
+        if self.is_market_maker { self.seller_order_id } else { self.buyer_order_id }
+    }
+
+    /// Ids of the raw trades this trade stands for.
+    pub fn raw_trade_ids(&self) -> Vec<String> {
+        match &self.trade_ids {
+            TradeIds::Single => vec![self.trade_id.clone()],
+            TradeIds::Range { first, last } => (*first..=*last).map(|id| id.to_string()).collect(),
+            TradeIds::Merged(ids) => ids.clone(),
+        }
+    }
+}
+
+/// Represents the JSON message format for aggregate trade updates via WebSockets (`@aggTrade`).
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AggTradeUpdate {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "a")]
+    pub aggregate_trade_id: u64,
+    #[serde(rename = "p", deserialize_with = "deserialize_decimal", serialize_with = "serialize_decimal")]
+    pub price: Decimal,
+    #[serde(rename = "q", deserialize_with = "deserialize_decimal", serialize_with = "serialize_decimal")]
+    pub quantity: Decimal,
+    #[serde(rename = "f")]
+    pub first_trade_id: u64,
+    #[serde(rename = "l")]
+    pub last_trade_id: u64,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+    #[serde(rename = "m")]
+    pub is_market_maker: bool,
+}
+
+impl From<AggTradeUpdate> for TradeUpdate {
+    /// An aggregate trade as one trade identified by its aggregate id, standing for its range of
+    /// raw trades; order ids are not sent.
+    fn from(agg_trade: AggTradeUpdate) -> Self {
+        Self {
+            event_type: agg_trade.event_type,
+            event_time: agg_trade.event_time,
+            symbol: agg_trade.symbol,
+            trade_id: agg_trade.aggregate_trade_id.to_string(),
+            price: agg_trade.price,
+            quantity: agg_trade.quantity,
+            trade_time: agg_trade.trade_time,
+            is_market_maker: agg_trade.is_market_maker,
+            trade_ids: TradeIds::Range { first: agg_trade.first_trade_id, last: agg_trade.last_trade_id },
+            ..Default::default()
+        }
+    }
 }
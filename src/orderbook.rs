@@ -1,54 +1,226 @@
 use rust_decimal::Decimal;
-use std::collections::BTreeMap;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 
-use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
-use crate::trade_matcher::{TradeType};
+use crate::book_store::{BookStore, Levels, OrderBookStore};
+use crate::error::EngineError;
+use crate::fixed_point::{Amount, Representation};
+use crate::messages::{check_levels, DepthUpdate, SnapShotUpdate, TradeUpdate};
+use crate::sequencing::{Sequence, Sequencer};
+use crate::trade_matcher::{TradeType, DROPPED};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// When a depth event happened, as compared with trade times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DepthTime {
+    pub event_time: u64,
+    pub transaction_time: Option<u64>,
+}
+
+impl DepthTime {
+    pub fn of(update: &DepthUpdate) -> Self {
+        Self {
+            event_time: update.event_time,
+            transaction_time: update.transaction_time,
+        }
+    }
+
+    /// Times to compare with a trade's: transaction times when the depth stream sends them
+    /// (futures), event times otherwise.
+    pub fn against(&self, trade: &TradeUpdate) -> (u64, u64) {
+        match self.transaction_time {
+            Some(transaction_time) => (transaction_time, trade.trade_time),
+            None => (self.event_time, trade.event_time),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LevelDelta {
-    price: Decimal,
-    volume: Decimal,
+    price: Amount,
+    volume: Amount,
     event_time: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transaction_time: Option<u64>,
+    update_id: u64,
 }
 
 impl LevelDelta {
-    pub fn new(price: Decimal, volume: Decimal, event_time: u64) -> Self {
+    pub fn new(price: Amount, volume: Amount, event_time: u64, transaction_time: Option<u64>, update_id: u64) -> Self {
         Self {
-            price: price,
-            volume: volume,
-            event_time: event_time,
+            price,
+            volume,
+            event_time,
+            transaction_time,
+            update_id,
+        }
+    }
+
+    pub fn price(&self) -> Amount {
+        self.price
+    }
+
+    /// Volume that left the level; negative when the level grew.
+    pub fn volume(&self) -> Amount {
+        self.volume
+    }
+
+    /// Final update id of the depth event.
+    pub fn update_id(&self) -> u64 {
+        self.update_id
+    }
+
+    pub fn depth_time(&self) -> DepthTime {
+        DepthTime {
+            event_time: self.event_time,
+            transaction_time: self.transaction_time,
+        }
+    }
+
+    fn times_against(&self, trade: &TradeUpdate) -> (u64, u64) {
+        self.depth_time().against(trade)
+    }
+}
+
+/// Price and quantity of a trade, and of each level it swept, as a book holds them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeFill {
+    pub price: Amount,
+    pub quantity: Amount,
+    pub sweep: Vec<(Amount, Amount)>,
+}
+
+impl TradeFill {
+    /// Fails when the trade is off the grid of a fixed-point representation.
+    pub fn of(trade: &TradeUpdate, representation: Representation) -> Result<Self, EngineError> {
+        Ok(Self {
+            price: representation.price(trade.price)?,
+            quantity: representation.quantity(trade.quantity)?,
+            sweep: trade.sweep.iter().map(|level| representation.level(*level)).collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// What to do when a depth event leaves the best bid at or above the best ask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CrossRepair {
+    /// Count the crossed or locked book and leave it as is.
+    #[default]
+    None,
+    /// Remove the crossing levels of the side whose best level was updated least recently.
+    DropStaleSide,
+    /// Remove whichever of the best bid and best ask was updated least recently, until the book uncrosses.
+    TrustNewest,
+    /// Drop the book and wait for the next snapshot.
+    Resync,
+}
+
+impl FromStr for CrossRepair {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(CrossRepair::None),
+            "drop-stale-side" => Ok(CrossRepair::DropStaleSide),
+            "trust-newest" => Ok(CrossRepair::TrustNewest),
+            "resync" => Ok(CrossRepair::Resync),
+            other => Err(format!("Unknown cross repair policy: {}", other)),
         }
     }
+}
+
+impl fmt::Display for CrossRepair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CrossRepair::None => "none",
+            CrossRepair::DropStaleSide => "drop-stale-side",
+            CrossRepair::TrustNewest => "trust-newest",
+            CrossRepair::Resync => "resync",
+        };
+        write!(f, "{}", name)
+    }
+}
 
+/// Crossed and locked books met while applying depth events, and how they were repaired.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrossedBooks {
+    /// Depth events after which the best bid was above the best ask.
+    pub crossed: u64,
+    /// Depth events after which the best bid equalled the best ask.
+    pub locked: u64,
+    /// Levels removed to uncross the book.
+    pub removed_levels: u64,
+    /// Books dropped to wait for a snapshot.
+    pub resyncs: u64,
+    /// Crossed or locked books the policy could not repair, e.g. both sides updated by the same event.
+    pub unrepaired: u64,
 }
 
-// Order Book struct
-pub struct OrderBook {
-    last_update_id: u64,
-    first_update_id_in_event: u64,
-    final_update_id_in_event: u64,
-    bids: BTreeMap<Decimal, Decimal>,
-    asks: BTreeMap<Decimal, Decimal>,
+// Order Book struct, keeping its levels in a store chosen at runtime unless given another
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OrderBook<S = Levels> {
+    sequencer: Sequencer,
+    representation: Representation,
+    levels: S,
+    cross_repair: CrossRepair,
+    crossed_books: CrossedBooks,
     best_bid_updated: bool,
     best_ask_updated: bool,
     best_bid_deltas: Vec::<LevelDelta>,
     best_ask_deltas: Vec::<LevelDelta>,
+    sequence_gaps: u64,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OrderBook {
     pub fn new() -> Self {
+        Self::with_levels(Levels::default())
+    }
+
+    /// A book repairing crossed and locked states with `cross_repair`.
+    pub fn with_cross_repair(cross_repair: CrossRepair) -> Self {
+        Self { cross_repair, ..Self::new() }
+    }
+
+    /// A book keeping its levels in `store`. Levels already held are dropped, so it is given
+    /// to a new book; the array store holds fixed-point levels only.
+    pub fn with_store(self, store: BookStore) -> Self {
+        Self { levels: Levels::new(store), ..self }
+    }
+
+    pub fn store(&self) -> BookStore {
+        self.levels.store()
+    }
+
+    /// A book holding a snapshot.
+    pub fn from_snapshot(snapshot: SnapShotUpdate) -> Self {
+        let mut book = Self::new();
+        book.update_with_snapshot(snapshot);
+        book
+    }
+}
+
+impl<S: OrderBookStore> OrderBook<S> {
+    /// A book keeping its levels in `levels`.
+    pub fn with_levels(levels: S) -> Self {
         Self {
-            last_update_id: 0,
-            first_update_id_in_event: 0,
-            final_update_id_in_event: 0,
-            bids: BTreeMap::new(),
-            asks: BTreeMap::new(),
+            sequencer: Sequencer::default(),
+            representation: Representation::Decimal,
+            levels,
+            cross_repair: CrossRepair::None,
+            crossed_books: CrossedBooks::default(),
             best_bid_updated: false,
             best_ask_updated: false,
             best_bid_deltas: Vec::<LevelDelta>::new(),
             best_ask_deltas: Vec::<LevelDelta>::new(),
+            sequence_gaps: 0,
         }
     }
 
@@ -63,134 +235,355 @@ impl OrderBook {
         self.best_ask_updated
     }
 
-    pub fn update(&mut self, update: DepthUpdate) {
-        // Skip if orderbok has not been loaded
-        if self.last_update_id == 0 { return; }
-        // Skip if final_update_id_in_event <= last_update_id
-        if update.final_update_id_in_event <= self.last_update_id { return; }
-        if self.first_update_id_in_event == 0 && update.first_update_id_in_event > self.last_update_id + 1 { 
-            warn!("Snapshot is a little too old - first_update_id_in_event = {:?}  last_update_id = {:?}", update.first_update_id_in_event, self.last_update_id);
+    /// Changes of the best levels of a side made by the last depth event, best level first.
+    pub fn best_deltas(&self, trade_type: TradeType) -> &[LevelDelta] {
+        match trade_type {
+            TradeType::Bid => &self.best_bid_deltas,
+            TradeType::Ask => &self.best_ask_deltas,
         }
-        if self.first_update_id_in_event != 0 && update.first_update_id_in_event != self.final_update_id_in_event + 1 { 
-            warn!("Update sequence from websocket is mechakucha - update.first_update_id_in_event = {:?}  self.final_update_id_in_event = {:?}", update.first_update_id_in_event, self.final_update_id_in_event);
+    }
+
+    /// A book holding its levels, and matching trades, in `representation`. Levels already
+    /// held are not converted, so it is given to a new book.
+    pub fn with_representation(mut self, representation: Representation) -> Self {
+        self.representation = representation;
+        self
+    }
+
+    pub fn representation(&self) -> Representation {
+        self.representation
+    }
+
+    /// Number of depth events that did not follow on from the snapshot or the previous event.
+    pub fn sequence_gaps(&self) -> u64 {
+        self.sequence_gaps
+    }
+
+    pub fn crossed_books(&self) -> CrossedBooks {
+        self.crossed_books
+    }
+
+    /// Applies a depth event. A negative level, or one off the grid of a fixed-point book,
+    /// drops the book until the next snapshot; `try_update` fails instead.
+    pub fn update(&mut self, update: DepthUpdate) {
+        if let Err(e) = self.try_update(update) {
+            warn!("{} - dropping the book until the next snapshot", e);
+            self.invalidate();
         }
-        debug!("{:?} - Updating depth - event timestamp: {}", "Bid", update.event_time);
-        self.first_update_id_in_event = update.first_update_id_in_event;
-        self.final_update_id_in_event = update.final_update_id_in_event;
-        // Store the current best bid and ask prices
-        let current_best_bid = self.bids.keys().next_back().cloned();
-        let current_best_ask = self.asks.keys().next().cloned();
+    }
+
+    /// Scales the levels of a message to the book's representation, refusing negative ones.
+    fn scaled(&self, levels: &[(Decimal, Decimal)]) -> Result<Vec<(Amount, Amount)>, EngineError> {
+        check_levels(levels)?;
+        levels.iter().map(|level| self.representation.level(*level)).collect()
+    }
+
+    /// Applies a depth event, failing with the book unchanged when a level is negative or off
+    /// the grid of a fixed-point book.
+    pub fn try_update(&mut self, update: DepthUpdate) -> Result<(), EngineError> {
         // Reset flags
         self.best_bid_updated = false;
         self.best_ask_updated = false;
         // Reset LevelDeltas before each update
         self.best_bid_deltas.clear();
         self.best_ask_deltas.clear();
+        // Skip if orderbok has not been loaded
+        if !self.sequencer.is_synced() { return Ok(()); }
+        // Scaled first, so that a level off the grid leaves the book as it was
+        let (bids_to_update, asks_to_update) = (self.scaled(&update.bids_to_update)?, self.scaled(&update.asks_to_update)?);
+        match self.sequencer.check(&update) {
+            // Skip events already covered by the snapshot or a previous event
+            Sequence::Stale => return Ok(()),
+            Sequence::Gap if !self.sequencer.has_started() => {
+                warn!("Snapshot is a little too old - {}", self.sequencer.gap(&update));
+                self.sequence_gaps += 1;
+            }
+            Sequence::Gap => {
+                warn!("Update sequence from websocket is mechakucha - {}", self.sequencer.gap(&update));
+                self.sequence_gaps += 1;
+            }
+            Sequence::Next => {}
+        }
+        debug!("{:?} - Updating depth - event timestamp: {}", "Bid", update.event_time);
+        self.sequencer.advance(&update);
+        let update_id = update.final_update_id_in_event;
+        // Store the current best bid and ask prices
+        let current_best_bid = self.levels.best(TradeType::Bid);
+        let current_best_ask = self.levels.best(TradeType::Ask);
         // Update bids
         let mut add_next_bid_level_delta: bool = false;
-        for (price_level, quantity) in update.bids_to_update {
-            debug!("{:?} - bid = {} quantity = {}", "Bid", price_level, quantity);
+        for (price_level, quantity) in bids_to_update {
+            debug!("{:?} - bid = {:?} quantity = {:?}", "Bid", price_level, quantity);
             // Check if the best bid price is updated
             if Some(&price_level) == current_best_bid.as_ref() || add_next_bid_level_delta {
-                if let Some(current_volume) = self.bids.get(&price_level) {
+                if let Some(current_volume) = self.levels.quantity(TradeType::Bid, price_level) {
                     self.best_bid_updated = true;
-                    let volume_delta = *current_volume - quantity;
-                    if quantity.is_zero() {
-                        add_next_bid_level_delta = true;
-                    } else {
-                        add_next_bid_level_delta = false;
-                    }
-                    let level_delta = LevelDelta::new(price_level, volume_delta, update.event_time);
-                    debug!("{:?} - Best Delta {}", "Bid", serde_json::to_string(&level_delta).unwrap());
+                    let volume_delta = current_volume - quantity;
+                    add_next_bid_level_delta = quantity.is_zero();
+                    let level_delta = LevelDelta::new(price_level, volume_delta, update.event_time, update.transaction_time, update_id);
+                    debug!("{:?} - Best Delta {:?}", "Bid", level_delta);
                     self.best_bid_deltas.push(level_delta);
                 }
             }
-            if quantity.is_zero() { self.bids.remove(&price_level); }
-            else { self.bids.insert(price_level, quantity); }
+            if quantity.is_zero() {
+                self.levels.remove(TradeType::Bid, price_level);
+            } else {
+                self.levels.insert(TradeType::Bid, price_level, quantity, Some(update_id));
+            }
         }
 
         // Update asks
         let mut add_next_ask_level_delta: bool = false;
-        for (price_level, quantity) in update.asks_to_update {
-            debug!("{:?} - ask = {} quantity = {}", "Ask", price_level, quantity);
+        for (price_level, quantity) in asks_to_update {
+            debug!("{:?} - ask = {:?} quantity = {:?}", "Ask", price_level, quantity);
             // Check if the best ask price is updated
             if Some(&price_level) == current_best_ask.as_ref() || add_next_ask_level_delta {
-                if let Some(current_volume) = self.asks.get(&price_level) {
+                if let Some(current_volume) = self.levels.quantity(TradeType::Ask, price_level) {
                     self.best_ask_updated = true;
-                    let volume_delta = *current_volume - quantity;
-                    if quantity.is_zero() {
-                        add_next_ask_level_delta = true;
-                    } else {
-                        add_next_ask_level_delta = false;
-                    }
-                    let level_delta = LevelDelta::new(price_level, volume_delta, update.event_time);
-                    debug!("{:?} - Best Delta {}", "Bid", serde_json::to_string(&level_delta).unwrap());
+                    let volume_delta = current_volume - quantity;
+                    add_next_ask_level_delta = quantity.is_zero();
+                    let level_delta = LevelDelta::new(price_level, volume_delta, update.event_time, update.transaction_time, update_id);
+                    debug!("{:?} - Best Delta {:?}", "Bid", level_delta);
                     self.best_ask_deltas.push(level_delta);
                 }
             }
-            if quantity.is_zero() { self.asks.remove(&price_level); } 
-            else { self.asks.insert(price_level, quantity); }
+            if quantity.is_zero() {
+                self.levels.remove(TradeType::Ask, price_level);
+            } else {
+                self.levels.insert(TradeType::Ask, price_level, quantity, Some(update_id));
+            }
         }
+        self.check_crossed(update_id);
+        Ok(())
     }
 
+    /// Counts a crossed or locked book left by an event and repairs it according to the policy.
+    fn check_crossed(&mut self, update_id: u64) {
+        let (Some(best_bid), Some(best_ask)) = (self.top_bid(), self.top_ask()) else { return };
+        if best_bid < best_ask {
+            return;
+        }
+        let price = |amount| self.representation.to_price(amount);
+        if best_bid > best_ask {
+            warn!("Book crossed at update {} - best bid {} best ask {}", update_id, price(best_bid), price(best_ask));
+            self.crossed_books.crossed += 1;
+        } else {
+            debug!("Book locked at update {} - best bid and ask {}", update_id, price(best_bid));
+            self.crossed_books.locked += 1;
+        }
+        match self.cross_repair {
+            CrossRepair::None => {}
+            CrossRepair::Resync => {
+                warn!("Dropping the book until the next snapshot");
+                self.crossed_books.resyncs += 1;
+                self.invalidate();
+            }
+            CrossRepair::DropStaleSide | CrossRepair::TrustNewest => self.uncross(),
+        }
+    }
+
+    /// Removes crossing levels until the best bid is below the best ask: the older of the two
+    /// best levels each time, or with `DropStaleSide` the levels of the side whose best level
+    /// was the older when the book crossed.
+    fn uncross(&mut self) {
+        let mut stale_side = None;
+        while let (Some(best_bid), Some(best_ask)) = (self.top_bid(), self.top_ask()) {
+            if best_bid < best_ask {
+                return;
+            }
+            let bid_age = self.levels.update_id(TradeType::Bid, best_bid).unwrap_or_default();
+            let ask_age = self.levels.update_id(TradeType::Ask, best_ask).unwrap_or_default();
+            let older = match bid_age.cmp(&ask_age) {
+                Ordering::Less => Some(TradeType::Bid),
+                Ordering::Greater => Some(TradeType::Ask),
+                Ordering::Equal => None,
+            };
+            let Some(side) = stale_side.or(older) else {
+                let price = |amount| self.representation.to_price(amount);
+                warn!("Cannot tell which side is stale - leaving the book crossed at {} / {}", price(best_bid), price(best_ask));
+                self.crossed_books.unrepaired += 1;
+                return;
+            };
+            if self.cross_repair == CrossRepair::DropStaleSide {
+                stale_side = Some(side);
+            }
+            let level = if side == TradeType::Bid { best_bid } else { best_ask };
+            debug!("Removing stale {:?} level {:?}", side, level);
+            self.levels.remove(side, level);
+            self.crossed_books.removed_levels += 1;
+        }
+    }
+
+    /// Loads a snapshot. A negative level, or one off the grid of a fixed-point book, drops
+    /// the book until the next snapshot; `try_update_with_snapshot` fails instead.
     pub fn update_with_snapshot(&mut self, update: SnapShotUpdate) {
-        self.last_update_id = update.last_update_id;
+        if let Err(e) = self.try_update_with_snapshot(update) {
+            warn!("{} - dropping the book until the next snapshot", e);
+            self.invalidate();
+        }
+    }
+
+    /// Loads a snapshot, failing with the book unchanged when a level is negative or off the
+    /// grid of a fixed-point book.
+    pub fn try_update_with_snapshot(&mut self, update: SnapShotUpdate) -> Result<(), EngineError> {
+        let (bids, asks) = (self.scaled(&update.bids)?, self.scaled(&update.asks)?);
+        self.sequencer.reset(update.last_update_id);
         debug!("snapshot update -- update.last_update_id = {}", update.last_update_id);
         self.print_orderbook(10, "State of the order book before snapshot update");
         // Clear existing bids and asks
-        self.bids.clear();
-        self.asks.clear();
-        // Update bids
-        for (price, quantity) in update.bids {
-            self.bids.insert(price, quantity);
+        self.levels.clear();
+        // Update bids, an empty level being no level
+        for (price, quantity) in bids.into_iter().filter(|(_, quantity)| !quantity.is_zero()) {
+            self.levels.insert(TradeType::Bid, price, quantity, None);
         }
         // Update asks
-        for (price, quantity) in update.asks {
-            self.asks.insert(price, quantity);
+        for (price, quantity) in asks.into_iter().filter(|(_, quantity)| !quantity.is_zero()) {
+            self.levels.insert(TradeType::Ask, price, quantity, None);
         }
         self.print_orderbook(10, "State of the order book after snapshot update");
+        Ok(())
+    }
+
+    /// The book as a snapshot at its last update id, levels best first as Binance sends them.
+    pub fn to_snapshot(&self) -> SnapShotUpdate {
+        SnapShotUpdate {
+            last_update_id: self.last_update_id(),
+            bids: self.top_bids(usize::MAX).collect(),
+            asks: self.top_asks(usize::MAX).collect(),
+        }
+    }
+
+    /// Final update id of the last applied depth event, or the snapshot's when none was applied.
+    pub fn last_update_id(&self) -> u64 {
+        self.sequencer.final_update_id().unwrap_or(self.sequencer.snapshot_update_id())
     }
 
-    pub fn match_and_process_trade(&mut self, trade: &TradeUpdate, trade_type: TradeType) -> u64 {
+    /// Quantity resting at a price level, `None` when there is no level.
+    pub fn level(&self, trade_type: TradeType, price: Decimal) -> Option<Decimal> {
+        let price = self.representation.price(price).ok()?;
+        self.levels.quantity(trade_type, price).map(|quantity| self.representation.to_quantity(quantity))
+    }
+
+    /// Whether a snapshot has been loaded since the book was created or invalidated.
+    pub fn is_synced(&self) -> bool {
+        self.sequencer.is_synced()
+    }
+
+    /// Drops the book state after a detected desync; depth updates are skipped until the next snapshot.
+    pub fn invalidate(&mut self) {
+        self.sequencer = Sequencer::default();
+        self.levels.clear();
+        self.best_bid_updated = false;
+        self.best_ask_updated = false;
+        self.best_bid_deltas.clear();
+        self.best_ask_deltas.clear();
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.top_bid().map(|price| self.representation.to_price(price))
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.top_ask().map(|price| self.representation.to_price(price))
+    }
+
+    fn top_bid(&self) -> Option<Amount> {
+        self.levels.best(TradeType::Bid)
+    }
+
+    fn top_ask(&self) -> Option<Amount> {
+        self.levels.best(TradeType::Ask)
+    }
+
+    /// Best `n` bid levels, best first.
+    pub fn top_bids(&self, n: usize) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.levels.levels(TradeType::Bid).take(n).map(|level| self.representation.to_level(level))
+    }
+
+    /// Best `n` ask levels, best first.
+    pub fn top_asks(&self, n: usize) -> impl Iterator<Item = (Decimal, Decimal)> + '_ {
+        self.levels.levels(TradeType::Ask).take(n).map(|level| self.representation.to_level(level))
+    }
+
+    /// Keeps only the best `depth` levels of each side, for feeds that stop updating levels beyond their depth.
+    pub fn truncate(&mut self, depth: usize) {
+        self.levels.truncate(TradeType::Bid, depth);
+        self.levels.truncate(TradeType::Ask, depth);
+    }
+
+    /// Matches a trade with the level deltas of the last depth event, `window` being the
+    /// milliseconds a depth event may lag the trade. A trade off the grid of a fixed-point
+    /// book matches nothing.
+    pub fn match_and_process_trade(&mut self, trade: &TradeUpdate, trade_type: TradeType, window: u64) -> u64 {
+        match TradeFill::of(trade, self.representation) {
+            Ok(fill) => self.match_fill(trade, &fill, trade_type, window),
+            Err(_) => 0,
+        }
+    }
+
+    /// Matches a trade whose price and quantities are already in the book's representation.
+    pub fn match_fill(&mut self, trade: &TradeUpdate, fill: &TradeFill, trade_type: TradeType, window: u64) -> u64 {
         let level_deltas = match trade_type {
             TradeType::Bid => &mut self.best_bid_deltas,
             TradeType::Ask => &mut self.best_ask_deltas,
         };
+        if !fill.sweep.is_empty() {
+            return Self::match_sweep(level_deltas, trade, fill, trade_type, window);
+        }
         for level_delta in level_deltas.iter_mut() {
-            debug!("{:?} - Level Delta {}", trade_type, serde_json::to_string(&level_delta).unwrap());
-            debug!("{:?} - Trade {}", trade_type, serde_json::to_string(&trade).unwrap());
-            let trade_event_time = trade.event_time;
-            debug!("{:?} - trade_id = {} level_delta.event_time = {} trade_event_time + 100 = {}", trade_type, trade.trade_id, level_delta.event_time, trade_event_time + 100);
-            if level_delta.event_time > (trade_event_time + 100) {
-                return 1;
+            debug!("{:?} - Level Delta {:?}", trade_type, level_delta);
+            debug!("{:?} - Trade {:?}", trade_type, trade);
+            let (level_delta_time, trade_event_time) = level_delta.times_against(trade);
+            debug!("{:?} - trade_id = {} level_delta time = {} trade time + window = {}", trade_type, trade.trade_id, level_delta_time, trade_event_time.saturating_add(window));
+            if level_delta_time > trade_event_time.saturating_add(window) {
+                return DROPPED;
             }
-            if level_delta.volume < Decimal::new(0, 0) { continue; }
-            if trade.price == level_delta.price && trade.quantity == level_delta.volume {
+            if level_delta.volume.signum() == Ordering::Less { continue; }
+            if fill.price == level_delta.price && fill.quantity == level_delta.volume {
                 // Here, you'd perform the matching logic and return the trade_id if matched
-                debug!("{:?} - Matched event:{} with trade {} - event.volume = {}", trade_type, serde_json::to_string(&level_delta).unwrap(), serde_json::to_string(&trade).unwrap(), level_delta.volume);
-                level_delta.volume -= trade.quantity;
+                debug!("{:?} - Matched event:{:?} with trade {:?} - event.volume = {:?}", trade_type, level_delta, trade, fill.quantity);
+                level_delta.volume -= fill.quantity;
                 return level_delta.event_time;
             }
         }
         0
     }
 
+    /// Matches a trade that walked several levels: every price of its sweep must have
+    /// lost exactly the quantity traded there in the same depth event.
+    fn match_sweep(level_deltas: &mut [LevelDelta], trade: &TradeUpdate, fill: &TradeFill, trade_type: TradeType, window: u64) -> u64 {
+        // All level deltas come from the same depth event
+        let Some(first_delta) = level_deltas.first() else { return 0 };
+        let (level_delta_time, trade_event_time) = first_delta.times_against(trade);
+        if level_delta_time > trade_event_time.saturating_add(window) {
+            return DROPPED;
+        }
+        let positions: Option<Vec<usize>> = fill.sweep.iter()
+            .map(|(price, quantity)| level_deltas.iter().position(|delta| delta.price == *price && delta.volume == *quantity))
+            .collect();
+        let Some(positions) = positions else { return 0 };
+        debug!("{:?} - Matched sweep of trade {} over {} levels", trade_type, trade.trade_id, positions.len());
+        for (position, (_, quantity)) in positions.into_iter().zip(fill.sweep.iter()) {
+            level_deltas[position].volume -= *quantity;
+        }
+        level_deltas[0].event_time
+    }
+
     pub fn print_orderbook(&self, n: usize, title: &str) {
-        let bid_len = self.bids.len();
-        let ask_len = self.asks.len();
+        let bid_len = self.levels.len(TradeType::Bid);
+        let ask_len = self.levels.len(TradeType::Ask);
         let n = n.min(bid_len.min(ask_len));
         let mut log_output = Vec::new();
         log_output.push(format!("{}\n\t\tBID\t\t\t\t\tASK", title));
-        let mut bid_iter = self.bids.iter().rev(); // BTreeMap is sorted in ascending order
-        let mut ask_iter = self.asks.iter();
+        let mut bid_iter = self.top_bids(n);
+        let mut ask_iter = self.top_asks(n);
+        // Rounded rather than formatted with a precision, which overflows rust_decimal's
+        // formatting buffer on volumes with many integer digits
+        let level = |(price, volume): (Decimal, Decimal)| format!("{} -- {}", price, volume.round_dp(15));
 
         for _ in 0..n {
-            let bid = bid_iter.next()
-                .map(|(price, volume)| format!("{} -- {:.15}", price, volume))
-                .unwrap_or_default();
-            let ask = ask_iter.next()
-                .map(|(price, volume)| format!("{} -- {:.15}", price, volume))
-                .unwrap_or_default();
+            let bid = bid_iter.next().map(level).unwrap_or_default();
+            let ask = ask_iter.next().map(level).unwrap_or_default();
                 log_output.push(format!("{:<15}\t|\t{}", bid, ask));
         }
         // Log all at once at the end
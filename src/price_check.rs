@@ -0,0 +1,125 @@
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::messages::{DepthUpdate, TradeUpdate};
+use crate::orderbook::{DepthTime, OrderBook};
+use crate::trade_matcher::TradeType;
+
+/// Relation between the best bid and the best ask of a book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spread {
+    /// The best bid is below the best ask.
+    Open,
+    /// The best bid equals the best ask.
+    Locked,
+    /// The best bid is above the best ask.
+    Crossed,
+    /// A side is empty.
+    OneSided,
+}
+
+pub fn spread(book: &OrderBook) -> Spread {
+    match (book.best_bid(), book.best_ask()) {
+        (Some(bid), Some(ask)) if bid < ask => Spread::Open,
+        (Some(bid), Some(ask)) if bid == ask => Spread::Locked,
+        (Some(_), Some(_)) => Spread::Crossed,
+        _ => Spread::OneSided,
+    }
+}
+
+/// What is needed of a raw trade to check its price.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradePrice {
+    pub trade_id: String,
+    pub event_time: u64,
+    pub trade_time: u64,
+    pub price: Decimal,
+    /// Book side the trade hit, whatever the stream it was read from.
+    pub side: TradeType,
+}
+
+impl TradePrice {
+    pub fn new(trade: &TradeUpdate, side: TradeType) -> Self {
+        Self {
+            trade_id: trade.trade_id.clone(),
+            event_time: trade.event_time,
+            trade_time: trade.trade_time,
+            price: trade.price,
+            side,
+        }
+    }
+
+    /// Whether the trade happened by a depth event: compared with its transaction time when
+    /// it has one.
+    pub fn happened_by(&self, depth_time: DepthTime) -> bool {
+        match depth_time.transaction_time {
+            Some(transaction_time) => self.trade_time <= transaction_time,
+            None => self.event_time <= depth_time.event_time,
+        }
+    }
+}
+
+/// Trade price anomalies of a replay.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceChecks {
+    /// Trades priced better than the best level of the side they hit, i.e. inside the spread
+    /// or on the other side of the book.
+    pub out_of_book_trades: u64,
+    /// Trades that could not be checked because the book was not synced, one-sided, locked or crossed.
+    pub unchecked_trades: u64,
+}
+
+/// Checks every trade against the book as it stood when the trade happened, that is
+/// before the first depth event that is not older than the trade. Trades are expected to be
+/// added before the depth events after them are checked.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct PriceChecker {
+    trades: Vec<TradePrice>,
+    checks: PriceChecks,
+}
+
+impl PriceChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues trades read from either side's stream.
+    pub fn add(&mut self, trades: impl IntoIterator<Item = TradePrice>) {
+        self.trades.extend(trades);
+    }
+
+    /// Checks the trades that happened up to a depth event against the book before it.
+    pub fn check_before(&mut self, book: &OrderBook, depth_update: &DepthUpdate) {
+        let depth_time = DepthTime::of(depth_update);
+        let (happened, later): (Vec<_>, _) = std::mem::take(&mut self.trades).into_iter().partition(|trade| trade.happened_by(depth_time));
+        self.trades = later;
+        for trade in happened {
+            self.check_trade(book, &trade);
+        }
+    }
+
+    /// Checks the trades left after the last depth event against the final book.
+    pub fn finish(mut self, book: &OrderBook) -> PriceChecks {
+        for trade in std::mem::take(&mut self.trades) {
+            self.check_trade(book, &trade);
+        }
+        self.checks
+    }
+
+    fn check_trade(&mut self, book: &OrderBook, trade: &TradePrice) {
+        let trade_type = trade.side;
+        if !book.is_synced() || spread(book) != Spread::Open {
+            self.checks.unchecked_trades += 1;
+            return;
+        }
+        let (best, out_of_book) = match trade_type {
+            TradeType::Ask => (book.best_ask(), book.best_ask().is_some_and(|best_ask| trade.price < best_ask)),
+            TradeType::Bid => (book.best_bid(), book.best_bid().is_some_and(|best_bid| trade.price > best_bid)),
+        };
+        if out_of_book {
+            warn!("{:?} - Trade ID {} at {} is better than the best level {:?}", trade_type, trade.trade_id, trade.price, best);
+            self.checks.out_of_book_trades += 1;
+        }
+    }
+}
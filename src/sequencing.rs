@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::EngineError;
+use crate::messages::DepthUpdate;
+
+/// Where a depth event falls relative to the snapshot and the events already applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    /// Follows on from the previous event, or straddles the snapshot for the first one.
+    Next,
+    /// Already covered by the snapshot or a previous event.
+    Stale,
+    /// Some events are missing before this one.
+    Gap,
+}
+
+/// Tracks the update ids of a depth stream and checks each event against them.
+///
+/// Adapters express their exchange's sync semantics through the ids of `DepthUpdate`:
+/// - without a previous id (Binance spot `U`/`u`, Bybit `u`, synthesised ids), events chain
+///   when `U` is the previous `u` plus one, and the first one must straddle the snapshot id plus one;
+/// - with a previous id (Binance futures `pu`, OKX `prevSeqId`), events chain when it equals the
+///   previous `u`, and the first one must name the snapshot id or straddle it.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Sequencer {
+    snapshot_update_id: u64,
+    final_update_id: Option<u64>,
+}
+
+impl Sequencer {
+    /// Restarts the sequence from a snapshot.
+    pub fn reset(&mut self, snapshot_update_id: u64) {
+        self.snapshot_update_id = snapshot_update_id;
+        self.final_update_id = None;
+    }
+
+    /// Whether a snapshot has been loaded.
+    pub fn is_synced(&self) -> bool {
+        self.snapshot_update_id != 0
+    }
+
+    /// Whether an event has been applied since the snapshot.
+    pub fn has_started(&self) -> bool {
+        self.final_update_id.is_some()
+    }
+
+    pub fn snapshot_update_id(&self) -> u64 {
+        self.snapshot_update_id
+    }
+
+    /// Final update id of the last applied event.
+    pub fn final_update_id(&self) -> Option<u64> {
+        self.final_update_id
+    }
+
+    /// The gap between the last applied event, or the snapshot, and an event checked as `Gap`.
+    pub fn gap(&self, update: &DepthUpdate) -> EngineError {
+        EngineError::SequenceGap {
+            last_update_id: self.final_update_id.unwrap_or(self.snapshot_update_id),
+            first_update_id: update.first_update_id_in_event,
+        }
+    }
+
+    pub fn check(&self, update: &DepthUpdate) -> Sequence {
+        let first = update.first_update_id_in_event;
+        let last = update.final_update_id_in_event;
+        let snapshot = self.snapshot_update_id;
+        match update.previous_final_update_id {
+            Some(previous) => {
+                if last < snapshot { return Sequence::Stale; }
+                match self.final_update_id {
+                    Some(applied) if previous == applied => Sequence::Next,
+                    Some(applied) if last <= applied => Sequence::Stale,
+                    Some(_) => Sequence::Gap,
+                    None if previous == snapshot || first <= snapshot => Sequence::Next,
+                    None => Sequence::Gap,
+                }
+            }
+            None => {
+                if last <= snapshot { return Sequence::Stale; }
+                match self.final_update_id {
+                    Some(applied) if first == applied + 1 => Sequence::Next,
+                    Some(applied) if last <= applied => Sequence::Stale,
+                    Some(_) => Sequence::Gap,
+                    None if first <= snapshot + 1 => Sequence::Next,
+                    None => Sequence::Gap,
+                }
+            }
+        }
+    }
+
+    /// Records an event as applied.
+    pub fn advance(&mut self, update: &DepthUpdate) {
+        self.final_update_id = Some(update.final_update_id_in_event);
+    }
+}
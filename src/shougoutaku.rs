@@ -1,80 +1,28 @@
-mod messages;
-mod orderbook;
-mod trade_matcher;
-
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead};
-use log::{info, debug, error};
-use rust_decimal::Decimal;
-use std::error::Error;
-use std::fmt;
-use clap::{Command, Arg};
-
-use messages::{SnapShotUpdate, TradeUpdate, DepthUpdate};
-use orderbook::OrderBook;
-use trade_matcher::{TradeType, TradeMatcher};
-
-type Reader = io::Lines<io::BufReader<File>>;
-
-#[derive(Debug)]
-struct TradeReadError {
-    message: String,
-}
-
-impl TradeReadError {
-    fn new(msg: &str) -> TradeReadError {
-        TradeReadError { message: msg.to_string() }
-    }
-}
-
-impl fmt::Display for TradeReadError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "TradeReadError: {}", self.message)
-    }
-}
-
-impl Error for TradeReadError {}
-
-struct PreviousTradeInfo {
-    prev_event_time: u64,
-    prev_price: Decimal,
-    prev_quantity: Decimal,
-    prev_trade_id: String,
-}
-
-impl PreviousTradeInfo {
-    pub fn new() -> Self {
-        Self {
-            prev_event_time: 0,
-            prev_price: Decimal::new(0, 0),
-            prev_quantity: Decimal::new(0, 0),
-            prev_trade_id: String::from(""),
-        }
-    }
-}
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use log::info;
+use anyhow::{bail, Context, Result};
+use clap::{Command, Arg, ArgAction, ArgMatches};
 
-fn next_trades(trade_reader: &mut Reader, prev_trade_info: &mut PreviousTradeInfo, trade_matcher: &mut TradeMatcher, trade_type: &str) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(Ok(line)) = trade_reader.next() {
-        let mut trade_update: TradeUpdate = serde_json::from_str(&line)?;
-        debug!("{} {:?}", trade_type, trade_update);
-        // Compare current and previous values
-        if trade_update.event_time == prev_trade_info.prev_event_time && trade_update.price == prev_trade_info.prev_price {
-            trade_update.quantity += prev_trade_info.prev_quantity;
-            trade_update.trade_id += "-";
-            trade_update.trade_id += &prev_trade_info.prev_trade_id;
-        }
-        // Update the variables with current values for next iteration
-        prev_trade_info.prev_event_time = trade_update.event_time;
-        prev_trade_info.prev_price = trade_update.price.clone();
-        prev_trade_info.prev_quantity = trade_update.quantity.clone();
-        prev_trade_info.prev_trade_id = trade_update.trade_id.clone();
-        trade_matcher.add_trade(trade_update);
-        return Ok(());
-    }
-    Err(Box::new(TradeReadError::new("No more trades to read")))
-}
+use shougoutaku::batch;
+use shougoutaku::book_state::{self, BookFormat, SNAPSHOTS_DIR};
+use shougoutaku::book_store::BookStore;
+use shougoutaku::capture::{self, CaptureConfig, SNAPSHOT_LIMIT};
+use shougoutaku::checkpoint::{Checkpointing, CHECKPOINT_EVERY};
+use shougoutaku::engine::{self, Aggregation, ReplayOptions, ReplayReport, Streams, TradeStreams};
+use shougoutaku::evaluation;
+use shougoutaku::simulation::{self, SimulationConfig};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::fixed_point::{FixedPoint, FixedPoints};
+use shougoutaku::malformed::{LinePolicy, RejectFile};
+use shougoutaku::orderbook::CrossRepair;
+use shougoutaku::trade_matcher::Matching;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<()> {
     info!("Starting Matching Engine");
     env_logger::init();
 
@@ -82,162 +30,467 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .version("1.0")
         .author("Author Name <author@example.com>")
         .about("Does awesome trade matching")
-        .arg(Arg::new("snapshot")
-             .short('s')
-             .long("snapshot")
-             .value_name("PATH_TO_SNAPSHOT")
-             .help("Sets the snapshot file path")
-             .required(true))  // Making this argument required
-        .arg(Arg::new("ask_trade")
-             .short('a')
-             .long("ask_trade")
-             .value_name("PATH_TO_ASK_TRADE")
-             .help("Sets the ask trade file path")
-             .required(true))  // Making this argument required
-        .arg(Arg::new("bid_trade")
-             .short('b')
-             .long("bid_trade")
-             .value_name("PATH_TO_BID_TRADE")
-             .help("Sets the bid trade file path")
-             .required(true))  // Making this argument required
-        .arg(Arg::new("depth")
-             .short('d')
-             .long("depth")
-             .value_name("PATH_TO_DEPTH")
-             .help("Sets the depth file path")
-             .required(true))  // Making this argument required
+        .subcommand_negates_reqs(true)
+        .args(replay_args())
+        .arg(Arg::new("aggregation")
+             .long("aggregation")
+             .value_name("POLICY")
+             .help("Sets how raw trades are merged before matching: none (e.g. for aggTrade files), time-price or aggressor")
+             .value_parser(clap::value_parser!(Aggregation))
+             .default_value("time-price")
+             .global(true))
+        .arg(Arg::new("cross_repair")
+             .long("cross-repair")
+             .value_name("POLICY")
+             .help("Sets how a book left crossed or locked by a depth event is repaired: none, drop-stale-side, trust-newest or resync")
+             .value_parser(clap::value_parser!(CrossRepair))
+             .default_value("none")
+             .global(true))
+        .arg(Arg::new("matching")
+             .long("matching")
+             .value_name("MODE")
+             .help("Sets how trades are paired with depth events: first (first exact match) or scored (minimum-cost assignment of scored candidates)")
+             .value_parser(clap::value_parser!(Matching))
+             .default_value("first")
+             .global(true))
+        .arg(Arg::new("window")
+             .long("window")
+             .value_name("MS")
+             .help("Sets the milliseconds a depth event may lag a trade and still be matched with it, the initial value when adaptive")
+             .value_parser(clap::value_parser!(u64))
+             .default_value("100")
+             .global(true))
+        .arg(Arg::new("adaptive_window")
+             .long("adaptive-window")
+             .value_name("PERCENTILE")
+             .help("Adapts each symbol's window during the run to this percentile (e.g. 99.9) of the lags of its confident matches")
+             .value_parser(clap::value_parser!(f64))
+             .global(true))
+        .arg(Arg::new("line_policy")
+             .long("line-policy")
+             .value_name("POLICY")
+             .help("Sets what is done with lines of any stream that cannot be parsed: skip (with a warning), count (silently), abort or quarantine (to the reject file)")
+             .value_parser(clap::value_parser!(LinePolicy))
+             .default_value("skip")
+             .global(true))
+        .arg(Arg::new("reject_file")
+             .long("reject-file")
+             .value_name("PATH_TO_REJECTS")
+             .help("Sets the file quarantined lines are written to, with their symbol, stream, line number and error")
+             .global(true))
+        .arg(Arg::new("fixed_point")
+             .long("fixed-point")
+             .value_name("[SYMBOL=]TICK:LOT")
+             .help("Holds book levels and trades as whole ticks and lots of these sizes, for every symbol or once per SYMBOL. Output keeps the scale of the sizes, e.g. 0.01000000:0.00001000 for Binance BTCUSDT")
+             .action(ArgAction::Append)
+             .global(true))
+        .arg(Arg::new("book_store")
+             .long("book-store")
+             .value_name("STORE")
+             .help("Sets where books keep their levels: btree (a sorted map per side) or array (a ring of tick-indexed slots centred on the mid, for fixed-point books)")
+             .value_parser(clap::value_parser!(BookStore))
+             .default_value("btree")
+             .global(true))
+        .subcommand(Command::new("batch")
+            .about("Reconciles every capture session under a root folder in parallel")
+            .arg(Arg::new("root")
+                 .short('r')
+                 .long("root")
+                 .value_name("PATH_TO_CAPTURE_ROOT")
+                 .help("Sets the folder holding <symbol>/<session>/ captures")
+                 .required(true))
+            .arg(Arg::new("exchange")
+                 .short('x')
+                 .long("exchange")
+                 .value_name("EXCHANGE")
+                 .help("Sets the exchange the sessions come from: binance, coinbase, kraken, okx or bybit")
+                 .value_parser(clap::value_parser!(Exchange))
+                 .default_value("binance"))
+            .arg(Arg::new("jobs")
+                 .short('j')
+                 .long("jobs")
+                 .value_name("N")
+                 .help("Sets the number of sessions reconciled at once (default: number of cores)")
+                 .value_parser(clap::value_parser!(usize)))
+            .arg(Arg::new("output")
+                 .short('o')
+                 .long("output")
+                 .value_name("PATH_TO_SUMMARY")
+                 .help("Sets the summary file path (default: stdout)")))
+        .subcommand(Command::new("evaluate")
+            .about("Scores the matching output of a capture against ground-truth labels")
+            .args(replay_args())
+            .arg(Arg::new("labels")
+                 .short('l')
+                 .long("labels")
+                 .value_name("PATH_TO_LABELS")
+                 .help("Sets the label file path: one JSON object per line giving a trade_id and its expected depth update_id")
+                 .required(true)))
+        .subcommand(Command::new("simulate")
+            .about("Simulates a session of order flow and writes it as a Binance capture with ground-truth labels")
+            .arg(Arg::new("output")
+                 .short('o')
+                 .long("output")
+                 .value_name("PATH_TO_SESSION")
+                 .help("Sets the folder the snapshot, depth, trade and label files are written to")
+                 .required(true))
+            .arg(Arg::new("symbol")
+                 .long("symbol")
+                 .value_name("SYMBOL")
+                 .help("Sets the symbol of the events (default: BTCUSDT)"))
+            .arg(Arg::new("seed")
+                 .long("seed")
+                 .value_name("SEED")
+                 .help("Sets the seed of the order flow (default: 1)")
+                 .value_parser(clap::value_parser!(u64)))
+            .arg(Arg::new("duration")
+                 .long("duration")
+                 .value_name("MS")
+                 .help("Sets the length of the session (default: 60000)")
+                 .value_parser(clap::value_parser!(u64)))
+            .arg(Arg::new("batch_interval")
+                 .long("batch-interval")
+                 .value_name("MS")
+                 .help("Sets the interval whose book changes make one depth event (default: 100)")
+                 .value_parser(clap::value_parser!(u64)))
+            .arg(Arg::new("latency_jitter")
+                 .long("latency-jitter")
+                 .value_name("MS")
+                 .help("Sets the largest random delay added to event times (default: 20)")
+                 .value_parser(clap::value_parser!(u64)))
+            .arg(Arg::new("sweeps")
+                 .long("sweeps")
+                 .value_name("PROBABILITY")
+                 .help("Sets the share of market orders walking through several levels (default: 0.1)")
+                 .value_parser(clap::value_parser!(f64)))
+            .arg(Arg::new("icebergs")
+                 .long("icebergs")
+                 .value_name("PROBABILITY")
+                 .help("Sets the share of limit orders with a hidden reserve (default: 0.1)")
+                 .value_parser(clap::value_parser!(f64)))
+            .arg(Arg::new("cancellations")
+                 .long("cancellations")
+                 .value_name("PROBABILITY")
+                 .help("Sets the share of actions cancelling a resting order (default: 0.2)")
+                 .value_parser(clap::value_parser!(f64))))
+        .subcommand(Command::new("capture")
+            .about("Captures the depth and trade streams and REST snapshots of a Binance spot symbol until interrupted")
+            .arg(Arg::new("symbol")
+                 .short('s')
+                 .long("symbol")
+                 .value_name("SYMBOL")
+                 .help("Sets the trading symbol, e.g. BNBBTC")
+                 .required(true))
+            .arg(Arg::new("save_folder")
+                 .short('f')
+                 .long("save-folder")
+                 .value_name("PATH_TO_CAPTURE_ROOT")
+                 .help("Sets the folder the <symbol>/<session>/ capture is written under")
+                 .required(true))
+            .arg(Arg::new("snapshot_interval")
+                 .long("snapshot-interval")
+                 .value_name("SECONDS")
+                 .help(format!("Fetches a snapshot every SECONDS into the session's {}/ folder, which replays check their book against", SNAPSHOTS_DIR))
+                 .value_parser(clap::value_parser!(u64).range(1..)))
+            .arg(Arg::new("snapshot_limit")
+                 .long("snapshot-limit")
+                 .value_name("LEVELS")
+                 .help(format!("Sets the levels per side of each snapshot (default: {})", SNAPSHOT_LIMIT))
+                 .value_parser(clap::value_parser!(u32).range(1..=SNAPSHOT_LIMIT as i64))))
+        .subcommand(Command::new("book-diff")
+            .about("Compares the book replayed from depth events with a later snapshot of the exchange, reporting mismatched levels")
+            .arg(Arg::new("snapshot")
+                 .short('s')
+                 .long("snapshot")
+                 .value_name("PATH_TO_SNAPSHOT")
+                 .help("Sets the snapshot file the replay starts from, in the exchange's format or the binary one")
+                 .required(true))
+            .arg(Arg::new("exchange")
+                 .short('x')
+                 .long("exchange")
+                 .value_name("EXCHANGE")
+                 .help("Sets the exchange the capture comes from: binance, coinbase, kraken, okx or bybit")
+                 .value_parser(clap::value_parser!(Exchange))
+                 .default_value("binance"))
+            .arg(Arg::new("depth")
+                 .short('d')
+                 .long("depth")
+                 .value_name("PATH_TO_DEPTH")
+                 .help("Sets the depth file path")
+                 .required(true))
+            .arg(Arg::new("against")
+                 .long("against")
+                 .value_name("PATH_TO_LATER_SNAPSHOT")
+                 .help("Sets the later snapshot file the replayed book is compared with, in the exchange's format or the binary one")
+                 .required(true))
+            .arg(Arg::new("at")
+                 .long("at")
+                 .value_name("UPDATE_ID")
+                 .help("Sets the update id the book is replayed to (default: the later snapshot's)")
+                 .value_parser(clap::value_parser!(u64)))
+            .arg(Arg::new("output")
+                 .short('o')
+                 .long("output")
+                 .value_name("PATH_TO_BOOK")
+                 .help("Sets a file the replayed book is written to as a snapshot"))
+            .arg(Arg::new("format")
+                 .long("format")
+                 .value_name("FORMAT")
+                 .help("Sets the format of the written book: json (Binance snapshot) or binary")
+                 .value_parser(clap::value_parser!(BookFormat))
+                 .default_value("json")))
         .get_matches();
 
-    // Safely accessing the argument values
-    let snapshot_file_path = matches.get_one::<String>("snapshot").unwrap();
-    let ask_trade_file_path = matches.get_one::<String>("ask_trade").unwrap();
-    let bid_trade_file_path = matches.get_one::<String>("bid_trade").unwrap();
-    let depth_file_path = matches.get_one::<String>("depth").unwrap();
-
+    match matches.subcommand() {
+        Some(("batch", batch_matches)) => run_batch(batch_matches),
+        Some(("capture", capture_matches)) => run_capture(capture_matches),
+        Some(("book-diff", book_diff_matches)) => run_book_diff(book_diff_matches),
+        Some(("evaluate", evaluate_matches)) => run_evaluate(evaluate_matches),
+        Some(("simulate", simulate_matches)) => run_simulate(simulate_matches),
+        _ => run_reconcile(&matches),
+    }
+}
 
-    let snapshot_file = File::open(snapshot_file_path)?;
-    let ask_trade_file = File::open(ask_trade_file_path)?;
-    let bid_trade_file = File::open(bid_trade_file_path)?;
-    let depth_file = File::open(depth_file_path)?;
+/// Input arguments of a replay, shared by the top-level command and `evaluate`.
+fn replay_args() -> Vec<Arg> {
+    vec![
+        Arg::new("snapshot")
+            .short('s')
+            .long("snapshot")
+            .value_name("PATH_TO_SNAPSHOT")
+            .help("Sets the snapshot file path, or SYMBOL=PATH once per symbol of a multi-symbol capture. Omit it when the book stream carries its own snapshots")
+            .action(ArgAction::Append),
+        Arg::new("exchange")
+            .short('x')
+            .long("exchange")
+            .value_name("EXCHANGE")
+            .help("Sets the exchange the capture comes from: binance, coinbase, kraken, okx or bybit")
+            .value_parser(clap::value_parser!(Exchange))
+            .default_value("binance"),
+        Arg::new("ask_trade")
+            .short('a')
+            .long("ask_trade")
+            .value_name("PATH_TO_ASK_TRADE")
+            .help("Sets the ask trade file path")
+            .required_unless_present("trades"),
+        Arg::new("bid_trade")
+            .short('b')
+            .long("bid_trade")
+            .value_name("PATH_TO_BID_TRADE")
+            .help("Sets the bid trade file path")
+            .required_unless_present("trades"),
+        Arg::new("trades")
+            .short('t')
+            .long("trades")
+            .value_name("PATH_TO_TRADES")
+            .help("Sets a single trade file path holding both sides, the side hit being derived from the market maker flag")
+            .conflicts_with_all(["ask_trade", "bid_trade"]),
+        Arg::new("depth")
+            .short('d')
+            .long("depth")
+            .value_name("PATH_TO_DEPTH")
+            .help("Sets the depth file path")
+            .required(true),  // Making this argument required
+        Arg::new("validate")
+            .long("validate")
+            .value_name("PATH_TO_SNAPSHOTS")
+            .help(format!("Sets the folder of snapshots taken during the capture the book is checked against (default: the {}/ folder beside a single snapshot file, if any)", SNAPSHOTS_DIR)),
+        Arg::new("checkpoint")
+            .long("checkpoint")
+            .value_name("PATH_TO_CHECKPOINT")
+            .help("Sets the file the state of a single-symbol replay is periodically saved to"),
+        Arg::new("checkpoint_every")
+            .long("checkpoint-every")
+            .value_name("LINES")
+            .help(format!("Sets the number of depth lines between two checkpoints (default: {})", CHECKPOINT_EVERY))
+            .value_parser(clap::value_parser!(u64).range(1..))
+            .requires("checkpoint"),
+        Arg::new("resume")
+            .long("resume")
+            .help("Continues the replay from the last checkpoint, if there is one, with the same results as an uninterrupted replay")
+            .action(ArgAction::SetTrue)
+            .requires("checkpoint"),
+    ]
+}
 
-    // Create buffered readers for each file
-    let snapshot_reader = io::BufReader::new(snapshot_file);
-    let mut ask_trade_reader = io::BufReader::new(ask_trade_file).lines();
-    let mut bid_trade_reader = io::BufReader::new(bid_trade_file).lines();
-    let mut depth_reader = io::BufReader::new(depth_file).lines();
+fn run_batch(matches: &ArgMatches) -> Result<()> {
+    let root = arg::<String>(matches, "root")?;
+    let jobs = matches.get_one::<usize>("jobs").copied()
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
 
-    let snapshot: SnapShotUpdate = serde_json::from_reader(snapshot_reader)?;
-    debug!("Snapshot loaded: {:?}", snapshot);
+    let options = replay_options(matches, arg::<Exchange>(matches, "exchange")?, false)?;
 
-    let mut orderbook = OrderBook::new();
-    orderbook.update_with_snapshot(snapshot);
+    let sessions = batch::discover_sessions(Path::new(&root))?;
+    info!("Found {} sessions under {} - reconciling with {} workers", sessions.len(), root, jobs);
+    let summaries = batch::run_batch(&sessions, &options, jobs);
 
-    let mut next_ask_trade = true;
-    let mut next_bid_trade = true;
+    let mut out: Box<dyn Write> = match matches.get_one::<String>("output") {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    batch::write_summary(&mut out, &summaries)?;
+    Ok(())
+}
 
-    let mut bid_matcher = TradeMatcher::new(TradeType::Bid);
-    let mut ask_matcher = TradeMatcher::new(TradeType::Ask);
+/// Value of an argument that has a default or is required.
+fn arg<T: Clone + Send + Sync + 'static>(matches: &ArgMatches, id: &str) -> Result<T> {
+    matches.get_one::<T>(id).cloned().with_context(|| format!("Missing argument {}", id))
+}
 
-    let mut prev_ask_info = PreviousTradeInfo::new();
-    let mut prev_bid_info = PreviousTradeInfo::new();
+/// Replay settings given by the global arguments. A resumed replay adds to the reject file.
+fn replay_options(matches: &ArgMatches, exchange: Exchange, resume: bool) -> Result<ReplayOptions> {
+    let line_policy = arg::<LinePolicy>(matches, "line_policy")?;
+    let reject_file = match matches.get_one::<String>("reject_file") {
+        Some(path) if resume => Some(RejectFile::append(Path::new(path))?),
+        Some(path) => Some(RejectFile::create(Path::new(path))?),
+        None if line_policy == LinePolicy::Quarantine => bail!("--line-policy quarantine needs a --reject-file"),
+        None => None,
+    };
+    Ok(ReplayOptions {
+        exchange,
+        aggregation: arg::<Aggregation>(matches, "aggregation")?,
+        cross_repair: arg::<CrossRepair>(matches, "cross_repair")?,
+        matching: arg::<Matching>(matches, "matching")?,
+        window: arg::<u64>(matches, "window")?,
+        adaptive_window: matches.get_one::<f64>("adaptive_window").copied(),
+        line_policy,
+        reject_file,
+        checkpointing: None,
+        fixed_points: fixed_points(matches)?,
+        book_store: arg::<BookStore>(matches, "book_store")?,
+    })
+}
 
-    loop {
-        match next_trades(&mut ask_trade_reader, &mut prev_ask_info, &mut ask_matcher, "Ask") {
-            Ok(()) => { /* Normal processing */ },
-            Err(e) => {
-                error!("Error reading trade: {}", e);
-                break; // Break out of the loop
-            }
-        }
-        if ask_matcher.number_of_timestamps() == 3 {
-            break;
-        }
+/// Fixed-point scales given as TICK:LOT for every symbol or SYMBOL=TICK:LOT for one.
+fn fixed_points(matches: &ArgMatches) -> Result<FixedPoints> {
+    let mut fixed_points = FixedPoints::default();
+    for spec in matches.get_many::<String>("fixed_point").unwrap_or_default() {
+        let (symbol, sizes) = match spec.split_once('=') {
+            Some((symbol, sizes)) => (Some(symbol), sizes),
+            None => (None, spec.as_str()),
+        };
+        let fixed_point = sizes.parse::<FixedPoint>().map_err(anyhow::Error::msg)?;
+        fixed_points.insert(symbol, fixed_point);
     }
-    loop {
-        match next_trades(&mut bid_trade_reader, &mut prev_bid_info, &mut bid_matcher, "Bid") {
-            Ok(()) => { /* Normal processing */ },
-            Err(e) => {
-                error!("Error reading trade: {}", e);
-                break; // Break out of the loop
-            }
-        }
-        if bid_matcher.number_of_timestamps() == 3 {
-            break;
-        }
+    Ok(fixed_points)
+}
+
+/// Checkpoint settings given by the `replay_args` of a command, if it checkpoints.
+fn checkpointing(matches: &ArgMatches) -> Option<Checkpointing> {
+    let path = matches.get_one::<String>("checkpoint")?;
+    Some(Checkpointing {
+        every: matches.get_one::<u64>("checkpoint_every").copied().unwrap_or(CHECKPOINT_EVERY),
+        resume: matches.get_flag("resume"),
+        ..Checkpointing::new(Path::new(path))
+    })
+}
+
+fn run_evaluate(matches: &ArgMatches) -> Result<()> {
+    let labels = evaluation::load_labels(Path::new(&arg::<String>(matches, "labels")?))?;
+    let replay = replay(matches)?;
+    evaluation::evaluate(&labels, &replay.reports).print();
+    Ok(())
+}
+
+fn run_simulate(matches: &ArgMatches) -> Result<()> {
+    let defaults = SimulationConfig::default();
+    let config = SimulationConfig {
+        symbol: matches.get_one::<String>("symbol").cloned().unwrap_or(defaults.symbol),
+        seed: matches.get_one::<u64>("seed").copied().unwrap_or(defaults.seed),
+        duration: matches.get_one::<u64>("duration").copied().unwrap_or(defaults.duration),
+        batch_interval: matches.get_one::<u64>("batch_interval").copied().unwrap_or(defaults.batch_interval),
+        latency_jitter: matches.get_one::<u64>("latency_jitter").copied().unwrap_or(defaults.latency_jitter),
+        sweep_probability: matches.get_one::<f64>("sweeps").copied().unwrap_or(defaults.sweep_probability),
+        iceberg_probability: matches.get_one::<f64>("icebergs").copied().unwrap_or(defaults.iceberg_probability),
+        cancel_probability: matches.get_one::<f64>("cancellations").copied().unwrap_or(defaults.cancel_probability),
+        ..defaults
+    };
+    let output = arg::<String>(matches, "output")?;
+    let simulation = simulation::simulate(&config);
+    simulation.write(Path::new(&output))?;
+    info!("Simulated {} depth events and {} trades into {}", simulation.depth.len(), simulation.labels.len(), output);
+    Ok(())
+}
+
+fn run_capture(matches: &ArgMatches) -> Result<()> {
+    let config = CaptureConfig {
+        snapshot_interval: matches.get_one::<u64>("snapshot_interval").map(|seconds| Duration::from_secs(*seconds)),
+        snapshot_limit: matches.get_one::<u32>("snapshot_limit").copied().unwrap_or(SNAPSHOT_LIMIT),
+        ..CaptureConfig::new(&arg::<String>(matches, "symbol")?, Path::new(&arg::<String>(matches, "save_folder")?))
+    };
+    let session = tokio::runtime::Runtime::new()?.block_on(capture::capture(&config))?;
+    info!("Capture written to {}", session.display());
+    Ok(())
+}
+
+fn run_book_diff(matches: &ArgMatches) -> Result<()> {
+    let exchange = arg::<Exchange>(matches, "exchange")?;
+    let snapshot = book_state::load(exchange, Path::new(&arg::<String>(matches, "snapshot")?))?;
+    let later = book_state::load(exchange, Path::new(&arg::<String>(matches, "against")?))?;
+    let update_id = matches.get_one::<u64>("at").copied().unwrap_or(later.last_update_id);
+    let depth = engine::open_lines(Path::new(&arg::<String>(matches, "depth")?))?;
+    let book_at = book_state::replay_to(exchange, arg::<CrossRepair>(matches, "cross_repair")?, snapshot, depth, update_id)?;
+    if let Some(output) = matches.get_one::<String>("output") {
+        book_state::save(Path::new(output), &book_at.book, arg::<BookFormat>(matches, "format")?)?;
+        info!("Book at update {} written to {}", book_at.book.last_update_id(), output);
     }
-    
-    orderbook.print_orderbook(5, "Before Run");
-    loop {
+    book_state::diff(&book_at.book, book_at.straddling.as_ref(), &later).print();
+    Ok(())
+}
 
-        let depth_line = depth_reader.next();
+fn run_reconcile(matches: &ArgMatches) -> Result<()> {
+    replay(matches)?.print();
+    Ok(())
+}
 
-        // Check if all files have reached EOF
-        if depth_line.is_none() {
-            break;
-        }
+/// Replays the capture given by the `replay_args` of a command.
+fn replay(matches: &ArgMatches) -> Result<ReplayReport> {
+    // Safely accessing the argument values
+    let snapshot_specs: Vec<&String> = matches.get_many::<String>("snapshot").unwrap_or_default().collect();
+    let exchange = arg::<Exchange>(matches, "exchange")?;
+    let checkpointing = checkpointing(matches);
+    let resume = checkpointing.as_ref().is_some_and(|checkpointing| checkpointing.resume);
+    let options = ReplayOptions { checkpointing, ..replay_options(matches, exchange, resume)? };
+    let depth_file_path = arg::<String>(matches, "depth")?;
 
-        if next_ask_trade {
-            next_ask_trade = false;
-            loop {
-                match next_trades(&mut ask_trade_reader, &mut prev_ask_info, &mut ask_matcher, "Ask") {
-                    Ok(()) => { /* Normal processing */ },
-                    Err(e) => {
-                        error!("Error reading trade: {}", e);
-                        break; // Break out of the loop
-                    }
-                }
-                if ask_matcher.number_of_timestamps() == 3 {
-                    break;
-                }
+    let trades = match matches.get_one::<String>("trades") {
+        Some(trade_file_path) => TradeStreams::Single(engine::open_lines(Path::new(trade_file_path))?),
+        None => {
+            let ask_trade_file_path = arg::<String>(matches, "ask_trade")?;
+            let bid_trade_file_path = arg::<String>(matches, "bid_trade")?;
+            TradeStreams::BySide {
+                ask: engine::open_lines(Path::new(&ask_trade_file_path))?,
+                bid: engine::open_lines(Path::new(&bid_trade_file_path))?,
             }
         }
+    };
+    let streams = Streams {
+        depth: engine::open_lines(Path::new(&depth_file_path))?,
+        trades,
+    };
 
-        if next_bid_trade {
-            next_bid_trade = false;
-            loop {
-                match next_trades(&mut bid_trade_reader, &mut prev_bid_info, &mut bid_matcher, "Bid") {
-                    Ok(()) => { /* Normal processing */ },
-                    Err(e) => {
-                        error!("Error reading trade: {}", e);
-                        break; // Break out of the loop
-                    }
-                }
-                if bid_matcher.number_of_timestamps() == 3 {
-                    break;
-                }
-            }
+    let replay = match snapshot_specs.as_slice() {
+        [path] if !path.contains('=') => {
+            let snapshot = engine::load_snapshot(exchange, Path::new(path))?;
+            let beside = Path::new(path).with_file_name(SNAPSHOTS_DIR);
+            let validation = match matches.get_one::<String>("validate") {
+                Some(dir) => book_state::load_dir(exchange, Path::new(dir))?,
+                None if beside.is_dir() => book_state::load_dir(exchange, &beside)?,
+                None => Vec::new(),
+            };
+            ReplayReport::from(engine::reconcile_validated(&options, "", Some(snapshot), validation, streams)?)
         }
-
-        if let Some(Ok(line)) = depth_line {
-            let depth_update: DepthUpdate = serde_json::from_str(&line)?;
-            debug!("{:?}", depth_update);
-            orderbook.update(depth_update);
-            if orderbook.is_best_ask_updated() {
-                let event_times = ask_matcher.match_trades(&mut orderbook);
-                if event_times.len() != 0 {
-                    next_ask_trade = true;
-                } 
-            }
-            if orderbook.is_best_bid_updated() {
-                let event_times = bid_matcher.match_trades(&mut orderbook);
-                if event_times.len() != 0 {
-                    next_bid_trade = true;
-                } 
+        _ if matches.contains_id("validate") => bail!("--validate is only supported for single-symbol replays"),
+        specs => {
+            let mut snapshots = HashMap::new();
+            for spec in specs {
+                let (symbol, path) = spec.split_once('=')
+                    .with_context(|| format!("Expected SYMBOL=PATH for each snapshot of a multi-symbol capture, got {}", spec))?;
+                snapshots.insert(symbol.to_string(), engine::load_snapshot(exchange, Path::new(path))?);
             }
+            engine::reconcile_symbols(&options, snapshots, streams)?
         }
-    }
-
-    ask_matcher.purge();
-    bid_matcher.purge();
-    orderbook.print_orderbook(5, "After Run");
+    };
 
-    // After all matching and purging are done
-    ask_matcher.clean_trade_results();
-    bid_matcher.clean_trade_results();
-
-    // Then print the cleaned trade results
-    ask_matcher.print_trade_results();
-    bid_matcher.print_trade_results();
-
-    Ok(())
+    Ok(replay)
 }
@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use rust_decimal::Decimal;
+
+use crate::engine::{self, Streams, TradeStreams};
+use crate::error::EngineError;
+use crate::evaluation::{self, Labels};
+use crate::messages::{DepthUpdate, SnapShotUpdate, TradeUpdate};
+use crate::trade_matcher::TradeType;
+
+/// Update id of the simulated snapshot.
+const SNAPSHOT_UPDATE_ID: u64 = 1000;
+/// Share of the actions that are market orders, the others being limit orders and cancellations.
+const MARKET_ORDER_SHARE: f64 = 0.3;
+/// Largest limit order, in lots.
+const MAX_ORDER_LOTS: i64 = 20;
+/// Largest distance behind the best level of a new limit order, in ticks.
+const MAX_OFFSET_TICKS: u64 = 4;
+/// Scale of the prices and quantities written, as Binance sends them.
+const SCALE: u32 = 8;
+
+/// Settings of a simulated session.
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    pub symbol: String,
+    pub seed: u64,
+    /// Time of the snapshot, in milliseconds since the epoch.
+    pub start_time: u64,
+    /// Length of the session in milliseconds.
+    pub duration: u64,
+    /// Milliseconds between depth events; the book changes of an interval are sent as one event.
+    pub batch_interval: u64,
+    /// Largest random delay in milliseconds added to the event time of depth events and trades.
+    pub latency_jitter: u64,
+    /// Mean number of order book actions per second.
+    pub action_rate: f64,
+    /// Share of market orders sized to walk through several levels.
+    pub sweep_probability: f64,
+    /// Share of limit orders showing only part of their quantity, refilled from a hidden reserve.
+    pub iceberg_probability: f64,
+    /// Share of the actions cancelling a resting order.
+    pub cancel_probability: f64,
+    pub start_price: Decimal,
+    pub tick_size: Decimal,
+    pub lot_size: Decimal,
+    /// Levels per side of the starting book.
+    pub levels: usize,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            symbol: String::from("BTCUSDT"),
+            seed: 1,
+            start_time: 1702798595000,
+            duration: 60_000,
+            batch_interval: 100,
+            latency_jitter: 20,
+            action_rate: 20.0,
+            sweep_probability: 0.1,
+            iceberg_probability: 0.1,
+            cancel_probability: 0.2,
+            start_price: Decimal::new(42000, 0),
+            tick_size: Decimal::new(1, 2),
+            lot_size: Decimal::new(1, 5),
+            levels: 20,
+        }
+    }
+}
+
+/// A simulated session in Binance spot format, with the ground truth of its trades.
+pub struct Simulation {
+    pub snapshot: String,
+    pub depth: Vec<String>,
+    /// Trades that hit the asks, in event time order.
+    pub ask_trades: Vec<String>,
+    /// Trades that hit the bids, in event time order.
+    pub bid_trades: Vec<String>,
+    pub labels: Labels,
+}
+
+impl Simulation {
+    /// Writes the session as a capture folder, along with its `labels.txt`.
+    pub fn write(&self, dir: &Path) -> Result<(), EngineError> {
+        fs::create_dir_all(dir).map_err(|e| EngineError::io(dir, e))?;
+        let snapshot = dir.join("snapshot.txt");
+        fs::write(&snapshot, &self.snapshot).map_err(|e| EngineError::io(&snapshot, e))?;
+        for (file, lines) in [("depth.txt", &self.depth), ("ask_trade.txt", &self.ask_trades), ("bid_trade.txt", &self.bid_trades)] {
+            let path = dir.join(file);
+            fs::write(&path, lines.iter().map(|line| format!("{}\n", line)).collect::<String>())
+                .map_err(|e| EngineError::io(&path, e))?;
+        }
+        evaluation::save_labels(&dir.join("labels.txt"), &self.labels)
+    }
+
+    /// Depth and trade streams of the session, to replay it without writing it.
+    pub fn streams(&self) -> Streams {
+        Streams {
+            depth: engine::lines_of(self.depth.clone()),
+            trades: TradeStreams::BySide {
+                ask: engine::lines_of(self.ask_trades.clone()),
+                bid: engine::lines_of(self.bid_trades.clone()),
+            },
+        }
+    }
+}
+
+/// SplitMix64, enough for reproducible order flow.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+
+    /// Uniform in [low, high].
+    fn between(&mut self, low: u64, high: u64) -> u64 {
+        match (high - low).checked_add(1) {
+            Some(span) => low + self.next_u64() % span,
+            // The whole range of u64
+            None => self.next_u64(),
+        }
+    }
+}
+
+/// A resting limit order; an iceberg shows at most `peak` lots and keeps the rest hidden.
+struct Order {
+    id: u64,
+    visible: i64,
+    hidden: i64,
+    peak: i64,
+}
+
+type Side = BTreeMap<i64, VecDeque<Order>>;
+
+fn level_lots(side: &Side, tick: i64) -> i64 {
+    side.get(&tick).map_or(0, |orders| orders.iter().map(|order| order.visible).sum())
+}
+
+/// Price-time priority book driven by random order flow, recording what a Binance
+/// connection would have received.
+struct Market<'a> {
+    config: &'a SimulationConfig,
+    rng: Rng,
+    bids: Side,
+    asks: Side,
+    next_order_id: u64,
+    next_trade_id: u64,
+    last_update_id: u64,
+    /// Visible lots at the start of the batch of every level touched during it.
+    touched_bids: BTreeMap<i64, i64>,
+    touched_asks: BTreeMap<i64, i64>,
+    /// Trades of the batch: id, book side hit and level.
+    batch_trades: Vec<(String, TradeType, i64)>,
+    depth: Vec<String>,
+    ask_trades: Vec<TradeUpdate>,
+    bid_trades: Vec<TradeUpdate>,
+    labels: Labels,
+}
+
+impl<'a> Market<'a> {
+    fn new(config: &'a SimulationConfig) -> Self {
+        let mut market = Self {
+            config,
+            rng: Rng(config.seed),
+            bids: Side::new(),
+            asks: Side::new(),
+            next_order_id: 1,
+            next_trade_id: 1,
+            last_update_id: SNAPSHOT_UPDATE_ID,
+            touched_bids: BTreeMap::new(),
+            touched_asks: BTreeMap::new(),
+            batch_trades: Vec::new(),
+            depth: Vec::new(),
+            ask_trades: Vec::new(),
+            bid_trades: Vec::new(),
+            labels: Labels::new(),
+        };
+        let mid = (config.start_price / config.tick_size).trunc().try_into().unwrap_or(1_000_000i64);
+        for level in 1..=config.levels as i64 {
+            for _ in 0..market.rng.between(1, 3) {
+                market.rest(TradeType::Bid, mid - level, false);
+                market.rest(TradeType::Ask, mid + level, false);
+            }
+        }
+        market.touched_bids.clear();
+        market.touched_asks.clear();
+        market
+    }
+
+    fn side(&mut self, side: TradeType) -> (&mut Side, &mut BTreeMap<i64, i64>) {
+        match side {
+            TradeType::Bid => (&mut self.bids, &mut self.touched_bids),
+            TradeType::Ask => (&mut self.asks, &mut self.touched_asks),
+        }
+    }
+
+    /// Remembers the visible lots of a level before its first change of the batch.
+    fn touch(&mut self, side: TradeType, tick: i64) {
+        let (book, touched) = self.side(side);
+        let lots = level_lots(book, tick);
+        touched.entry(tick).or_insert(lots);
+    }
+
+    fn best(&self, side: TradeType) -> Option<i64> {
+        match side {
+            TradeType::Bid => self.bids.keys().next_back().copied(),
+            TradeType::Ask => self.asks.keys().next().copied(),
+        }
+    }
+
+    fn price(&self, tick: i64) -> Decimal {
+        let mut price = Decimal::from(tick) * self.config.tick_size;
+        price.rescale(SCALE);
+        price
+    }
+
+    fn quantity(&self, lots: i64) -> Decimal {
+        let mut quantity = Decimal::from(lots) * self.config.lot_size;
+        quantity.rescale(SCALE);
+        quantity
+    }
+
+    fn levels(&self, side: TradeType) -> Vec<(Decimal, Decimal)> {
+        let book = match side {
+            TradeType::Bid => &self.bids,
+            TradeType::Ask => &self.asks,
+        };
+        let levels: Vec<_> = book.keys().map(|tick| (self.price(*tick), self.quantity(level_lots(book, *tick)))).collect();
+        match side {
+            TradeType::Bid => levels.into_iter().rev().collect(),
+            TradeType::Ask => levels,
+        }
+    }
+
+    /// Adds a limit order at a level.
+    fn rest(&mut self, side: TradeType, tick: i64, iceberg: bool) {
+        let lots = self.rng.between(1, MAX_ORDER_LOTS as u64) as i64;
+        let hidden = if iceberg { lots * self.rng.between(2, 4) as i64 } else { 0 };
+        let order = Order { id: self.next_order_id, visible: lots, hidden, peak: lots };
+        self.next_order_id += 1;
+        self.touch(side, tick);
+        self.side(side).0.entry(tick).or_default().push_back(order);
+    }
+
+    fn act(&mut self, time: u64) {
+        let draw = self.rng.unit();
+        if draw < self.config.cancel_probability {
+            self.cancel();
+        } else if draw < self.config.cancel_probability + MARKET_ORDER_SHARE {
+            self.market_order(time);
+        } else {
+            self.limit_order();
+        }
+    }
+
+    /// Places a limit order behind the best level of its side, or inside the spread when it is
+    /// wider than a tick.
+    fn limit_order(&mut self) {
+        let side = if self.rng.chance(0.5) { TradeType::Bid } else { TradeType::Ask };
+        let (own, other, towards) = match side {
+            TradeType::Bid => (self.best(TradeType::Bid), self.best(TradeType::Ask), 1),
+            TradeType::Ask => (self.best(TradeType::Ask), self.best(TradeType::Bid), -1),
+        };
+        let tick = match (own, other) {
+            (Some(own), Some(other)) if (other - own) * towards > 1 && self.rng.chance(0.5) => own + towards,
+            (Some(own), _) => own - towards * self.rng.between(0, MAX_OFFSET_TICKS) as i64,
+            (None, Some(other)) => other - towards,
+            (None, None) => return,
+        };
+        let iceberg = self.rng.chance(self.config.iceberg_probability);
+        self.rest(side, tick, iceberg);
+    }
+
+    /// Cancels a random resting order, hidden reserve included.
+    fn cancel(&mut self) {
+        let side = if self.rng.chance(0.5) { TradeType::Bid } else { TradeType::Ask };
+        let levels = self.side(side).0.len();
+        if levels <= 1 {
+            return;
+        }
+        let level = self.rng.between(0, levels as u64 - 1) as usize;
+        let Some(tick) = self.side(side).0.keys().nth(level).copied() else { return };
+        self.touch(side, tick);
+        let resting = self.side(side).0[&tick].len();
+        let index = self.rng.between(0, resting as u64 - 1) as usize;
+        let (book, _) = self.side(side);
+        let orders = book.get_mut(&tick).expect("level exists");
+        orders.remove(index);
+        if orders.is_empty() {
+            book.remove(&tick);
+        }
+    }
+
+    /// Sends a market order taking part of the best level, or walking through a few levels
+    /// when it is a sweep; every maker order it meets makes a trade.
+    fn market_order(&mut self, time: u64) {
+        let taker_buys = self.rng.chance(0.5);
+        let hit = if taker_buys { TradeType::Ask } else { TradeType::Bid };
+        let ticks: Vec<i64> = match hit {
+            TradeType::Ask => self.asks.keys().take(3).copied().collect(),
+            TradeType::Bid => self.bids.keys().rev().take(3).copied().collect(),
+        };
+        // Leave a level on the side so that the book stays two-sided
+        if ticks.len() < 2 {
+            return;
+        }
+        let book = match hit {
+            TradeType::Ask => &self.asks,
+            TradeType::Bid => &self.bids,
+        };
+        let best_lots = level_lots(book, ticks[0]);
+        // Sweeping needs a level to walk through besides the one left on the side
+        let mut lots = if self.rng.chance(self.config.sweep_probability) && ticks.len() > 2 {
+            let walked = self.rng.between(2, ticks.len() as u64 - 1) as usize;
+            let full: i64 = ticks[..walked - 1].iter().map(|tick| level_lots(book, *tick)).sum();
+            full + self.rng.between(1, level_lots(book, ticks[walked - 1]) as u64) as i64
+        } else {
+            self.rng.between(1, best_lots as u64) as i64
+        };
+        let taker = self.next_order_id;
+        self.next_order_id += 1;
+        while lots > 0 {
+            let Some(tick) = self.best(hit) else { break };
+            self.touch(hit, tick);
+            let (book, _) = self.side(hit);
+            let orders = book.get_mut(&tick).expect("best level exists");
+            let maker = orders.front_mut().expect("levels hold orders");
+            let fill = lots.min(maker.visible);
+            let maker_id = maker.id;
+            maker.visible -= fill;
+            if maker.visible == 0 {
+                let mut maker = orders.pop_front().expect("levels hold orders");
+                // An iceberg shows its next slice and loses its priority
+                if maker.hidden > 0 {
+                    maker.visible = maker.peak.min(maker.hidden);
+                    maker.hidden -= maker.visible;
+                    orders.push_back(maker);
+                }
+            }
+            if orders.is_empty() {
+                book.remove(&tick);
+            }
+            lots -= fill;
+            self.trade(time, hit, tick, fill, taker, maker_id);
+        }
+    }
+
+    fn trade(&mut self, time: u64, hit: TradeType, tick: i64, lots: i64, taker: u64, maker: u64) {
+        let trade_id = self.next_trade_id;
+        self.next_trade_id += 1;
+        let (buyer_order_id, seller_order_id) = match hit {
+            TradeType::Ask => (taker, maker),
+            TradeType::Bid => (maker, taker),
+        };
+        let trade = TradeUpdate {
+            event_type: String::from("trade"),
+            event_time: time.saturating_add(self.rng.between(0, self.config.latency_jitter)),
+            symbol: self.config.symbol.clone(),
+            trade_id: trade_id.to_string(),
+            price: self.price(tick),
+            quantity: self.quantity(lots),
+            buyer_order_id,
+            seller_order_id,
+            trade_time: time,
+            is_market_maker: hit == TradeType::Bid,
+            ..Default::default()
+        };
+        self.batch_trades.push((trade.trade_id.clone(), hit, tick));
+        match hit {
+            TradeType::Ask => self.ask_trades.push(trade),
+            TradeType::Bid => self.bid_trades.push(trade),
+        }
+    }
+
+    /// Sends the levels whose visible quantity changed during the batch as one depth event,
+    /// best levels first, and labels the batch's trades with it when it shows their level.
+    fn flush(&mut self, time: u64) {
+        let touched_bids = std::mem::take(&mut self.touched_bids);
+        let touched_asks = std::mem::take(&mut self.touched_asks);
+        let changed = |book: &Side, touched: BTreeMap<i64, i64>| -> Vec<i64> {
+            touched.into_iter()
+                .filter(|(tick, lots)| level_lots(book, *tick) != *lots)
+                .map(|(tick, _)| tick)
+                .collect()
+        };
+        let mut bid_ticks = changed(&self.bids, touched_bids);
+        bid_ticks.reverse();
+        let ask_ticks = changed(&self.asks, touched_asks);
+        let changes = (bid_ticks.len() + ask_ticks.len()) as u64;
+        let update_id = (changes > 0).then_some(self.last_update_id + changes);
+        for (trade_id, hit, tick) in std::mem::take(&mut self.batch_trades) {
+            let shown = match hit {
+                TradeType::Bid => bid_ticks.contains(&tick),
+                TradeType::Ask => ask_ticks.contains(&tick),
+            };
+            self.labels.insert((self.config.symbol.to_uppercase(), trade_id), update_id.filter(|_| shown));
+        }
+        let Some(update_id) = update_id else { return };
+        let update = DepthUpdate {
+            event_type: String::from("depthUpdate"),
+            event_time: time.saturating_add(self.rng.between(0, self.config.latency_jitter)),
+            symbol: self.config.symbol.clone(),
+            first_update_id_in_event: self.last_update_id + 1,
+            final_update_id_in_event: update_id,
+            bids_to_update: bid_ticks.iter().map(|tick| (self.price(*tick), self.quantity(level_lots(&self.bids, *tick)))).collect(),
+            asks_to_update: ask_ticks.iter().map(|tick| (self.price(*tick), self.quantity(level_lots(&self.asks, *tick)))).collect(),
+            ..Default::default()
+        };
+        self.last_update_id = update_id;
+        self.depth.push(serde_json::to_string(&update).expect("depth updates serialise"));
+    }
+}
+
+/// Runs a session of random order flow against a simple limit order book, one millisecond
+/// at a time, and records it as a Binance spot connection would have: a snapshot at the start,
+/// depth events batching the book changes of every interval, and raw trades split by the side
+/// they hit. Trades are labelled with the depth event showing the change of their level, or
+/// none when the batch left it unchanged (e.g. an iceberg refilling to the same size).
+pub fn simulate(config: &SimulationConfig) -> Simulation {
+    let mut market = Market::new(config);
+    let snapshot = SnapShotUpdate {
+        last_update_id: SNAPSHOT_UPDATE_ID,
+        bids: market.levels(TradeType::Bid),
+        asks: market.levels(TradeType::Ask),
+    };
+    let action_probability = config.action_rate / 1000.0;
+    let interval = config.batch_interval.max(1);
+    for elapsed in 1..=config.duration {
+        let time = config.start_time + elapsed;
+        if market.rng.chance(action_probability) {
+            market.act(time);
+        }
+        if elapsed % interval == 0 || elapsed == config.duration {
+            market.flush(time);
+        }
+    }
+    let to_lines = |mut trades: Vec<TradeUpdate>| -> Vec<String> {
+        trades.sort_by_key(|trade| trade.event_time);
+        trades.iter().map(|trade| serde_json::to_string(trade).expect("trades serialise")).collect()
+    };
+    Simulation {
+        snapshot: serde_json::to_string(&snapshot).expect("snapshots serialise"),
+        depth: market.depth,
+        ask_trades: to_lines(market.ask_trades),
+        bid_trades: to_lines(market.bid_trades),
+        labels: market.labels,
+    }
+}
@@ -1,36 +1,200 @@
-use std::collections::{BTreeSet, VecDeque};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
 
+use crate::assignment::min_cost_assignment;
+use crate::book_store::OrderBookStore;
+use crate::error::EngineError;
+use crate::fixed_point::Representation;
 use crate::messages::TradeUpdate;
-use crate::orderbook::OrderBook;
+use crate::orderbook::{DepthTime, LevelDelta, OrderBook, TradeFill};
 use log::{info, debug};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TradeType {
     Bid,
     Ask,
 }
 
+/// Event time recorded for a trade that overtook the matching window without a match.
+pub const DROPPED: u64 = 1;
+/// Event time recorded for a trade still queued when the depth stream ended.
+pub const PURGED: u64 = 2;
+
+/// Matching output: (trade id, trade event time, depth event time or `DROPPED`/`PURGED`).
+pub type TradeResults = BTreeSet<(String, u64, u64)>;
+
+/// Default milliseconds a depth event may lag a trade and still be matched with it.
+pub const MATCH_WINDOW: u64 = 100;
+
+/// How much more a scored match's runner-up candidate must cost for the match to be confident.
+const CONFIDENCE_MARGIN: f64 = 0.5;
+
+/// Weights of a scored candidate's lag (as a fraction of the window), quantity residual
+/// (as a fraction of the level delta) and level position (0 for the best level).
+const LAG_WEIGHT: f64 = 1.0;
+const QUANTITY_WEIGHT: f64 = 1.0;
+const LEVEL_WEIGHT: f64 = 0.25;
+
+/// How queued trades are paired with the level deltas of depth events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Matching {
+    /// Each trade takes the first level delta of equal price and quantity, in queue order.
+    #[default]
+    First,
+    /// Every level delta at a trade's price holding at least its quantity within the window is
+    /// a scored candidate; once a trade's window has closed its match is settled by a
+    /// minimum-cost one-to-one assignment of the queued trades to the candidates.
+    Scored,
+}
+
+impl FromStr for Matching {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "first" => Ok(Matching::First),
+            "scored" => Ok(Matching::Scored),
+            other => Err(format!("Unknown matching: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Matching {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Matching::First => "first",
+            Matching::Scored => "scored",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Cost of a scored match, lower being better, with the cost of the trade's next best candidate.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MatchScore {
+    pub score: f64,
+    pub runner_up: Option<f64>,
+}
+
+/// Scores of scored matches by trade id.
+pub type MatchScores = BTreeMap<String, MatchScore>;
+
+/// Final update id of the depth event each matched trade was matched with, by trade id.
+pub type MatchedUpdates = BTreeMap<String, u64>;
+
+/// A level delta some trade may be matched with.
+#[derive(Clone, Serialize, Deserialize)]
+struct Candidate {
+    delta: LevelDelta,
+    /// Position of the level in its depth event, 0 for the best level.
+    position: usize,
+}
+
+/// A trade waiting to be matched, with its price and quantities as the book holds them.
+#[derive(Clone, Serialize, Deserialize)]
+struct QueuedTrade {
+    trade: TradeUpdate,
+    fill: TradeFill,
+}
+
+/// Clock of the depth stream: transaction times when it sends them, event times otherwise.
+fn depth_clock(depth_time: DepthTime) -> u64 {
+    depth_time.transaction_time.unwrap_or(depth_time.event_time)
+}
+
+/// Cost of matching a trade with a candidate, infinite when they cannot be matched.
+/// A sweep is keyed by the level it took first.
+fn candidate_cost(queued: &QueuedTrade, candidate: &Candidate, window: u64) -> f64 {
+    let (price, quantity) = queued.fill.sweep.first().copied().unwrap_or((queued.fill.price, queued.fill.quantity));
+    let volume = candidate.delta.volume();
+    if candidate.delta.price() != price || volume < quantity || volume.signum() != Ordering::Greater {
+        return f64::INFINITY;
+    }
+    let (depth_time, trade_time) = candidate.delta.depth_time().against(&queued.trade);
+    let lag = (depth_time as f64 - trade_time as f64).abs();
+    if lag > window as f64 {
+        return f64::INFINITY;
+    }
+    let residual = (volume - quantity).ratio(volume).unwrap_or(1.0);
+    LAG_WEIGHT * lag / window as f64 + QUANTITY_WEIGHT * residual + LEVEL_WEIGHT * candidate.position as f64
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TradeMatcher {
     trade_type: TradeType,
-    trade_queue: VecDeque<TradeUpdate>,
-    trade_results: BTreeSet<(String, u64, u64)>,
+    matching: Matching,
+    representation: Representation,
+    trade_queue: VecDeque<QueuedTrade>,
+    candidates: Vec<Candidate>,
+    trade_results: TradeResults,
+    scores: MatchScores,
+    updates: MatchedUpdates,
+    window: u64,
+    /// Lags of the confident matches made since the last call to `take_confident_lags`.
+    confident_lags: Vec<i64>,
 }
 
 impl TradeMatcher {
     pub fn new(trade_type: TradeType) -> Self {
+        Self::with_matching(trade_type, Matching::First)
+    }
+
+    pub fn with_matching(trade_type: TradeType, matching: Matching) -> Self {
         Self {
             trade_type,
+            matching,
+            representation: Representation::Decimal,
             trade_queue: VecDeque::new(),
+            candidates: Vec::new(),
             trade_results: BTreeSet::new(),
+            scores: BTreeMap::new(),
+            updates: BTreeMap::new(),
+            window: MATCH_WINDOW,
+            confident_lags: Vec::new(),
         }
     }
 
-    pub fn add_trade(&mut self, trade: TradeUpdate) {
+    pub fn trade_type(&self) -> TradeType {
+        self.trade_type
+    }
+
+    pub fn matching(&self) -> Matching {
+        self.matching
+    }
+
+    /// Milliseconds a depth event may lag a trade and still be matched with it.
+    pub fn window(&self) -> u64 {
+        self.window
+    }
+
+    pub fn set_window(&mut self, window: u64) {
+        self.window = window;
+    }
+
+    /// Holds the prices and quantities of the trades added from now on in `representation`,
+    /// which must be the book's.
+    pub fn set_representation(&mut self, representation: Representation) {
+        self.representation = representation;
+    }
+
+    /// Lags in milliseconds of the confident matches made since the last call. Exact first
+    /// matches are all confident; scored ones are when no other candidate came close.
+    pub fn take_confident_lags(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.confident_lags)
+    }
+
+    /// Queues a trade, failing when it is off the grid of a fixed-point representation.
+    pub fn add_trade(&mut self, trade: TradeUpdate) -> Result<(), EngineError> {
         debug!("{:?} - Added Trade ID: {}", self.trade_type, trade.trade_id);
-        self.trade_queue.push_back(trade);
+        let fill = TradeFill::of(&trade, self.representation)?;
+        self.trade_queue.push_back(QueuedTrade { trade, fill });
+        Ok(())
     }
 
-    pub fn match_trades(&mut self, orderbook: &mut OrderBook) -> Vec::<u64> {
+    pub fn match_trades<S: OrderBookStore>(&mut self, orderbook: &mut OrderBook<S>) -> Vec::<u64> {
         let mut indices_to_remove = Vec::new();
         let mut event_times = Vec::<u64>::new();
         let mut trades_to_insert = Vec::new();
@@ -38,22 +202,29 @@ impl TradeMatcher {
         debug!("{:?} - *********************************************", self.trade_type);
         debug!("{:?} - Reconciliation attempt on {} trades", self.trade_type, self.trade_queue.len());
     
-        for (index, trade) in self.trade_queue.iter().enumerate() {
+        for (index, QueuedTrade { trade, fill }) in self.trade_queue.iter().enumerate() {
             debug!("{:?} - Attempting Trade ID: {} - price: {} - quantity: {} - timestamp {}", self.trade_type, trade.trade_id, trade.price, trade.quantity, trade.event_time);
             let te = match self.trade_type {
-                TradeType::Bid => orderbook.match_and_process_trade(trade, TradeType::Bid),
-                TradeType::Ask => orderbook.match_and_process_trade(trade, TradeType::Ask),
+                TradeType::Bid => orderbook.match_fill(trade, fill, TradeType::Bid, self.window),
+                TradeType::Ask => orderbook.match_fill(trade, fill, TradeType::Ask, self.window),
             };
             
-            if te == 1 {
+            if te == DROPPED {
                 info!("{:?} - Dropped Trade ID: {}", self.trade_type, trade.trade_id);
                 indices_to_remove.push(index);
-                trades_to_insert.push((trade.trade_id.clone(), trade.event_time, te));
-            } else if te > 1 {
+                trades_to_insert.push((trade.raw_trade_ids(), trade.event_time, te));
+            } else if te > DROPPED {
                 info!("{:?} - Matched Trade ID: {}, Event Time: {}", self.trade_type, trade.trade_id, te);
                 indices_to_remove.push(index);
                 event_times.push(te);
-                trades_to_insert.push((trade.trade_id.clone(), trade.event_time, te));
+                self.confident_lags.push(te as i64 - trade.event_time as i64);
+                // The level deltas all come from the last depth event
+                if let Some(delta) = orderbook.best_deltas(self.trade_type).first() {
+                    for trade_id in trade.raw_trade_ids() {
+                        self.updates.insert(trade_id, delta.update_id());
+                    }
+                }
+                trades_to_insert.push((trade.raw_trade_ids(), trade.event_time, te));
             }
         }
         // Remove items in reverse order
@@ -61,16 +232,106 @@ impl TradeMatcher {
             self.trade_queue.remove(index);
         }
         // Now that we are no longer borrowing `self.trade_queue`, insert the trades
-        for (trade_id, trade_event_time, event_time) in trades_to_insert {
-            self.insert_trade_ids(&trade_id,trade_event_time, event_time);
+        for (trade_ids, trade_event_time, event_time) in trades_to_insert {
+            self.insert_trade_ids(trade_ids, trade_event_time, event_time);
         }        
         event_times
     }
     
+    /// Collects the level deltas of the last depth event as candidates, then settles the trades
+    /// whose window the event closed. Returns the depth event times of the new matches.
+    pub fn match_scored<S: OrderBookStore>(&mut self, orderbook: &OrderBook<S>, now: DepthTime) -> Vec<u64> {
+        for (position, delta) in orderbook.best_deltas(self.trade_type).iter().enumerate() {
+            if delta.volume().signum() == Ordering::Greater {
+                self.candidates.push(Candidate { delta: delta.clone(), position });
+            }
+        }
+        let closed: Vec<bool> = self.trade_queue.iter()
+            .map(|queued| {
+                let (depth_time, trade_time) = now.against(&queued.trade);
+                depth_time > trade_time.saturating_add(self.window)
+            })
+            .collect();
+        let event_times = if closed.contains(&true) { self.settle(&closed, DROPPED) } else { Vec::new() };
+        // Candidates this old are out of the window of any trade still to come
+        let horizon = depth_clock(now).saturating_sub(self.window.saturating_mul(2));
+        self.candidates.retain(|candidate| depth_clock(candidate.delta.depth_time()) >= horizon);
+        event_times
+    }
+
+    /// Assigns the queued trades to the candidates at minimum total cost and records the
+    /// outcome of the `closed` ones, recording `unmatched` for those left without a candidate.
+    /// Open trades take part in the assignment so that closed ones do not take their better
+    /// candidates, but keep waiting.
+    fn settle(&mut self, closed: &[bool], unmatched: u64) -> Vec<u64> {
+        let costs: Vec<Vec<f64>> = self.trade_queue.iter()
+            .map(|trade| self.candidates.iter().map(|candidate| candidate_cost(trade, candidate, self.window)).collect())
+            .collect();
+        let assignment = min_cost_assignment(&costs);
+        let mut used = vec![false; self.candidates.len()];
+        let mut event_times = Vec::new();
+        let mut settled = Vec::new();
+        for (index, QueuedTrade { trade, .. }) in self.trade_queue.iter().enumerate() {
+            if !closed[index] {
+                continue;
+            }
+            match assignment[index] {
+                Some(column) => {
+                    used[column] = true;
+                    let event_time = self.candidates[column].delta.depth_time().event_time;
+                    let update_id = self.candidates[column].delta.update_id();
+                    let runner_up = costs[index].iter().enumerate()
+                        .filter(|(other, cost)| *other != column && cost.is_finite())
+                        .map(|(_, cost)| *cost)
+                        .min_by(f64::total_cmp);
+                    let score = MatchScore { score: costs[index][column], runner_up };
+                    if runner_up.is_none_or(|runner_up| runner_up - score.score >= CONFIDENCE_MARGIN) {
+                        self.confident_lags.push(event_time as i64 - trade.event_time as i64);
+                    }
+                    info!("{:?} - Matched Trade ID: {}, Event Time: {}, Score: {:.3}", self.trade_type, trade.trade_id, event_time, score.score);
+                    event_times.push(event_time);
+                    settled.push((trade.raw_trade_ids(), trade.event_time, event_time, Some((score, update_id))));
+                }
+                None => {
+                    match unmatched {
+                        DROPPED => info!("{:?} - Dropped Trade ID: {}", self.trade_type, trade.trade_id),
+                        _ => info!("{:?} - Purged Trade ID: {}", self.trade_type, trade.trade_id),
+                    }
+                    settled.push((trade.raw_trade_ids(), trade.event_time, unmatched, None));
+                }
+            }
+        }
+        let mut index = 0;
+        self.trade_queue.retain(|_| {
+            index += 1;
+            !closed[index - 1]
+        });
+        let mut index = 0;
+        self.candidates.retain(|_| {
+            index += 1;
+            !used[index - 1]
+        });
+        for (trade_ids, trade_event_time, event_time, score) in settled {
+            if let Some((score, update_id)) = score {
+                for trade_id in &trade_ids {
+                    self.scores.insert(trade_id.clone(), score);
+                    self.updates.insert(trade_id.clone(), update_id);
+                }
+            }
+            self.insert_trade_ids(trade_ids, trade_event_time, event_time);
+        }
+        event_times
+    }
+
     pub fn purge(&mut self) {
-        while let Some(trade) = self.trade_queue.pop_front() {
+        if self.matching == Matching::Scored && !self.trade_queue.is_empty() {
+            let closed = vec![true; self.trade_queue.len()];
+            self.settle(&closed, PURGED);
+            return;
+        }
+        while let Some(QueuedTrade { trade, .. }) = self.trade_queue.pop_front() {
             info!("{:?} - Purged Trade ID: {}", self.trade_type, trade.trade_id);
-            self.insert_trade_ids(&trade.trade_id, trade.event_time, 2);
+            self.insert_trade_ids(trade.raw_trade_ids(), trade.event_time, PURGED);
         }
     }
 
@@ -79,7 +340,7 @@ impl TradeMatcher {
         let mut old_timestamp: u64 = 0;
         let mut index = 0;
         while index < self.trade_queue.len() {
-            let trade = &self.trade_queue[index];
+            let trade = &self.trade_queue[index].trade;
             let timestamp = trade.event_time;
             if old_timestamp != timestamp {
                 nb_timestamps += 1;
@@ -87,7 +348,7 @@ impl TradeMatcher {
             old_timestamp = timestamp;
             index += 1;
         }
-        return nb_timestamps;
+        nb_timestamps
     }
 
     // Method to clean up trade results
@@ -104,7 +365,7 @@ impl TradeMatcher {
         // Second pass to collect all eligible entries
         for (trade_id, trade_time, event_time) in &self.trade_results {
             let count = trade_id_counts.get(trade_id).cloned().unwrap_or(0);
-            if count == 1 || *event_time != 1 && *event_time != 2 {
+            if count == 1 || *event_time != DROPPED && *event_time != PURGED {
                 // Collect entries of trade_id with various event times
                 trade_id_event_times.entry(trade_id.clone()).or_insert_with(Vec::new).push((*trade_time, *event_time));
             }
@@ -119,23 +380,32 @@ impl TradeMatcher {
 
         // Replace the old trade_results with the cleaned and ordered results
         self.trade_results = cleaned_results;
+        let matched: BTreeSet<&String> = self.trade_results.iter()
+            .filter(|(_, _, event_time)| *event_time > PURGED)
+            .map(|(trade_id, _, _)| trade_id)
+            .collect();
+        self.scores.retain(|trade_id, _| matched.contains(trade_id));
+        self.updates.retain(|trade_id, _| matched.contains(trade_id));
     }
 
-    pub fn print_trade_results(&self) {
-        let mut results = format!("{:?} - Matching output\n", self.trade_type);
-        results.push_str(&format!("\tTrade ID\tTrade Time\tEvent Time\n"));
-        for (trade_id, trade_event_time, event_time) in &self.trade_results {
-            results.push_str(&format!("\t{}\t{}\t{}\n", trade_id, trade_event_time, event_time));
-        }
-        // Log the concatenated result
-        info!("{}", results.trim_end()); // trim_end to remove the last newline character
+    pub fn trade_results(&self) -> &TradeResults {
+        &self.trade_results
+    }
+
+    /// Scores of the matched trades, when matching is scored.
+    pub fn scores(&self) -> &MatchScores {
+        &self.scores
+    }
+
+    /// Depth update ids of the matched trades.
+    pub fn updates(&self) -> &MatchedUpdates {
+        &self.updates
     }
 
-    fn insert_trade_ids(&mut self, trade_id: &str, trade_event_time: u64, event_time: u64) {
-        let ids: Vec<&str> = trade_id.split('-').collect();
-        for id in ids {
-            // Use trade_event_time for all the split trade IDs
-            self.trade_results.insert((id.to_string(), trade_event_time, event_time));
+    fn insert_trade_ids(&mut self, trade_ids: Vec<String>, trade_event_time: u64, event_time: u64) {
+        for id in trade_ids {
+            // Use trade_event_time for all the raw trade IDs
+            self.trade_results.insert((id, trade_event_time, event_time));
         }
     }
 
@@ -0,0 +1,35 @@
+mod common;
+
+use shougoutaku::engine::{Aggregation, ReplayOptions};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::messages::TradeUpdate;
+
+use common::{fixtures, reconcile};
+
+#[test]
+fn aggressor_is_the_taker_side() {
+    let buy = TradeUpdate { buyer_order_id: 900, seller_order_id: 31, is_market_maker: false, ..Default::default() };
+    assert_eq!(buy.aggressor_order_id(), 900);
+    let sell = TradeUpdate { buyer_order_id: 900, seller_order_id: 31, is_market_maker: true, ..Default::default() };
+    assert_eq!(sell.aggressor_order_id(), 31);
+}
+
+#[test]
+fn matches_an_aggressor_order_walking_several_levels() {
+    let options = ReplayOptions { aggregation: Aggregation::Aggressor, ..ReplayOptions::new(Exchange::Binance) };
+    let report = reconcile(&fixtures("aggressor"), &options).unwrap();
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    // Order 900 took all of 42000.10 and part of 42000.20 in one depth event
+    assert_eq!(ask, vec![
+        (String::from("601"), 1702798596100, 1702798596120),
+        (String::from("602"), 1702798596100, 1702798596120),
+        (String::from("603"), 1702798596100, 1702798596120),
+        (String::from("604"), 1702798596250, 1702798596260),
+    ]);
+}
+
+#[test]
+fn parses_aggressor_aggregation() {
+    assert_eq!("aggressor".parse::<Aggregation>(), Ok(Aggregation::Aggressor));
+    assert_eq!(Aggregation::Aggressor.to_string(), "aggressor");
+}
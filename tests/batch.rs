@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use shougoutaku::batch::{self, SessionSummary};
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::exchange::Exchange;
+
+use common::{CAPTURE_FILES, fixture, fixtures, streams};
+
+/// Copies the capture files of a fixture folder into a session folder.
+fn session(root: &Path, session: &str, folder: &str, files: &[&str]) -> PathBuf {
+    let path = root.join(session);
+    fs::create_dir_all(&path).unwrap();
+    for file in files {
+        fs::copy(fixture(folder, file), path.join(file)).unwrap();
+    }
+    path
+}
+
+fn summary(sessions: &[PathBuf], jobs: usize) -> String {
+    let summaries = batch::run_batch(sessions, &ReplayOptions::new(Exchange::Binance), jobs);
+    let mut out = Vec::new();
+    batch::write_summary(&mut out, &summaries).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn summarises_every_session_in_a_stable_order() {
+    let root = std::env::temp_dir().join(format!("shougoutaku-batch-{}", std::process::id()));
+    let files = [&["snapshot.txt"][..], &CAPTURE_FILES].concat();
+    let scored = session(&root, "btcusdt/1702798595534677", "scored", &files);
+    let aggressor = session(&root, "btcusdt/1702798600000000", "aggressor", &files);
+    let missing = session(&root, "ethusdt/1702798595534677", "scored", &["snapshot.txt", "ask_trade.txt"]);
+    // Folders without a snapshot hold no session
+    fs::create_dir_all(root.join("ethusdt/notes")).unwrap();
+
+    let sessions = batch::discover_sessions(&root).unwrap();
+    let summaries = [1, 4].map(|jobs| summary(&sessions, jobs));
+    fs::remove_dir_all(&root).unwrap();
+    assert_eq!(sessions, vec![scored.clone(), aggressor.clone(), missing.clone()]);
+    // More jobs than one reconcile the sessions in any order but write them in the input order
+    assert_eq!(summaries[0], summaries[1]);
+
+    let rows: Vec<Vec<&str>> = summaries[0].lines().map(|line| line.split('\t').collect()).collect();
+    assert_eq!(rows.len(), 5);
+    assert_eq!(rows[0][..3], ["Session", "Trades", "Match rate"]);
+    assert_eq!(rows[1][0], scored.display().to_string());
+    assert_eq!(rows[2][0], aggressor.display().to_string());
+    assert_eq!(rows[3], [missing.display().to_string().as_str(), "FAILED: missing depth.txt"]);
+    assert_eq!(rows[4][0], "TOTAL (3 sessions, 1 failed)");
+    // Totals add up the sessions that ran
+    let count = |row: &[&str], column: usize| row[column].parse::<usize>().unwrap();
+    for column in 1..8 {
+        if column != 2 {
+            assert_eq!(count(&rows[4], column), count(&rows[1], column) + count(&rows[2], column), "{}", rows[0][column]);
+        }
+    }
+    assert!(count(&rows[4], 3) > 0);
+}
+
+#[test]
+fn discovers_nothing_under_an_empty_root() {
+    let root = std::env::temp_dir().join(format!("shougoutaku-batch-empty-{}", std::process::id()));
+    fs::create_dir_all(root.join("btcusdt")).unwrap();
+    let sessions = batch::discover_sessions(&root).unwrap();
+    let summary = summary(&sessions, 2);
+    fs::remove_dir_all(&root).unwrap();
+    assert!(sessions.is_empty());
+    assert_eq!(summary.lines().last().unwrap().split('\t').next(), Some("TOTAL (0 sessions, 0 failed)"));
+}
+
+#[test]
+fn totals_count_the_malformed_lines_routed_to_no_symbol() {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("malformed", "snapshot.txt")).unwrap();
+    let streams = streams(&fixtures("malformed"), CAPTURE_FILES);
+    let replay = engine::reconcile_symbols(&ReplayOptions::new(Exchange::Binance), [(String::from("BTCUSDT"), snapshot)].into(), streams).unwrap();
+    let summaries = [SessionSummary { session: fixture("malformed", ""), outcome: Ok(replay) }];
+    let mut out = Vec::new();
+    batch::write_summary(&mut out, &summaries).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let malformed: Vec<&str> = out.lines().skip(1).map(|line| line.split('\t').nth(7).unwrap()).collect();
+    // The truncated depth line is left out of the session's row but not of the total
+    assert_eq!(malformed, ["1", "2"]);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::exchange::{BookEvent, Exchange};
+
+use common::{CAPTURE_FILES, fixture, fixtures, streams};
+
+#[test]
+fn parses_futures_depth_fields() {
+    let line = std::fs::read_to_string(fixture("binance_futures", "depth.txt")).unwrap();
+    let BookEvent::Depth(update) = Exchange::Binance.adapter().parse_book(line.lines().next().unwrap()).unwrap().remove(0) else { panic!("expected a depth update") };
+    assert_eq!(update.previous_final_update_id, Some(489));
+    assert_eq!(update.transaction_time, Some(1702798595895));
+}
+
+#[test]
+fn parses_agg_trades_into_the_trade_pipeline() {
+    let line = std::fs::read_to_string(fixture("binance_futures", "ask_trade.txt")).unwrap();
+    let trade = Exchange::Binance.adapter().parse_trades(line.trim_end()).unwrap().remove(0);
+    assert_eq!(trade.trade_id, "9001");
+    assert_eq!(trade.trade_time, 1702798595995);
+    assert!(!trade.is_market_maker);
+}
+
+#[test]
+fn syncs_on_previous_update_id_and_matches_on_transaction_time() {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("binance_futures", "snapshot.txt")).unwrap();
+    let report = engine::reconcile(&ReplayOptions::new(Exchange::Binance), "", Some(snapshot), streams(&fixtures("binance_futures"), CAPTURE_FILES)).unwrap();
+    // pu = 508 does not follow u = 506
+    assert_eq!(report.sequence_gaps, 1);
+    // The depth event times are more than 100 ms after the trades, their transaction times are not
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    assert_eq!(ask, vec![
+        (String::from("70001"), 1702798596000, 1702798596200),
+        (String::from("70002"), 1702798596000, 1702798596200),
+    ]);
+    let bid: Vec<_> = report.bid_results.into_iter().collect();
+    assert_eq!(bid, vec![(String::from("70003"), 1702798596340, 1702798596400)]);
+}
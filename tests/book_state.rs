@@ -0,0 +1,104 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use serde_json::Value;
+
+use shougoutaku::book_state::{self, BookAt, BookFormat, LevelMismatch};
+use shougoutaku::engine;
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::orderbook::{CrossRepair, OrderBook};
+use shougoutaku::trade_matcher::TradeType;
+
+use common::{dec, fixture, session};
+
+fn replay_to(snapshot: &Path, depth: &Path, update_id: u64) -> BookAt {
+    let snapshot = book_state::load(Exchange::Binance, snapshot).unwrap();
+    book_state::replay_to(Exchange::Binance, CrossRepair::None, snapshot, engine::open_lines(depth).unwrap(), update_id).unwrap()
+}
+
+fn mismatch(side: TradeType, price: &str, book: Option<&str>, snapshot: Option<&str>, in_flight: bool) -> LevelMismatch {
+    LevelMismatch { side, price: dec(price), book: book.map(dec), snapshot: snapshot.map(dec), in_flight }
+}
+
+#[test]
+fn books_serialise_to_the_binance_snapshot_they_were_loaded_from() {
+    let raw = fs::read_to_string(session("snapshot.txt")).unwrap();
+    let book = OrderBook::from_snapshot(book_state::load(Exchange::Binance, &session("snapshot.txt")).unwrap());
+    let json = book_state::encode(&book.to_snapshot(), BookFormat::Json);
+    assert_eq!(serde_json::from_slice::<Value>(&json).unwrap(), serde_json::from_str::<Value>(&raw).unwrap());
+}
+
+#[test]
+fn binary_books_round_trip_exactly_and_are_smaller() {
+    let snapshot = book_state::load(Exchange::Binance, &session("snapshot.txt")).unwrap();
+    let binary = book_state::to_binary(&snapshot);
+    // Debug output keeps each dec's scale, so trailing zeros must survive too
+    assert_eq!(format!("{:?}", book_state::from_binary(&binary).unwrap()), format!("{:?}", snapshot));
+    assert!(binary.len() < book_state::encode(&snapshot, BookFormat::Json).len() / 2);
+
+    let path = std::env::temp_dir().join(format!("shougoutaku-book-{}.bin", std::process::id()));
+    book_state::save(&path, &OrderBook::from_snapshot(snapshot), BookFormat::Binary).unwrap();
+    let loaded = book_state::load(Exchange::Binance, &path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(format!("{:?}", loaded.unwrap()), format!("{:?}", book_state::from_binary(&binary).unwrap()));
+}
+
+#[test]
+fn truncated_or_extended_binary_books_are_refused() {
+    let snapshot = book_state::load(Exchange::Binance, &fixture("book_state", "snapshot.txt")).unwrap();
+    let binary = book_state::to_binary(&snapshot);
+    for end in 0..binary.len() {
+        let error = book_state::from_binary(&binary[..end]).unwrap_err();
+        assert!(matches!(error, EngineError::Parse { .. }), "{:?}", error);
+    }
+    let extended = [binary.as_slice(), &[0]].concat();
+    assert!(matches!(book_state::from_binary(&extended), Err(EngineError::Parse { .. })));
+}
+
+#[test]
+fn mismatched_levels_are_reported_within_the_shared_depth() {
+    let later = book_state::load(Exchange::Binance, &fixture("book_state", "later_snapshot.txt")).unwrap();
+    let book_at = replay_to(&fixture("book_state", "snapshot.txt"), &fixture("book_state", "depth.txt"), later.last_update_id);
+    // Events 105-107 straddle the snapshot's update id 106 and are left out
+    assert_eq!(book_at.book.last_update_id(), 104);
+    let diff = book_state::diff(&book_at.book, book_at.straddling.as_ref(), &later);
+    assert_eq!(diff.snapshot_update_id, 106);
+    // Bid 41999.50 and ask 42000.50 lie beyond the snapshot's and the book's depth
+    assert_eq!(diff.compared_levels, 6);
+    assert_eq!(diff.mismatches, vec![
+        mismatch(TradeType::Bid, "42000.00", Some("5.0"), Some("4.0"), true),
+        mismatch(TradeType::Bid, "41999.80", Some("1.0"), Some("0.7"), false),
+        mismatch(TradeType::Ask, "42000.40", None, Some("1.5"), false),
+    ]);
+
+    let book_at = replay_to(&fixture("book_state", "snapshot.txt"), &fixture("book_state", "depth.txt"), 107);
+    let diff = book_state::diff(&book_at.book, None, &later);
+    assert_eq!(diff.book_update_id, 107);
+    assert_eq!(diff.mismatches, vec![
+        mismatch(TradeType::Bid, "41999.80", Some("1.0"), Some("0.7"), false),
+        mismatch(TradeType::Ask, "42000.30", Some("2.0"), None, false),
+        mismatch(TradeType::Ask, "42000.40", None, Some("1.5"), false),
+    ]);
+}
+
+#[test]
+fn a_replayed_book_matches_its_own_saved_state() {
+    let book_at = replay_to(&session("snapshot.txt"), &session("depth.txt"), 41239691500);
+    let path = std::env::temp_dir().join(format!("shougoutaku-book-{}.json", std::process::id()));
+    book_state::save(&path, &book_at.book, BookFormat::Json).unwrap();
+    let saved = book_state::load(Exchange::Binance, &path);
+    fs::remove_file(&path).unwrap();
+    let diff = book_state::diff(&book_at.book, None, &saved.unwrap());
+    assert!(diff.is_match(), "{:?}", diff.mismatches);
+    assert!(diff.compared_levels > 5000);
+}
+
+#[test]
+fn books_cannot_be_replayed_back_before_their_snapshot() {
+    let snapshot = book_state::load(Exchange::Binance, &fixture("book_state", "snapshot.txt")).unwrap();
+    let depth = engine::open_lines(&fixture("book_state", "depth.txt")).unwrap();
+    let error = book_state::replay_to(Exchange::Binance, CrossRepair::None, snapshot, depth, 99).err().unwrap();
+    assert!(matches!(error, EngineError::Config(_)), "{:?}", error);
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+use shougoutaku::book_store::{ArrayStore, BTreeStore, BookStore, OrderBookStore};
+use shougoutaku::engine::{self, ReplayOptions, SymbolReport};
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::fixed_point::{Amount, FixedPoints, Representation};
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::{CrossRepair, OrderBook};
+use shougoutaku::simulation::{simulate, SimulationConfig};
+use shougoutaku::trade_matcher::{Matching, TradeType};
+
+use common::{capture, reconcile};
+
+fn options(store: BookStore, matching: Matching) -> ReplayOptions {
+    let mut fixed_points = FixedPoints::default();
+    fixed_points.insert(None, "0.01000000:0.00000100".parse().unwrap());
+    ReplayOptions { fixed_points, book_store: store, matching, ..ReplayOptions::new(Exchange::Binance) }
+}
+
+fn assert_same_report(array: &SymbolReport, btree: &SymbolReport) {
+    assert_eq!((&array.ask_results, &array.bid_results, &array.updates), (&btree.ask_results, &btree.bid_results, &btree.updates));
+    assert_eq!((&array.ask_scores, &array.bid_scores), (&btree.ask_scores, &btree.bid_scores));
+    assert_eq!((array.price_checks, array.crossed_books, &array.confident_lags), (btree.price_checks, btree.crossed_books, &btree.confident_lags));
+}
+
+#[derive(Debug, Clone)]
+enum Change {
+    Insert(TradeType, i64, i64, Option<u64>),
+    Remove(TradeType, i64),
+    Truncate(TradeType, usize),
+    Clear,
+}
+
+fn trade_type() -> impl Strategy<Value = TradeType> {
+    prop_oneof![Just(TradeType::Bid), Just(TradeType::Ask)]
+}
+
+/// Changes over ticks spanning many windows of the array store, so that levels keep moving
+/// in and out of its ring.
+fn change() -> impl Strategy<Value = Change> {
+    prop_oneof![
+        8 => (trade_type(), -60i64..60, 1i64..50, prop::option::of(1u64..1000)).prop_map(|(side, tick, lots, update_id)| Change::Insert(side, tick, lots, update_id)),
+        6 => (trade_type(), -60i64..60).prop_map(|(side, tick)| Change::Remove(side, tick)),
+        1 => (trade_type(), 0usize..6).prop_map(|(side, depth)| Change::Truncate(side, depth)),
+        1 => Just(Change::Clear),
+    ]
+}
+
+fn apply<S: OrderBookStore>(store: &mut S, change: &Change) {
+    match *change {
+        Change::Insert(side, tick, lots, update_id) => store.insert(side, Amount::Fixed(tick), Amount::Fixed(lots), update_id),
+        Change::Remove(side, tick) => store.remove(side, Amount::Fixed(tick)),
+        Change::Truncate(side, depth) => store.truncate(side, depth),
+        Change::Clear => store.clear(),
+    }
+}
+
+type Side = Vec<(Amount, Amount, Option<u64>)>;
+
+/// Levels of both sides best first, with their update ids.
+fn contents<S: OrderBookStore>(store: &S) -> [(Option<Amount>, usize, Side); 2] {
+    [TradeType::Bid, TradeType::Ask].map(|side| {
+        let levels = store.levels(side)
+            .map(|(price, quantity)| {
+                assert_eq!(store.quantity(side, price), Some(quantity));
+                (price, quantity, store.update_id(side, price))
+            })
+            .collect();
+        (store.best(side), store.len(side), levels)
+    })
+}
+
+fn depth_update(index: u64, bids: Vec<(Decimal, Decimal)>, asks: Vec<(Decimal, Decimal)>) -> DepthUpdate {
+    DepthUpdate {
+        event_time: 1_000 + index * 100,
+        first_update_id_in_event: 101 + index,
+        final_update_id_in_event: 101 + index,
+        bids_to_update: bids,
+        asks_to_update: asks,
+        ..Default::default()
+    }
+}
+
+/// Levels of one depth event side: ticks around a cent price grid, some removals.
+fn changes() -> impl Strategy<Value = Vec<(Decimal, Decimal)>> {
+    prop::collection::vec((-40i64..40, prop_oneof![Just(0i64), 1i64..500]), 0..6)
+        .prop_map(|levels| levels.into_iter().map(|(tick, lots)| (Decimal::new(4_200_000 + tick, 2), Decimal::new(lots, 3))).collect())
+}
+
+proptest! {
+    #[test]
+    fn array_stores_hold_the_levels_of_btree_stores(changes in prop::collection::vec(change(), 1..80)) {
+        let (mut btree, mut array) = (BTreeStore::default(), ArrayStore::with_width(8));
+        for change in &changes {
+            apply(&mut btree, change);
+            apply(&mut array, change);
+            prop_assert_eq!(contents(&array), contents(&btree));
+        }
+    }
+
+    #[test]
+    fn array_books_update_and_repair_like_btree_books(events in prop::collection::vec((changes(), changes()), 1..30)) {
+        let representation = Representation::Fixed("0.01:0.001".parse().unwrap());
+        let new_book = |store| OrderBook::with_cross_repair(CrossRepair::TrustNewest).with_representation(representation).with_store(store);
+        let (mut btree, mut array) = (new_book(BookStore::BTree), new_book(BookStore::Array));
+        let snapshot = SnapShotUpdate {
+            last_update_id: 100,
+            bids: vec![(Decimal::new(4_199_990, 2), Decimal::ONE)],
+            asks: vec![(Decimal::new(4_200_010, 2), Decimal::ONE)],
+        };
+        btree.try_update_with_snapshot(snapshot.clone()).unwrap();
+        array.try_update_with_snapshot(snapshot).unwrap();
+        for (index, (bids, asks)) in events.into_iter().enumerate() {
+            let update = depth_update(index as u64, bids, asks);
+            btree.try_update(update.clone()).unwrap();
+            array.try_update(update).unwrap();
+            prop_assert_eq!(format!("{:?}", array.to_snapshot()), format!("{:?}", btree.to_snapshot()));
+            for side in [TradeType::Bid, TradeType::Ask] {
+                prop_assert_eq!(format!("{:?}", array.best_deltas(side)), format!("{:?}", btree.best_deltas(side)));
+            }
+            prop_assert_eq!(array.crossed_books(), btree.crossed_books());
+        }
+    }
+}
+
+#[test]
+fn array_book_replays_match_like_btree_ones() {
+    for matching in [Matching::First, Matching::Scored] {
+        let btree = reconcile(&capture(), &options(BookStore::BTree, matching)).unwrap();
+        let array = reconcile(&capture(), &options(BookStore::Array, matching)).unwrap();
+        assert!(btree.matched() > 0);
+        assert_same_report(&array, &btree);
+    }
+    // Sweeps and iceberg refills of a simulated session
+    let simulation = simulate(&SimulationConfig { duration: 20_000, ..SimulationConfig::default() });
+    let snapshot = Exchange::Binance.adapter().parse_snapshot(&simulation.snapshot).unwrap();
+    for matching in [Matching::First, Matching::Scored] {
+        let mut fixed_points = FixedPoints::default();
+        fixed_points.insert(None, "0.01:0.00001".parse().unwrap());
+        let replay = |store| {
+            let options = ReplayOptions { fixed_points: fixed_points.clone(), book_store: store, matching, ..ReplayOptions::new(Exchange::Binance) };
+            engine::reconcile(&options, "", Some(snapshot.clone()), simulation.streams()).unwrap()
+        };
+        assert_same_report(&replay(BookStore::Array), &replay(BookStore::BTree));
+    }
+}
+
+#[test]
+fn array_books_need_fixed_point_sizes() {
+    let options = ReplayOptions { book_store: BookStore::Array, ..ReplayOptions::new(Exchange::Binance) };
+    assert!(matches!(reconcile(&capture(), &options), Err(EngineError::Config(_))));
+    for store in ["btree", "array", "ARRAY"] {
+        let parsed: BookStore = store.parse().unwrap();
+        assert_eq!(parsed.to_string(), store.to_lowercase());
+    }
+    assert!("vector".parse::<BookStore>().is_err());
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::exchange::{BookEvent, Exchange};
+
+use common::{dec, fixture, fixtures, lines, streams};
+
+#[test]
+fn maps_snapshot_and_delta_messages() {
+    let mut adapter = Exchange::Bybit.adapter();
+    let book = lines(&fixture("bybit", "orderbook.txt"));
+    assert!(adapter.parse_book(&book[0]).unwrap().is_empty());
+    assert_eq!(adapter.symbol_of(&book[1]).unwrap().as_deref(), Some("BTCUSDT"));
+
+    let BookEvent::Snapshot(snapshot) = adapter.parse_book(&book[1]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    assert_eq!(snapshot.last_update_id, 1000);
+
+    let BookEvent::Depth(delta) = adapter.parse_book(&book[2]).unwrap().remove(0) else { panic!("expected a depth update") };
+    assert_eq!((delta.first_update_id_in_event, delta.final_update_id_in_event), (1001, 1001));
+    assert_eq!(delta.previous_final_update_id, None);
+    assert_eq!(delta.event_time, 1702798596120);
+    assert_eq!(delta.transaction_time, Some(1702798596118));
+    assert_eq!(delta.asks_to_update, vec![(dec("42000.10"), dec("0.500"))]);
+
+    // A service restart resends a snapshot with u = 1
+    let BookEvent::Snapshot(restart) = adapter.parse_book(&book[4]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    assert_eq!(restart.last_update_id, 1);
+}
+
+#[test]
+fn maps_taker_side_to_book_side() {
+    let mut adapter = Exchange::Bybit.adapter();
+    let ask_trade = adapter.parse_trades(&lines(&fixture("bybit", "ask_trades.txt"))[0]).unwrap().remove(0);
+    assert!(!ask_trade.is_market_maker);
+    assert_eq!(ask_trade.trade_time, 1702798596100);
+    assert_eq!(ask_trade.trade_id, "20f43950-d8dd-5b31-9112-a178eb6023af");
+    let bid_trade = adapter.parse_trades(&lines(&fixture("bybit", "bid_trades.txt"))[0]).unwrap().remove(0);
+    assert!(bid_trade.is_market_maker);
+}
+
+#[test]
+fn reconciles_across_gap_and_restart() {
+    let streams = streams(&fixtures("bybit"), ["orderbook.txt", "ask_trades.txt", "bid_trades.txt"]);
+    let report = engine::reconcile(&ReplayOptions::new(Exchange::Bybit), "", None, streams).unwrap();
+    assert_eq!(report.symbol, "BTCUSDT");
+    // u = 1003 does not follow u = 1001
+    assert_eq!(report.sequence_gaps, 1);
+    let ask: Vec<_> = report.ask_results.into_iter().map(|(_, _, event_time)| event_time).collect();
+    assert_eq!(ask, vec![1702798596120, 1702798596910]);
+    let bid: Vec<_> = report.bid_results.into_iter().map(|(_, _, event_time)| event_time).collect();
+    assert_eq!(bid, vec![1702798596340]);
+}
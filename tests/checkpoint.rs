@@ -0,0 +1,97 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use shougoutaku::book_store::BookStore;
+use shougoutaku::checkpoint::Checkpointing;
+use shougoutaku::engine::{self, Aggregation, ReplayOptions, Streams, SymbolReport, TradeStreams};
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::fixed_point::FixedPoints;
+use shougoutaku::trade_matcher::Matching;
+
+use common::{lines, session};
+
+/// Book stream lines between two checkpoints, prime to the length of the depth file.
+const EVERY: u64 = 7;
+const SNAPSHOT_LEVELS: usize = 100;
+
+fn checkpoint_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("shougoutaku-checkpoint-{}-{}.json", std::process::id(), name))
+}
+
+/// Replays the capture over its first `depth_lines` book lines, trades all on one stream
+/// when `single`. The snapshot is cut to its best levels to keep checkpoints small.
+fn replay(options: &ReplayOptions, depth_lines: usize, single: bool) -> Result<SymbolReport, EngineError> {
+    let mut snapshot = engine::load_snapshot(Exchange::Binance, &session("snapshot.txt")).unwrap();
+    snapshot.bids.truncate(SNAPSHOT_LEVELS);
+    snapshot.asks.truncate(SNAPSHOT_LEVELS);
+    let depth = lines(&session("depth.txt")).into_iter().take(depth_lines).collect();
+    let trades = if single {
+        TradeStreams::Single(engine::lines_of([lines(&session("ask_trade.txt")), lines(&session("bid_trade.txt"))].concat()))
+    } else {
+        TradeStreams::BySide { ask: engine::lines_of(lines(&session("ask_trade.txt"))), bid: engine::lines_of(lines(&session("bid_trade.txt"))) }
+    };
+    engine::reconcile(options, "", Some(snapshot), Streams { depth: engine::lines_of(depth), trades })
+}
+
+/// Stops a checkpointed replay after every fifth book line in turn, as a crash would, and
+/// resumes it over the whole capture: each resumed replay must report exactly what the
+/// uninterrupted one does.
+fn check_resume(name: &str, options: ReplayOptions, single: bool) {
+    let depth_lines = lines(&session("depth.txt")).len();
+    let expected = format!("{:?}", replay(&options, depth_lines, single).unwrap());
+    let path = checkpoint_path(name);
+    for crash in (1..depth_lines).step_by(5) {
+        let _ = fs::remove_file(&path);
+        let checkpointing = Checkpointing { every: EVERY, ..Checkpointing::new(&path) };
+        let options = ReplayOptions { checkpointing: Some(checkpointing.clone()), ..options.clone() };
+        replay(&options, crash, single).unwrap();
+        let options = ReplayOptions { checkpointing: Some(Checkpointing { resume: true, ..checkpointing }), ..options };
+        let resumed = format!("{:?}", replay(&options, depth_lines, single).unwrap());
+        assert_eq!(resumed, expected, "{}: resumed after a stop at book line {}", name, crash);
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn resumed_replays_match_uninterrupted_ones() {
+    check_resume("first", ReplayOptions::new(Exchange::Binance), false);
+    check_resume("scored", ReplayOptions { matching: Matching::Scored, adaptive_window: Some(99.0), ..ReplayOptions::new(Exchange::Binance) }, false);
+    check_resume("aggressor", ReplayOptions { aggregation: Aggregation::Aggressor, ..ReplayOptions::new(Exchange::Binance) }, false);
+    let mut fixed_points = FixedPoints::default();
+    fixed_points.insert(None, "0.01000000:0.00000100".parse().unwrap());
+    check_resume("fixed-point", ReplayOptions { fixed_points: fixed_points.clone(), matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }, false);
+    check_resume("array", ReplayOptions { fixed_points, book_store: BookStore::Array, ..ReplayOptions::new(Exchange::Binance) }, false);
+}
+
+#[test]
+fn resumed_single_stream_replays_match_uninterrupted_ones() {
+    check_resume("single", ReplayOptions::new(Exchange::Binance), true);
+}
+
+#[test]
+fn checkpoints_are_only_resumed_with_their_settings() {
+    let path = checkpoint_path("settings");
+    let checkpointing = Checkpointing { every: EVERY, ..Checkpointing::new(&path) };
+    let options = ReplayOptions { checkpointing: Some(checkpointing.clone()), ..ReplayOptions::new(Exchange::Binance) };
+    replay(&options, 20, false).unwrap();
+    let options = ReplayOptions {
+        matching: Matching::Scored,
+        checkpointing: Some(Checkpointing { resume: true, ..checkpointing }),
+        ..options
+    };
+    let error = replay(&options, 91, false).unwrap_err();
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(error, EngineError::Config(_)), "{:?}", error);
+}
+
+#[test]
+fn multi_symbol_replays_are_not_checkpointed() {
+    let options = ReplayOptions { checkpointing: Some(Checkpointing::new(&checkpoint_path("symbols"))), ..ReplayOptions::new(Exchange::Binance) };
+    let streams = Streams { depth: engine::lines_of(Vec::new()), trades: TradeStreams::Single(engine::lines_of(Vec::new())) };
+    let error = engine::reconcile_symbols(&options, HashMap::new(), streams).unwrap_err();
+    assert!(matches!(error, EngineError::Config(_)), "{:?}", error);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::{parse_rfc3339_millis, BookEvent, Exchange};
+use shougoutaku::trade_matcher::DROPPED;
+
+use common::{dec, fixture, fixtures, lines, streams};
+
+#[test]
+fn parses_rfc3339_timestamps() {
+    assert_eq!(parse_rfc3339_millis("2019-08-14T20:42:27.265Z").unwrap(), 1565815347265);
+    assert_eq!(parse_rfc3339_millis("2023-12-17T07:36:36.120456Z").unwrap(), 1702798596120);
+    assert_eq!(parse_rfc3339_millis("1970-01-01T00:00:00Z").unwrap(), 0);
+    assert!(parse_rfc3339_millis("yesterday").is_err());
+    for timestamp in ["2019-08-14T20:42:27.12éZ", "2019-08-14T20:42:27.12é", "2019-08-14T20:42:27.265+01:00", "2019-08-14T20:42:27.265", "2019-08-14T20:42:27.Z", "2019-08-14T20:42:27.+5Z"] {
+        assert!(matches!(parse_rfc3339_millis(timestamp), Err(EngineError::Parse { .. })), "{}", timestamp);
+    }
+    // Fields out of range, including ones whose arithmetic would overflow
+    assert_eq!(parse_rfc3339_millis("2024-02-29T23:59:60.999Z").unwrap(), 1709251200999);
+    for timestamp in ["2019-13-45T25:61:61Z", "2019-00-14T20:42:27Z", "2019-02-29T20:42:27Z", "2019-04-31T20:42:27Z", "2019-08-14T24:00:00Z",
+                      "2019-08-14T20:60:00Z", "2019-08-14T20:42:61Z", "1969-12-31T23:59:59Z", "10000-01-01T00:00:00Z",
+                      "9999999999999999-01-01T00:00:00Z", "2019-01-01T99999999999999999:00:00Z", "2019-+8-14T20:42:27Z"] {
+        assert!(matches!(parse_rfc3339_millis(timestamp), Err(EngineError::Parse { .. })), "{}", timestamp);
+    }
+}
+
+#[test]
+fn normalises_level2_snapshot_and_updates() {
+    let mut adapter = Exchange::Coinbase.adapter();
+    let events: Vec<BookEvent> = lines(&fixture("coinbase", "level2.txt")).iter()
+        .flat_map(|line| adapter.parse_book(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 4);
+
+    let BookEvent::Snapshot(snapshot) = &events[0] else { panic!("expected a snapshot") };
+    assert_eq!(snapshot.last_update_id, 1);
+    assert_eq!(snapshot.bids[0], (dec("42000.10"), dec("1.50000000")));
+    assert_eq!(snapshot.asks[0], (dec("42000.20"), dec("0.75000000")));
+
+    let BookEvent::Depth(first) = &events[1] else { panic!("expected a depth update") };
+    assert_eq!(first.symbol, "BTC-USD");
+    assert_eq!(first.event_time, 1702798596120);
+    assert_eq!((first.first_update_id_in_event, first.final_update_id_in_event), (2, 2));
+    assert!(first.bids_to_update.is_empty());
+    assert_eq!(first.asks_to_update, vec![(dec("42000.20"), dec("0.50000000"))]);
+
+    let BookEvent::Depth(last) = &events[3] else { panic!("expected a depth update") };
+    assert_eq!(last.final_update_id_in_event, 4);
+    assert_eq!(last.bids_to_update, vec![(dec("42000.10"), dec("0")), (dec("42000.00"), dec("1.90000000"))]);
+}
+
+#[test]
+fn maps_maker_side_to_book_side() {
+    let mut adapter = Exchange::Coinbase.adapter();
+    let ask_trade = adapter.parse_trades(&lines(&fixture("coinbase", "ask_matches.txt"))[1]).unwrap().remove(0);
+    // A sell maker was lifted by a buying taker
+    assert!(!ask_trade.is_market_maker);
+    assert_eq!(ask_trade.trade_id, "101");
+    assert_eq!(ask_trade.event_time, 1702798596100);
+    assert_eq!((ask_trade.price, ask_trade.quantity), (dec("42000.20"), dec("0.25")));
+
+    let bid_trades: Vec<_> = lines(&fixture("coinbase", "bid_matches.txt")).iter()
+        .flat_map(|line| adapter.parse_trades(line).unwrap())
+        .collect();
+    assert!(bid_trades.iter().all(|trade| trade.is_market_maker));
+    // Both fills of the same taker carry the same seller order id
+    assert_eq!(bid_trades[1].seller_order_id, bid_trades[2].seller_order_id);
+    assert_ne!(bid_trades[1].buyer_order_id, bid_trades[2].buyer_order_id);
+}
+
+#[test]
+fn skips_replayed_match_sequences() {
+    let mut adapter = Exchange::Coinbase.adapter();
+    let trade_ids: Vec<String> = lines(&fixture("coinbase", "ask_matches.txt")).iter()
+        .flat_map(|line| adapter.parse_trades(line).unwrap())
+        .map(|trade| trade.trade_id)
+        .collect();
+    assert_eq!(trade_ids, vec!["100", "101"]);
+}
+
+#[test]
+fn reconciles_fixture_capture() {
+    let streams = streams(&fixtures("coinbase"), ["level2.txt", "ask_matches.txt", "bid_matches.txt"]);
+    let report = engine::reconcile(&ReplayOptions::new(Exchange::Coinbase), "", None, streams).unwrap();
+    assert_eq!(report.symbol, "BTC-USD");
+
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    assert_eq!(ask, vec![
+        (String::from("100"), 1702798595010, DROPPED),
+        (String::from("101"), 1702798596100, 1702798596120),
+    ]);
+    let bid: Vec<_> = report.bid_results.into_iter().collect();
+    assert_eq!(bid, vec![
+        (String::from("102"), 1702798596330, 1702798596340),
+        (String::from("103"), 1702798596600, 1702798596610),
+        (String::from("104"), 1702798596600, 1702798596610),
+    ]);
+}
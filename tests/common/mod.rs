@@ -0,0 +1,58 @@
+//! Helpers shared by the integration tests. Each test binary uses only some of them.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust_decimal::Decimal;
+
+use shougoutaku::engine::{self, ReplayOptions, Streams, SymbolReport, TradeStreams};
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+
+/// Capture files of a Binance replay folder: depth, ask trades and bid trades.
+pub const CAPTURE_FILES: [&str; 3] = ["depth.txt", "ask_trade.txt", "bid_trade.txt"];
+
+/// A folder of `tests/fixtures/`.
+pub fn fixtures(folder: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(folder)
+}
+
+pub fn fixture(folder: &str, name: &str) -> PathBuf {
+    fixtures(folder).join(name)
+}
+
+/// The BTCUSDT capture session of `data/capture/`.
+pub fn capture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("data/capture/btcusdt/1702798595534677")
+}
+
+/// A file of the BTCUSDT capture session.
+pub fn session(name: &str) -> PathBuf {
+    capture().join(name)
+}
+
+pub fn lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path).unwrap().lines().map(String::from).collect()
+}
+
+pub fn dec(value: &str) -> Decimal {
+    value.parse().unwrap()
+}
+
+/// Streams of the depth, ask trade and bid trade files `names` of `folder`.
+pub fn streams(folder: &Path, [depth, ask, bid]: [&str; 3]) -> Streams {
+    Streams {
+        depth: engine::open_lines(&folder.join(depth)).unwrap(),
+        trades: TradeStreams::BySide {
+            ask: engine::open_lines(&folder.join(ask)).unwrap(),
+            bid: engine::open_lines(&folder.join(bid)).unwrap(),
+        },
+    }
+}
+
+/// Replays a Binance folder holding `snapshot.txt` and the `CAPTURE_FILES`.
+pub fn reconcile(folder: &Path, options: &ReplayOptions) -> Result<SymbolReport, EngineError> {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &folder.join("snapshot.txt")).unwrap();
+    engine::reconcile(options, "", Some(snapshot), streams(folder, CAPTURE_FILES))
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use rust_decimal::Decimal;
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::{CrossRepair, CrossedBooks, OrderBook};
+
+use common::dec;
+
+fn levels(levels: &[(&str, &str)]) -> Vec<(Decimal, Decimal)> {
+    levels.iter().map(|(price, quantity)| (dec(price), dec(quantity))).collect()
+}
+
+fn depth(update_id: u64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> DepthUpdate {
+    DepthUpdate {
+        event_type: String::from("depthUpdate"),
+        event_time: 1702798596000 + update_id,
+        symbol: String::from("BTCUSDT"),
+        first_update_id_in_event: update_id,
+        final_update_id_in_event: update_id,
+        bids_to_update: levels(bids),
+        asks_to_update: levels(asks),
+        ..Default::default()
+    }
+}
+
+/// Bids 100.0 and 99.9 against asks 100.1 and 100.2, then an event at 101 raising a bid
+/// to 100.1 (locked) and an event at 102 raising a bid to 100.2 (crossed over both asks).
+fn replay(cross_repair: CrossRepair) -> OrderBook {
+    let mut orderbook = OrderBook::with_cross_repair(cross_repair);
+    orderbook.update_with_snapshot(SnapShotUpdate {
+        last_update_id: 100,
+        bids: levels(&[("100.0", "1"), ("99.9", "1")]),
+        asks: levels(&[("100.1", "1"), ("100.2", "1")]),
+    });
+    orderbook.update(depth(101, &[("100.1", "1")], &[]));
+    orderbook.update(depth(102, &[("100.2", "1")], &[]));
+    orderbook
+}
+
+#[test]
+fn counts_crossed_and_locked_books_without_repair() {
+    let orderbook = replay(CrossRepair::None);
+    assert_eq!(orderbook.crossed_books(), CrossedBooks { crossed: 1, locked: 1, ..Default::default() });
+    assert_eq!((orderbook.best_bid(), orderbook.best_ask()), (Some(dec("100.2")), Some(dec("100.1"))));
+}
+
+#[test]
+fn drop_stale_side_removes_the_crossing_asks() {
+    let orderbook = replay(CrossRepair::DropStaleSide);
+    // Each new bid locks the book on the ask left by the previous repair
+    assert_eq!(orderbook.crossed_books(), CrossedBooks { locked: 2, removed_levels: 2, ..Default::default() });
+    assert_eq!(orderbook.best_bid(), Some(dec("100.2")));
+    assert_eq!(orderbook.best_ask(), None);
+}
+
+#[test]
+fn drop_stale_side_judges_sides_moved_by_the_same_event_by_their_best_levels() {
+    let mut orderbook = OrderBook::with_cross_repair(CrossRepair::DropStaleSide);
+    orderbook.update_with_snapshot(SnapShotUpdate {
+        last_update_id: 100,
+        bids: levels(&[("100.0", "1")]),
+        asks: levels(&[("100.1", "1"), ("100.2", "1"), ("100.5", "1")]),
+    });
+    orderbook.update(depth(101, &[("100.1", "1")], &[("100.5", "2")]));
+    // A bid at 100.2 and a deep ask in one event: the best ask is still the snapshot's
+    orderbook.update(depth(102, &[("100.2", "1")], &[("100.6", "1")]));
+    assert_eq!(orderbook.crossed_books(), CrossedBooks { crossed: 0, locked: 2, removed_levels: 2, ..Default::default() });
+    assert_eq!((orderbook.best_bid(), orderbook.best_ask()), (Some(dec("100.2")), Some(dec("100.5"))));
+    // Asks from the same event as the crossing bid are neither side's stale levels
+    orderbook.update(depth(103, &[("100.5", "1")], &[("100.5", "3")]));
+    assert_eq!(orderbook.crossed_books().unrepaired, 1);
+}
+
+#[test]
+fn trust_newest_removes_older_levels_first() {
+    let mut orderbook = replay(CrossRepair::TrustNewest);
+    // The snapshot asks were older than both new bids
+    assert_eq!(orderbook.crossed_books().removed_levels, 2);
+    orderbook.update(depth(103, &[], &[("100.1", "2")]));
+    // The new ask at 100.1 is newer than the bid at 100.2 it crosses, and the bid at 100.1
+    assert_eq!(orderbook.best_ask(), Some(dec("100.1")));
+    assert_eq!(orderbook.best_bid(), Some(dec("100.0")));
+    assert_eq!(orderbook.crossed_books().removed_levels, 4);
+}
+
+#[test]
+fn resync_drops_the_book_until_the_next_snapshot() {
+    let orderbook = replay(CrossRepair::Resync);
+    assert_eq!(orderbook.crossed_books(), CrossedBooks { locked: 1, resyncs: 1, ..Default::default() });
+    assert!(!orderbook.is_synced());
+}
+
+#[test]
+fn parses_policy_names() {
+    for policy in [CrossRepair::None, CrossRepair::DropStaleSide, CrossRepair::TrustNewest, CrossRepair::Resync] {
+        assert_eq!(policy.to_string().parse::<CrossRepair>(), Ok(policy));
+    }
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use shougoutaku::engine;
+use shougoutaku::error::{EngineError, Location};
+use shougoutaku::evaluation;
+use shougoutaku::exchange::{BookEvent, Exchange};
+use shougoutaku::messages::DepthUpdate;
+use shougoutaku::orderbook::OrderBook;
+use shougoutaku::sequencing::{Sequence, Sequencer};
+
+use common::fixture;
+
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("shougoutaku-{}-{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn io_errors_name_their_file() {
+    let missing = fixture("scored", "missing.txt");
+    match engine::open_lines(&missing) {
+        Err(EngineError::Io { path, line: None, source }) => {
+            assert_eq!(path, Some(missing));
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        _ => panic!("expected an I/O error"),
+    }
+    // Placed in a stream line, an I/O error stays one
+    let error = EngineError::from(std::io::Error::new(std::io::ErrorKind::InvalidData, "stream did not contain valid UTF-8")).at("BTCUSDT depth stream", Some(3));
+    assert_eq!(error.to_string(), "BTCUSDT depth stream:3: stream did not contain valid UTF-8");
+    match error {
+        EngineError::Io { path, line, source } => {
+            assert_eq!((path, line), (Some(PathBuf::from("BTCUSDT depth stream")), Some(3)));
+            assert_eq!(source.kind(), std::io::ErrorKind::InvalidData);
+        }
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_errors_name_their_file_and_line() {
+    let snapshot = temp_file("snapshot.txt", r#"{"lastUpdateId":100,"bids":[["42000.00"]]}"#);
+    let error = engine::load_snapshot(Exchange::Binance, &snapshot).unwrap_err();
+    fs::remove_file(&snapshot).unwrap();
+    assert!(matches!(error, EngineError::Parse { location: Some(Location { line: None, .. }), .. }), "{:?}", error);
+
+    let labels = temp_file("labels.txt", "{\"trade_id\":\"901\",\"update_id\":101}\n\n{\"trade_id\":902}x\n");
+    let error = evaluation::load_labels(&labels).unwrap_err();
+    fs::remove_file(&labels).unwrap();
+    match error {
+        EngineError::Parse { location: Some(location), .. } => {
+            assert_eq!(location, Location { file: labels.display().to_string(), line: Some(3) });
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn sequence_gaps_give_the_missing_update_ids() {
+    let mut sequencer = Sequencer::default();
+    sequencer.reset(100);
+    let update = DepthUpdate { first_update_id_in_event: 105, final_update_id_in_event: 106, ..Default::default() };
+    assert_eq!(sequencer.check(&update), Sequence::Gap);
+    assert!(matches!(sequencer.gap(&update), EngineError::SequenceGap { last_update_id: 100, first_update_id: 105 }));
+}
+
+#[test]
+fn checksum_mismatches_are_desyncs_and_snapshot_files_config_errors() {
+    let mut adapter = Exchange::Kraken.adapter();
+    let mut orderbook = OrderBook::new();
+    let mut errors = Vec::new();
+    for line in fs::read_to_string(fixture("kraken", "book.txt")).unwrap().lines() {
+        for event in adapter.parse_book(line).unwrap() {
+            match event {
+                BookEvent::Snapshot(snapshot) => orderbook.update_with_snapshot(snapshot),
+                BookEvent::Depth(update) => orderbook.update(update),
+            }
+        }
+        errors.extend(adapter.verify_book(&mut orderbook).err());
+    }
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(errors[0], EngineError::Desync(_)), "{:?}", errors[0]);
+
+    assert!(matches!(adapter.parse_snapshot("{}"), Err(EngineError::Config(_))));
+}
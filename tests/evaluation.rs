@@ -0,0 +1,79 @@
+mod common;
+
+use std::fs;
+
+use shougoutaku::engine::ReplayOptions;
+use shougoutaku::evaluation::{self, ErrorKind, LabelError, Labels};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::trade_matcher::Matching;
+
+use common::{fixture, fixtures, reconcile};
+
+#[test]
+fn labels_accept_string_and_number_trade_ids() {
+    let labels = evaluation::load_labels(&fixture("scored", "labels.txt")).unwrap();
+    let expected: Labels = [("901", Some(101)), ("902", Some(102)), ("903", None)].into_iter()
+        .map(|(trade_id, update_id)| ((String::new(), trade_id.to_string()), update_id))
+        .collect();
+    assert_eq!(labels, expected);
+}
+
+#[test]
+fn reports_record_the_matched_update_ids() {
+    let report = reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }).unwrap();
+    assert_eq!(report.updates.get("902"), Some(&102));
+    assert_eq!(report.updates.get("903"), Some(&101));
+    assert_eq!(report.updates.get("901"), None);
+}
+
+#[test]
+fn first_matching_misses_the_later_trade() {
+    let labels = evaluation::load_labels(&fixture("scored", "labels.txt")).unwrap();
+    let evaluation = evaluation::evaluate(&labels, &[reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::First, ..ReplayOptions::new(Exchange::Binance) }).unwrap()]);
+    assert_eq!((evaluation.correct, evaluation.wrong_event, evaluation.missed, evaluation.spurious), (1, 0, 1, 0));
+    assert_eq!(evaluation.unmatched, 1);
+    assert_eq!(evaluation.unlabelled, 0);
+    assert_eq!(evaluation.precision(), Some(1.0));
+    assert_eq!(evaluation.recall(), Some(0.5));
+}
+
+#[test]
+fn scored_matching_errors_are_categorised() {
+    let labels = evaluation::load_labels(&fixture("scored", "labels.txt")).unwrap();
+    let evaluation = evaluation::evaluate(&labels, &[reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }).unwrap()]);
+    assert_eq!(evaluation.errors, vec![
+        LabelError { symbol: String::from("BTCUSDT"), trade_id: String::from("901"), kind: ErrorKind::Missed, expected: Some(101), predicted: None },
+        LabelError { symbol: String::from("BTCUSDT"), trade_id: String::from("903"), kind: ErrorKind::Spurious, expected: None, predicted: Some(101) },
+    ]);
+    assert_eq!(evaluation.precision(), Some(0.5));
+    assert_eq!(evaluation.recall(), Some(0.5));
+
+    // A trade matched with another event than its label's, and one left out of the labels
+    let labels: Labels = [((String::new(), String::from("903")), Some(102))].into_iter().collect();
+    let evaluation = evaluation::evaluate(&labels, &[reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }).unwrap()]);
+    assert_eq!(evaluation.wrong_event, 1);
+    assert_eq!(evaluation.errors[0].kind, ErrorKind::WrongEvent);
+    assert_eq!(evaluation.unlabelled, 2);
+    assert_eq!(evaluation.precision(), Some(0.0));
+}
+
+#[test]
+fn equal_trade_ids_of_two_symbols_are_scored_against_their_own_labels() {
+    let btcusdt = reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }).unwrap();
+    let mut ethusdt = btcusdt.clone();
+    ethusdt.symbol = String::from("ETHUSDT");
+    ethusdt.updates.insert(String::from("902"), 502);
+    let labels: Labels = [("BTCUSDT", Some(102)), ("ETHUSDT", Some(502))].into_iter()
+        .map(|(symbol, update_id)| ((symbol.to_string(), String::from("902")), update_id))
+        .collect();
+    // Labels keep their symbol through a label file
+    let path = std::env::temp_dir().join(format!("shougoutaku-labels-{}.txt", std::process::id()));
+    evaluation::save_labels(&path, &labels).unwrap();
+    assert_eq!(evaluation::load_labels(&path).unwrap(), labels);
+    fs::remove_file(&path).unwrap();
+
+    let evaluation = evaluation::evaluate(&labels, &[btcusdt, ethusdt]);
+    assert_eq!((evaluation.correct, evaluation.wrong_event, evaluation.missed, evaluation.spurious), (2, 0, 0, 0));
+    // 901 and 903 of both symbols
+    assert_eq!(evaluation.unlabelled, 4);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use std::fs;
+use serde_json::Value;
+
+use shougoutaku::book_state::{self, BookFormat};
+use shougoutaku::engine::{self, ReplayOptions, SymbolReport};
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::fixed_point::{FixedPoint, FixedPoints, Representation};
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::OrderBook;
+use shougoutaku::simulation::{simulate, SimulationConfig};
+use shougoutaku::trade_matcher::Matching;
+
+use common::{capture, dec, reconcile, session};
+
+/// Tick and lot sizes the BTCUSDT capture is on, with the scale Binance sends.
+const CAPTURE_SIZES: &str = "0.01000000:0.00000100";
+
+fn fixed(sizes: &str) -> ReplayOptions {
+    let mut fixed_points = FixedPoints::default();
+    fixed_points.insert(None, sizes.parse().unwrap());
+    ReplayOptions { fixed_points, ..ReplayOptions::new(Exchange::Binance) }
+}
+
+/// Reports agree but for the scores, whose quantity residuals are divided as floats in fixed point.
+fn assert_same_matches(fixed: &SymbolReport, decimal: &SymbolReport) {
+    assert_eq!((&fixed.ask_results, &fixed.bid_results, &fixed.updates), (&decimal.ask_results, &decimal.bid_results, &decimal.updates));
+    assert_eq!((fixed.price_checks, fixed.crossed_books, &fixed.confident_lags), (decimal.price_checks, decimal.crossed_books, &decimal.confident_lags));
+    for (fixed_scores, decimal_scores) in [(&fixed.ask_scores, &decimal.ask_scores), (&fixed.bid_scores, &decimal.bid_scores)] {
+        assert_eq!(fixed_scores.keys().collect::<Vec<_>>(), decimal_scores.keys().collect::<Vec<_>>());
+        for (trade_id, score) in fixed_scores {
+            assert!((score.score - decimal_scores[trade_id].score).abs() < 1e-12, "{}", trade_id);
+        }
+    }
+}
+
+#[test]
+fn prices_and_quantities_scale_to_whole_ticks_and_lots() {
+    let fixed_point: FixedPoint = CAPTURE_SIZES.parse().unwrap();
+    assert_eq!(fixed_point.ticks(dec("41926.86000000")).unwrap(), 4192686);
+    assert_eq!(fixed_point.ticks(dec("41926.86")).unwrap(), 4192686);
+    assert_eq!(fixed_point.lots(dec("129.54107100")).unwrap(), 129541071);
+    // Back with the scale of the sizes, whatever the scale they were read with
+    assert_eq!(fixed_point.price(4192686).to_string(), "41926.86000000");
+    assert_eq!(fixed_point.quantity(129541071).to_string(), "129.54107100");
+    assert!(matches!(fixed_point.ticks(dec("41926.865")), Err(EngineError::Parse { .. })));
+    assert!(matches!(fixed_point.lots(dec("0.0000001")), Err(EngineError::Parse { .. })));
+    assert!(fixed_point.ticks(dec("1e17")).is_err());
+    for spec in ["0.01", "0.01:0", "-0.01:1", "tick:1", "0.01:1:1"] {
+        assert!(spec.parse::<FixedPoint>().is_err(), "{}", spec);
+    }
+}
+
+#[test]
+fn fixed_point_books_write_the_strings_they_were_loaded_from() {
+    let raw = fs::read_to_string(session("snapshot.txt")).unwrap();
+    let snapshot = engine::load_snapshot(Exchange::Binance, &session("snapshot.txt")).unwrap();
+    let mut book = OrderBook::new().with_representation(Representation::Fixed(CAPTURE_SIZES.parse().unwrap()));
+    book.try_update_with_snapshot(snapshot.clone()).unwrap();
+    let json = book_state::encode(&book.to_snapshot(), BookFormat::Json);
+    assert_eq!(serde_json::from_slice::<Value>(&json).unwrap(), serde_json::from_str::<Value>(&raw).unwrap());
+    // Debug output keeps each decimal's scale
+    assert_eq!(format!("{:?}", book.to_snapshot()), format!("{:?}", snapshot));
+}
+
+#[test]
+fn fixed_point_replays_match_like_decimal_ones() {
+    for matching in [Matching::First, Matching::Scored] {
+        let decimal = reconcile(&capture(), &ReplayOptions { matching, ..ReplayOptions::new(Exchange::Binance) }).unwrap();
+        let fixed = reconcile(&capture(), &ReplayOptions { matching, ..fixed(CAPTURE_SIZES) }).unwrap();
+        assert_same_matches(&fixed, &decimal);
+    }
+    // Sweeps and iceberg refills of a simulated session, at the simulation's sizes
+    let simulation = simulate(&SimulationConfig { duration: 20_000, ..SimulationConfig::default() });
+    let snapshot = Exchange::Binance.adapter().parse_snapshot(&simulation.snapshot).unwrap();
+    for matching in [Matching::First, Matching::Scored] {
+        let decimal = engine::reconcile(&ReplayOptions { matching, ..ReplayOptions::new(Exchange::Binance) }, "", Some(snapshot.clone()), simulation.streams()).unwrap();
+        let fixed = engine::reconcile(&ReplayOptions { matching, ..fixed("0.01:0.00001") }, "", Some(snapshot.clone()), simulation.streams()).unwrap();
+        assert!(decimal.matched() > 0);
+        assert_same_matches(&fixed, &decimal);
+    }
+}
+
+#[test]
+fn levels_off_the_grid_fail_the_replay_at_their_line() {
+    // Depth quantities of the capture have six decimals
+    let error = reconcile(&capture(), &fixed("0.01:0.00001")).unwrap_err();
+    match error {
+        EngineError::Parse { location: Some(location), message } => {
+            assert_eq!(location.file, "BTCUSDT depth stream");
+            assert!(location.line.is_some() && message.contains("lots"), "{}", message);
+        }
+        other => panic!("{:?}", other),
+    }
+    // Snapshot prices are on the cent
+    let error = reconcile(&capture(), &fixed("0.1:0.000001")).unwrap_err();
+    assert!(matches!(error, EngineError::Parse { location: Some(ref location), .. } if location.file == "snapshot"), "{:?}", error);
+}
+
+#[test]
+fn a_level_off_the_grid_leaves_the_book_as_it_was() {
+    let mut book = OrderBook::new().with_representation(Representation::Fixed("0.1:1".parse().unwrap()));
+    book.try_update_with_snapshot(SnapShotUpdate { last_update_id: 1, bids: vec![(dec("100.0"), dec("2"))], asks: vec![(dec("100.1"), dec("3"))] }).unwrap();
+    let update = DepthUpdate {
+        first_update_id_in_event: 2,
+        final_update_id_in_event: 2,
+        bids_to_update: vec![(dec("100.0"), dec("0"))],
+        asks_to_update: vec![(dec("100.15"), dec("1"))],
+        ..Default::default()
+    };
+    assert!(book.try_update(update.clone()).is_err());
+    assert_eq!(book.top_bids(5).collect::<Vec<_>>(), vec![(dec("100.0"), dec("2"))]);
+    assert_eq!(book.last_update_id(), 1);
+    // Without a way to fail, the book is dropped until the next snapshot
+    book.update(update);
+    assert!(!book.is_synced());
+}
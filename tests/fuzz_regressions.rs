@@ -0,0 +1,72 @@
+mod common;
+
+use std::fs;
+use std::panic;
+
+use rust_decimal::Decimal;
+
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::fuzzing::TARGETS;
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::OrderBook;
+
+use common::fixtures;
+
+/// Runs every input saved under `tests/fixtures/fuzz/<target>/`, e.g. a crash found by
+/// `cargo fuzz run <target>`, through the target's harness.
+#[test]
+fn saved_fuzz_inputs_run_cleanly() {
+    let root = fixtures("fuzz");
+    for (name, target) in TARGETS {
+        let Ok(entries) = fs::read_dir(root.join(name)) else { continue };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            let input = fs::read(&path).unwrap();
+            assert!(panic::catch_unwind(|| target(&input)).is_ok(), "{} panicked", path.display());
+        }
+    }
+}
+
+/// Negative and out-of-range values fail their line as a parse error, before reaching a book.
+#[test]
+fn negative_and_huge_values_are_parse_errors() {
+    let depth = |level: &str| format!(r#"{{"e":"depthUpdate","E":1702798596110,"s":"BTCUSDT","U":101,"u":101,"b":[{}],"a":[]}}"#, level);
+    let trade = |price: &str, quantity: &str| format!(r#"{{"e":"trade","E":1702798596100,"s":"BTCUSDT","t":901,"p":"{}","q":"{}","b":11,"a":12,"T":1702798596099,"m":false}}"#, price, quantity);
+    let huge = "1".repeat(40);
+    let mut adapter = Exchange::Binance.adapter();
+    for level in [r#"["9","-5"]"#, r#"["-9","5"]"#, r#"["9","-1e-3"]"#, &format!(r#"["{}","5"]"#, huge)] {
+        let result = adapter.parse_book(&depth(level));
+        assert!(matches!(result, Err(EngineError::Parse { .. })), "{}: {:?}", level, result);
+    }
+    for (price, quantity) in [("-3", "-1"), ("-3", "1"), ("3", "-1"), (huge.as_str(), "1")] {
+        let result = adapter.parse_trades(&trade(price, quantity));
+        assert!(matches!(result, Err(EngineError::Parse { .. })), "{} {}: {:?}", price, quantity, result);
+    }
+    // Exponents are read as the value they write
+    let trades = adapter.parse_trades(&trade("1e-3", "1E3")).unwrap();
+    assert_eq!((trades[0].price, trades[0].quantity), (Decimal::new(1, 3), Decimal::from(1000)));
+    assert!(adapter.parse_book(&depth(r#"["9","0"]"#)).is_ok());
+    assert!(adapter.parse_snapshot(r#"{"lastUpdateId":100,"bids":[["9","-5"]],"asks":[]}"#).is_err());
+}
+
+/// A negative level given to a book directly leaves it as it was.
+#[test]
+fn negative_levels_leave_the_book_as_it_was() {
+    let level = |price: &str, quantity: &str| (price.parse::<Decimal>().unwrap(), quantity.parse::<Decimal>().unwrap());
+    let mut book = OrderBook::new();
+    let snapshot = SnapShotUpdate { last_update_id: 1, bids: vec![level("9", "5")], asks: vec![level("10", "3")] };
+    book.try_update_with_snapshot(snapshot).unwrap();
+    let update = DepthUpdate {
+        first_update_id_in_event: 2,
+        final_update_id_in_event: 2,
+        bids_to_update: vec![level("9", "-5")],
+        ..Default::default()
+    };
+    assert!(matches!(book.try_update(update), Err(EngineError::Parse { .. })));
+    assert_eq!(book.top_bids(5).collect::<Vec<_>>(), vec![level("9", "5")]);
+    assert_eq!(book.last_update_id(), 1);
+    let snapshot = SnapShotUpdate { last_update_id: 3, bids: vec![], asks: vec![level("-10", "3")] };
+    assert!(book.try_update_with_snapshot(snapshot).is_err());
+    assert_eq!(book.last_update_id(), 1);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use shougoutaku::engine::{Aggregation, ReplayOptions, SymbolReport};
+use shougoutaku::batch;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::trade_matcher::{MatchScores, Matching, TradeResults};
+
+use common::fixture;
+
+/// Set to rewrite the expected files from the current output instead of comparing with them.
+const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+/// Bundled sessions replayed against expected files, with the prefix of their file names.
+const GOLDEN_SESSIONS: [(&str, &str); 1] = [("btcusdt/1702798595534677", "btcusdt")];
+
+/// Bundled sessions captured without a depth stream, which leaves nothing to replay.
+const SESSIONS_WITHOUT_DEPTH: [&str; 1] = ["btcusdt/1702970588517503"];
+
+fn bundled() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("data/capture")
+}
+
+/// Matching output as one tab-separated line per trade, then the report's counters.
+fn render(report: &SymbolReport) -> String {
+    let mut out = String::from("side\ttrade_id\ttrade_time\tevent_time\tupdate_id\tscore\trunner_up\n");
+    let sides: [(&str, &TradeResults, &MatchScores); 2] = [("ask", &report.ask_results, &report.ask_scores), ("bid", &report.bid_results, &report.bid_scores)];
+    let cost = |cost: Option<f64>| cost.map(|cost| format!("{:.6}", cost)).unwrap_or_else(|| String::from("-"));
+    for (side, results, scores) in sides {
+        for (trade_id, trade_time, event_time) in results {
+            let update_id = report.updates.get(trade_id).map(|id| id.to_string()).unwrap_or_else(|| String::from("-"));
+            let score = scores.get(trade_id);
+            out.push_str(&format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\n", side, trade_id, trade_time, event_time, update_id,
+                cost(score.map(|score| score.score)), cost(score.and_then(|score| score.runner_up))));
+        }
+    }
+    out.push_str(&format!("matched\t{}\ndropped\t{}\npurged\t{}\nsequence_gaps\t{}\n", report.matched(), report.dropped(), report.purged(), report.sequence_gaps));
+    out.push_str(&format!("crossed\t{}\nlocked\t{}\n", report.crossed_books.crossed, report.crossed_books.locked));
+    out.push_str(&format!("out_of_book_trades\t{}\nunchecked_trades\t{}\n", report.price_checks.out_of_book_trades, report.price_checks.unchecked_trades));
+    out.push_str(&format!("confident_lags\t{:?}\n", report.confident_lags));
+    out
+}
+
+/// Replays every golden session with `options` and compares with its `<prefix>_<name>.tsv`.
+fn check(name: &str, options: ReplayOptions) {
+    for (session, prefix) in GOLDEN_SESSIONS {
+        check_session(session, &format!("{}_{}.tsv", prefix, name), &options);
+    }
+}
+
+fn check_session(session: &str, name: &str, options: &ReplayOptions) {
+    let report = batch::reconcile_session(&bundled().join(session), options).unwrap();
+    let actual = render(&report);
+    let path = fixture("golden", name);
+    if env::var_os(UPDATE_VAR).is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} - run with {}=1 to create it", path.display(), e, UPDATE_VAR));
+    if actual != expected {
+        let difference = actual.lines().zip(expected.lines())
+            .position(|(actual, expected)| actual != expected)
+            .unwrap_or(actual.lines().count().min(expected.lines().count()));
+        panic!("{} differs from the replay from line {}: expected {:?}, got {:?} - run with {}=1 to accept the new output",
+            path.display(), difference + 1, expected.lines().nth(difference), actual.lines().nth(difference), UPDATE_VAR);
+    }
+}
+
+#[test]
+fn every_bundled_session_has_golden_output_or_no_depth_stream() {
+    let root = bundled();
+    let mut sessions: Vec<String> = batch::discover_sessions(&root).unwrap().iter()
+        .map(|session| session.strip_prefix(&root).unwrap().iter().map(|part| part.to_string_lossy()).collect::<Vec<_>>().join("/"))
+        .collect();
+    sessions.sort();
+    let mut known: Vec<String> = GOLDEN_SESSIONS.iter().map(|(session, _)| *session).chain(SESSIONS_WITHOUT_DEPTH).map(String::from).collect();
+    known.sort();
+    assert_eq!(sessions, known, "bundled sessions changed - add new ones to GOLDEN_SESSIONS with {}=1", UPDATE_VAR);
+    for session in SESSIONS_WITHOUT_DEPTH {
+        assert!(!root.join(session).join("depth.txt").exists(), "{} has a depth stream now - move it to GOLDEN_SESSIONS", session);
+    }
+}
+
+#[test]
+fn first_matching_output_is_unchanged() {
+    check("first", ReplayOptions::new(Exchange::Binance));
+}
+
+#[test]
+fn scored_matching_output_is_unchanged() {
+    check("scored", ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) });
+}
+
+#[test]
+fn aggressor_aggregation_output_is_unchanged() {
+    check("aggressor", ReplayOptions { aggregation: Aggregation::Aggressor, ..ReplayOptions::new(Exchange::Binance) });
+}
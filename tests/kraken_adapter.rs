@@ -0,0 +1,120 @@
+mod common;
+
+use shougoutaku::engine::{self, ReplayOptions, SymbolReport};
+use shougoutaku::exchange::kraken::book_checksum;
+use shougoutaku::exchange::{BookEvent, Exchange, ExchangeAdapter};
+use shougoutaku::orderbook::OrderBook;
+
+use common::{dec, fixture, fixtures, lines, streams};
+
+#[test]
+fn normalises_book_snapshot_and_updates() {
+    let mut adapter = Exchange::Kraken.adapter();
+    let book = lines(&fixture("kraken", "book.txt"));
+    assert!(adapter.parse_book(&book[0]).unwrap().is_empty());
+
+    let BookEvent::Snapshot(snapshot) = adapter.parse_book(&book[1]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    assert_eq!(snapshot.asks.len(), 10);
+    assert_eq!(snapshot.bids[0], (dec("41999.90000"), dec("0.80000000")));
+
+    let BookEvent::Depth(update) = adapter.parse_book(&book[2]).unwrap().remove(0) else { panic!("expected a depth update") };
+    assert_eq!(update.symbol, "XBT/USD");
+    assert_eq!(update.event_time, 1702798596120);
+    assert_eq!(update.final_update_id_in_event, snapshot.last_update_id + 1);
+    assert_eq!(update.asks_to_update, vec![(dec("42000.10000"), dec("1.25000000"))]);
+}
+
+#[test]
+fn validates_checksums_against_the_reconstructed_book() {
+    let mut adapter = Exchange::Kraken.adapter();
+    let mut orderbook = OrderBook::new();
+    let mut verdicts = Vec::new();
+    for line in lines(&fixture("kraken", "book.txt")) {
+        let events = adapter.parse_book(&line).unwrap();
+        if events.is_empty() {
+            continue;
+        }
+        for event in events {
+            match event {
+                BookEvent::Snapshot(snapshot) => orderbook.update_with_snapshot(snapshot),
+                BookEvent::Depth(update) => orderbook.update(update),
+            }
+        }
+        verdicts.push(adapter.verify_book(&mut orderbook).is_ok());
+    }
+    // Only the update whose checksum was corrupted in the fixture fails
+    assert_eq!(verdicts, vec![true, true, true, false, true, true, true]);
+    assert_eq!(orderbook.top_asks(1).next(), Some((dec("42000.10000"), dec("1.00000000"))));
+}
+
+#[test]
+fn checksum_uses_received_scale() {
+    let mut adapter = Exchange::Kraken.adapter();
+    let BookEvent::Snapshot(snapshot) = adapter.parse_book(&lines(&fixture("kraken", "book.txt"))[1]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    let mut orderbook = OrderBook::new();
+    orderbook.update_with_snapshot(snapshot);
+    let checksum = book_checksum(&orderbook);
+
+    let mut rescaled = OrderBook::new();
+    let BookEvent::Snapshot(mut snapshot) = Exchange::Kraken.adapter().parse_book(&lines(&fixture("kraken", "book.txt"))[1]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    for level in snapshot.asks.iter_mut() {
+        level.0.rescale(2);
+    }
+    rescaled.update_with_snapshot(snapshot);
+    assert_ne!(book_checksum(&rescaled), checksum);
+}
+
+#[test]
+fn maps_taker_side_to_book_side() {
+    let mut adapter = Exchange::Kraken.adapter();
+    let ask_trade = adapter.parse_trades(&lines(&fixture("kraken", "ask_trade.txt"))[0]).unwrap().remove(0);
+    assert!(!ask_trade.is_market_maker);
+    assert_eq!(ask_trade.event_time, 1702798596101);
+    let bid_trade = adapter.parse_trades(&lines(&fixture("kraken", "bid_trade.txt"))[0]).unwrap().remove(0);
+    assert!(bid_trade.is_market_maker);
+    assert_eq!((bid_trade.price, bid_trade.quantity), (dec("41999.9"), dec("0.8")));
+}
+
+#[test]
+fn trade_ids_come_from_the_trades_themselves() {
+    let line = r#"[337,[["42000.10000","0.25000000","1702798596.101234","b","m",""],["42000.10000","0.25000000","1702798596.101234","b","m",""]],"trade","XBT/USD"]"#;
+    let ids = |adapter: &mut Box<dyn ExchangeAdapter>| adapter.parse_trades(line).unwrap().into_iter().map(|trade| trade.trade_id).collect::<Vec<_>>();
+    let (mut first, mut second) = (Exchange::Kraken.adapter(), Exchange::Kraken.adapter());
+    let ids_of_first = ids(&mut first);
+    // Equal trades of one message differ by their index
+    assert_ne!(ids_of_first[0], ids_of_first[1]);
+    // Any reader gives a trade the same id
+    assert_eq!(ids(&mut second), ids_of_first);
+    // The first trades of the ask and bid readers no longer share one
+    let ask_trade = Exchange::Kraken.adapter().parse_trades(&lines(&fixture("kraken", "ask_trade.txt"))[0]).unwrap().remove(0);
+    let bid_trade = Exchange::Kraken.adapter().parse_trades(&lines(&fixture("kraken", "bid_trade.txt"))[0]).unwrap().remove(0);
+    assert_ne!(ask_trade.trade_id, bid_trade.trade_id);
+}
+
+fn replay(book: &str) -> SymbolReport {
+    let streams = streams(&fixtures("kraken"), [book, "ask_trade.txt", "bid_trade.txt"]);
+    engine::reconcile(&ReplayOptions::new(Exchange::Kraken), "", None, streams).unwrap()
+}
+
+#[test]
+fn recovers_from_checksum_mismatch_on_next_snapshot() {
+    let report = replay("book.txt");
+    assert_eq!(report.symbol, "XBT/USD");
+    assert_eq!((report.checksum_mismatches, report.desynced_updates), (1, 1));
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    assert_eq!(ask, vec![
+        (String::from("1702798596.101234-42000.10000-0.25000000-b-0"), 1702798596101, 1702798596120),
+        (String::from("1702798596.900001-42000.10000-0.25000000-b-0"), 1702798596900, 1702798596910),
+    ]);
+    let bid: Vec<_> = report.bid_results.into_iter().collect();
+    assert_eq!(bid, vec![(String::from("1702798596.330456-41999.90000-0.80000000-s-0"), 1702798596330, 1702798596340)]);
+}
+
+#[test]
+fn reports_the_book_desynced_without_a_snapshot_to_recover_on() {
+    // A v1 capture holds the snapshot sent on subscription only
+    let report = replay("book_without_resync.txt");
+    assert_eq!((report.checksum_mismatches, report.desynced_updates), (1, 2));
+    // The trade after the mismatch is left unmatched
+    assert_eq!(report.matched(), 2);
+}
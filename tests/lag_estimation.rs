@@ -0,0 +1,55 @@
+mod common;
+
+use shougoutaku::engine::ReplayOptions;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::lag::LagEstimator;
+use shougoutaku::trade_matcher::{Matching, DROPPED, MATCH_WINDOW};
+
+use common::{fixtures, reconcile};
+
+#[test]
+fn fixed_window_ignores_the_lags() {
+    let mut estimator = LagEstimator::new(MATCH_WINDOW, None);
+    estimator.record(1..=100);
+    assert_eq!(estimator.window(), MATCH_WINDOW);
+    assert_eq!(estimator.percentile(50.0), Some(50));
+    assert_eq!(estimator.percentile(99.9), Some(100));
+}
+
+#[test]
+fn adaptive_window_follows_the_lag_percentile() {
+    let mut estimator = LagEstimator::new(MATCH_WINDOW, Some(99.9));
+    // Too few lags to move away from the initial window
+    estimator.record([200; 10]);
+    assert_eq!(estimator.window(), MATCH_WINDOW);
+    estimator.record((1..=90).rev());
+    assert_eq!(estimator.lags().len(), 100);
+    assert!(estimator.lags().windows(2).all(|pair| pair[0] <= pair[1]));
+    // p99.9 of the lags is 200, plus a quarter of headroom
+    assert_eq!(estimator.window(), 250);
+
+    let mut estimator = LagEstimator::new(MATCH_WINDOW, Some(99.9));
+    estimator.record([2; 60]);
+    assert_eq!(estimator.window(), 10);
+}
+
+#[test]
+fn confident_matches_publish_their_lags() {
+    let report = reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }).unwrap();
+    // 903's runner-up is far behind its match and 902 has none
+    assert_eq!(report.confident_lags, vec![5, 10]);
+    assert_eq!(report.window, MATCH_WINDOW);
+}
+
+#[test]
+fn window_bounds_the_lag_of_first_matches() {
+    let report = reconcile(&fixtures("scored"), &ReplayOptions { window: 5, ..ReplayOptions::new(Exchange::Binance) }).unwrap();
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    // 901 and 902 are 10 ms ahead of the next depth event, past the window, leaving the level delta to 903
+    assert_eq!(ask, vec![
+        (String::from("901"), 1702798596100, DROPPED),
+        (String::from("902"), 1702798596140, DROPPED),
+        (String::from("903"), 1702798596105, 1702798596110),
+    ]);
+    assert_eq!(report.confident_lags, vec![5]);
+}
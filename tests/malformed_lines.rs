@@ -0,0 +1,90 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+
+use shougoutaku::engine::{self, ReplayOptions, Streams, SymbolReport};
+use shougoutaku::error::{EngineError, Location};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::malformed::{LinePolicy, MalformedLines, RejectFile};
+
+use common::{CAPTURE_FILES, fixture, fixtures, streams};
+
+/// Replays a fixture folder as BTCUSDT, whose name the stream locations carry.
+fn replay(folder: &str, options: &ReplayOptions) -> Result<SymbolReport, EngineError> {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture(folder, "snapshot.txt")).unwrap();
+    engine::reconcile(options, "BTCUSDT", Some(snapshot), streams(&fixtures(folder), CAPTURE_FILES))
+}
+
+fn options(line_policy: LinePolicy) -> ReplayOptions {
+    ReplayOptions { line_policy, ..ReplayOptions::new(Exchange::Binance) }
+}
+
+#[test]
+fn skipped_lines_leave_the_replay_unchanged() {
+    let clean = replay("scored", &options(LinePolicy::Skip)).unwrap();
+    for policy in [LinePolicy::Skip, LinePolicy::Count] {
+        let report = replay("malformed", &options(policy)).unwrap();
+        // The trades after the bad trade line are still read
+        assert_eq!(report.ask_results, clean.ask_results);
+        assert_eq!(report.malformed_lines, MalformedLines { depth: 1, trades: 1 });
+    }
+    assert_eq!(clean.malformed_lines.total(), 0);
+}
+
+#[test]
+fn abort_names_the_stream_and_line() {
+    match replay("malformed", &options(LinePolicy::Abort)).unwrap_err() {
+        EngineError::Parse { location: Some(location), .. } => {
+            assert_eq!(location, Location { file: String::from("BTCUSDT ask trades stream"), line: Some(2) });
+        }
+        other => panic!("expected a parse error, got {:?}", other),
+    }
+}
+
+#[test]
+fn quarantine_writes_the_rejected_lines() {
+    let path = std::env::temp_dir().join(format!("shougoutaku-rejects-{}.txt", std::process::id()));
+    let options = ReplayOptions {
+        reject_file: Some(RejectFile::create(&path).unwrap()),
+        ..options(LinePolicy::Quarantine)
+    };
+    let report = replay("malformed", &options).unwrap();
+    assert_eq!(report.malformed_lines.total(), 2);
+    drop(options);
+    let rejects = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let fields: Vec<Vec<&str>> = rejects.lines().map(|line| line.split('\t').collect()).collect();
+    assert_eq!(fields.len(), 2);
+    assert_eq!(fields[0][..3], ["BTCUSDT", "ask trades", "2"]);
+    assert!(fields[0][4].contains("4200O.10"));
+    assert_eq!(fields[1][..3], ["BTCUSDT", "depth", "2"]);
+    assert_eq!(fields[1][4], r#"{"e":"depthUpdate","E":1702798596130,"s":"BTCUSDT","U":"#);
+}
+
+#[test]
+fn routed_lines_keep_their_line_numbers() {
+    let path = std::env::temp_dir().join(format!("shougoutaku-routed-rejects-{}.txt", std::process::id()));
+    let options = ReplayOptions {
+        reject_file: Some(RejectFile::create(&path).unwrap()),
+        ..options(LinePolicy::Quarantine)
+    };
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("malformed", "snapshot.txt")).unwrap();
+    let depth = fs::read_to_string(fixture("malformed", "depth.txt")).unwrap().lines()
+        // A symbol that routes the line, but no depth event
+        .chain([r#"{"e":"depthUpdate","s":"BTCUSDT"}"#])
+        .map(String::from)
+        .collect();
+    let Streams { trades, .. } = streams(&fixtures("malformed"), CAPTURE_FILES);
+    let streams = Streams { depth: engine::lines_of(depth), trades };
+    let replay = engine::reconcile_symbols(&options, HashMap::from([(String::from("BTCUSDT"), snapshot)]), streams).unwrap();
+    // The truncated depth line has no readable symbol and is counted by no symbol's report
+    assert_eq!(replay.reports[0].malformed_lines, MalformedLines { depth: 1, trades: 1 });
+    assert_eq!(replay.unroutable, MalformedLines { depth: 1, trades: 0 });
+    drop(options);
+    let rejects = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let mut fields: Vec<Vec<&str>> = rejects.lines().map(|line| line.split('\t').take(3).collect()).collect();
+    fields.sort();
+    assert_eq!(fields, vec![vec!["", "depth", "2"], vec!["BTCUSDT", "ask trades", "2"], vec!["BTCUSDT", "depth", "5"]]);
+}
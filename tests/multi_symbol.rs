@@ -0,0 +1,42 @@
+mod common;
+
+use std::collections::HashMap;
+
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::exchange::Exchange;
+
+use common::{CAPTURE_FILES, fixture, fixtures, streams};
+
+#[test]
+fn routes_each_symbol_to_its_own_book_and_report() {
+    let snapshots: HashMap<String, _> = ["ethusdt", "BTCUSDT"].into_iter()
+        .map(|symbol| (symbol.to_string(), engine::load_snapshot(Exchange::Binance, &fixture("multi_symbol", &format!("{}_snapshot.txt", symbol.to_lowercase()))).unwrap()))
+        .collect();
+    let reports = engine::reconcile_symbols(&ReplayOptions::new(Exchange::Binance), snapshots, streams(&fixtures("multi_symbol"), CAPTURE_FILES)).unwrap().reports;
+    // One report per symbol, in symbol order whichever symbol the streams start with
+    let symbols: Vec<_> = reports.iter().map(|report| report.symbol.as_str()).collect();
+    assert_eq!(symbols, ["BTCUSDT", "ETHUSDT"]);
+    let results = |index: usize| {
+        let report = &reports[index];
+        (report.ask_results.iter().cloned().collect::<Vec<_>>(), report.bid_results.iter().cloned().collect::<Vec<_>>())
+    };
+    assert_eq!(results(0), (
+        vec![(String::from("701"), 1702798596100, 1702798596120)],
+        vec![(String::from("702"), 1702798596330, 1702798596340)],
+    ));
+    assert_eq!(results(1), (
+        vec![(String::from("801"), 1702798596105, 1702798596110)],
+        vec![(String::from("802"), 1702798596240, 1702798596250)],
+    ));
+    // Each book follows its own update ids
+    assert!(reports.iter().all(|report| report.sequence_gaps == 0));
+    assert_eq!(reports[1].updates[&String::from("802")], 502);
+}
+
+#[test]
+fn replays_only_the_symbols_given_a_snapshot() {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("multi_symbol", "ethusdt_snapshot.txt")).unwrap();
+    let reports = engine::reconcile_symbols(&ReplayOptions::new(Exchange::Binance), HashMap::from([(String::from("ETHUSDT"), snapshot)]), streams(&fixtures("multi_symbol"), CAPTURE_FILES)).unwrap().reports;
+    assert_eq!(reports.len(), 1);
+    assert_eq!((reports[0].symbol.as_str(), reports[0].matched()), ("ETHUSDT", 2));
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::exchange::okx::book_checksum;
+use shougoutaku::exchange::{BookEvent, Exchange};
+use shougoutaku::orderbook::OrderBook;
+
+use common::{dec, fixture, fixtures, lines, streams};
+
+#[test]
+fn maps_sequence_ids_onto_update_ids() {
+    let mut adapter = Exchange::Okx.adapter();
+    let books = lines(&fixture("okx", "books.txt"));
+    assert!(adapter.parse_book(&books[0]).unwrap().is_empty());
+
+    let BookEvent::Snapshot(snapshot) = adapter.parse_book(&books[1]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    assert_eq!(snapshot.last_update_id, 100);
+    assert_eq!(snapshot.bids[0], (dec("42000.0"), dec("1.5")));
+
+    let BookEvent::Depth(update) = adapter.parse_book(&books[2]).unwrap().remove(0) else { panic!("expected a depth update") };
+    assert_eq!(update.symbol, "BTC-USDT");
+    assert_eq!(update.event_time, 1702798596120);
+    assert_eq!(update.previous_final_update_id, Some(100));
+    assert_eq!(update.final_update_id_in_event, 101);
+    assert_eq!(update.asks_to_update, vec![(dec("42000.1"), dec("0.5"))]);
+}
+
+#[test]
+fn snapshot_checksum_matches() {
+    let mut adapter = Exchange::Okx.adapter();
+    let BookEvent::Snapshot(snapshot) = adapter.parse_book(&lines(&fixture("okx", "books.txt"))[1]).unwrap().remove(0) else { panic!("expected a snapshot") };
+    let mut orderbook = OrderBook::new();
+    orderbook.update_with_snapshot(snapshot);
+    assert_eq!(book_checksum(&orderbook), -2082869060);
+    assert!(adapter.verify_book(&mut orderbook).is_ok());
+}
+
+#[test]
+fn maps_taker_side_to_book_side() {
+    let mut adapter = Exchange::Okx.adapter();
+    assert!(adapter.parse_trades(&lines(&fixture("okx", "ask_trades.txt"))[0]).unwrap().is_empty());
+    let ask_trade = adapter.parse_trades(&lines(&fixture("okx", "ask_trades.txt"))[1]).unwrap().remove(0);
+    assert!(!ask_trade.is_market_maker);
+    assert_eq!(ask_trade.trade_id, "501");
+    let bid_trade = adapter.parse_trades(&lines(&fixture("okx", "bid_trades.txt"))[0]).unwrap().remove(0);
+    assert!(bid_trade.is_market_maker);
+    assert_eq!(bid_trade.event_time, 1702798596330);
+}
+
+#[test]
+fn reconciles_with_gaps_and_checksum_recovery() {
+    let streams = streams(&fixtures("okx"), ["books.txt", "ask_trades.txt", "bid_trades.txt"]);
+    let report = engine::reconcile(&ReplayOptions::new(Exchange::Okx), "", None, streams).unwrap();
+    assert_eq!(report.symbol, "BTC-USDT");
+    // prevSeqId 105 does not follow seqId 102
+    assert_eq!(report.sequence_gaps, 1);
+    assert_eq!(report.checksum_mismatches, 1);
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    assert_eq!(ask, vec![
+        (String::from("501"), 1702798596100, 1702798596120),
+        (String::from("504"), 1702798596900, 1702798596910),
+    ]);
+    let bid: Vec<_> = report.bid_results.into_iter().collect();
+    assert_eq!(bid, vec![(String::from("502"), 1702798596330, 1702798596340)]);
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::OrderBook;
+use shougoutaku::trade_matcher::TradeType;
+
+const SNAPSHOT_UPDATE_ID: u64 = 100;
+
+type Levels = BTreeMap<Decimal, Decimal>;
+/// Levels of one side of a book, best first.
+type Side = Vec<(Decimal, Decimal)>;
+
+/// Prices on a small grid, so that events keep hitting the same levels.
+fn price() -> impl Strategy<Value = Decimal> {
+    (0i64..40).prop_map(|tick| Decimal::new(4_200_000 + tick, 2))
+}
+
+/// Quantities with a good share of level removals.
+fn quantity() -> impl Strategy<Value = Decimal> {
+    prop_oneof![Just(Decimal::ZERO), (1i64..500).prop_map(|lots| Decimal::new(lots, 3))]
+}
+
+fn side() -> impl Strategy<Value = Levels> {
+    prop::collection::btree_map(price(), quantity(), 0..8)
+}
+
+/// Changes of one depth event and the number of update ids it spans.
+fn event() -> impl Strategy<Value = (Levels, Levels, u64)> {
+    (side(), side(), 1u64..4)
+}
+
+fn snapshot(bids: &Levels, asks: &Levels, last_update_id: u64) -> SnapShotUpdate {
+    SnapShotUpdate {
+        last_update_id,
+        bids: bids.iter().rev().map(|(price, quantity)| (*price, *quantity)).collect(),
+        asks: asks.iter().map(|(price, quantity)| (*price, *quantity)).collect(),
+    }
+}
+
+/// Depth events chaining from the snapshot, levels sent best first as Binance does.
+fn depth_updates(events: &[(Levels, Levels, u64)]) -> Vec<DepthUpdate> {
+    let mut final_update_id = SNAPSHOT_UPDATE_ID;
+    events.iter()
+        .enumerate()
+        .map(|(index, (bids, asks, span))| {
+            let first_update_id = final_update_id + 1;
+            final_update_id += span;
+            DepthUpdate {
+                event_type: String::from("depthUpdate"),
+                event_time: 1_000 + index as u64 * 100,
+                symbol: String::from("BTCUSDT"),
+                first_update_id_in_event: first_update_id,
+                final_update_id_in_event: final_update_id,
+                bids_to_update: bids.iter().rev().map(|(price, quantity)| (*price, *quantity)).collect(),
+                asks_to_update: asks.iter().map(|(price, quantity)| (*price, *quantity)).collect(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Bid and ask levels of a book.
+fn levels(book: &OrderBook) -> (Side, Side) {
+    (
+        book.top_bids(usize::MAX).collect(),
+        book.top_asks(usize::MAX).collect(),
+    )
+}
+
+fn apply(levels: &mut Levels, changes: &Levels) {
+    for (price, quantity) in changes {
+        if quantity.is_zero() {
+            levels.remove(price);
+        } else {
+            levels.insert(*price, *quantity);
+        }
+    }
+}
+
+fn book_from(bids: &Levels, asks: &Levels) -> OrderBook {
+    let mut book = OrderBook::new();
+    book.update_with_snapshot(snapshot(bids, asks, SNAPSHOT_UPDATE_ID));
+    book
+}
+
+proptest! {
+    #[test]
+    fn levels_are_sorted_and_never_empty(bids in side(), asks in side(), events in prop::collection::vec(event(), 0..20)) {
+        let mut book = book_from(&bids, &asks);
+        for update in depth_updates(&events) {
+            book.update(update);
+            let (bids, asks) = levels(&book);
+            prop_assert!(bids.iter().chain(asks.iter()).all(|(_, quantity)| *quantity > Decimal::ZERO));
+            prop_assert!(bids.windows(2).all(|pair| pair[0].0 > pair[1].0));
+            prop_assert!(asks.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+    }
+
+    #[test]
+    fn snapshot_and_diffs_give_the_later_snapshot(bids in side(), asks in side(), events in prop::collection::vec(event(), 0..20)) {
+        let mut book = book_from(&bids, &asks);
+        let (mut later_bids, mut later_asks) = (bids.clone(), asks.clone());
+        let updates = depth_updates(&events);
+        for (update, (bid_changes, ask_changes, _)) in updates.into_iter().zip(events.iter()) {
+            book.update(update);
+            apply(&mut later_bids, bid_changes);
+            apply(&mut later_asks, ask_changes);
+        }
+        let last_update_id = SNAPSHOT_UPDATE_ID + events.iter().map(|(_, _, span)| span).sum::<u64>();
+        let mut later = OrderBook::new();
+        later.update_with_snapshot(snapshot(&later_bids, &later_asks, last_update_id));
+        prop_assert_eq!(levels(&book), levels(&later));
+        prop_assert_eq!(book.sequence_gaps(), 0);
+    }
+
+    #[test]
+    fn replaying_applied_events_changes_nothing(bids in side(), asks in side(), events in prop::collection::vec(event(), 1..20)) {
+        let mut book = book_from(&bids, &asks);
+        let updates = depth_updates(&events);
+        for update in &updates {
+            book.update(update.clone());
+        }
+        let replayed = levels(&book);
+        for update in &updates {
+            book.update(update.clone());
+            prop_assert!(!book.is_best_bid_updated() && !book.is_best_ask_updated());
+        }
+        prop_assert_eq!(levels(&book), replayed);
+        prop_assert_eq!(book.sequence_gaps(), 0);
+    }
+
+    #[test]
+    fn level_deltas_match_the_level_changes(bids in side(), asks in side(), events in prop::collection::vec(event(), 1..20)) {
+        let mut book = book_from(&bids, &asks);
+        for update in depth_updates(&events) {
+            let (bids_before, asks_before) = levels(&book);
+            let changed = [
+                (TradeType::Bid, bids_before, update.bids_to_update.clone()),
+                (TradeType::Ask, asks_before, update.asks_to_update.clone()),
+            ];
+            book.update(update);
+            let (bids_after, asks_after) = levels(&book);
+            for (side, before, changes) in changed {
+                let after = match side {
+                    TradeType::Bid => &bids_after,
+                    TradeType::Ask => &asks_after,
+                };
+                let quantity = |levels: &[(Decimal, Decimal)], price: Decimal| {
+                    levels.iter().find(|(level, _)| *level == price).map_or(Decimal::ZERO, |(_, quantity)| *quantity)
+                };
+                let deltas: Vec<(Decimal, Decimal)> = book.best_deltas(side).iter()
+                    .map(|delta| book.representation().to_level((delta.price(), delta.volume())))
+                    .collect();
+                for (delta_price, delta_volume) in &deltas {
+                    prop_assert!(before.iter().any(|(price, _)| price == delta_price));
+                    prop_assert_eq!(*delta_volume, quantity(&before, *delta_price) - quantity(after, *delta_price));
+                }
+                // A change of the best level is always the first delta
+                if let Some((best, _)) = before.first() {
+                    if changes.iter().any(|(price, _)| price == best) {
+                        prop_assert_eq!(deltas.first().map(|(price, _)| *price), Some(*best));
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use shougoutaku::engine::{self, ReplayOptions};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::messages::SnapShotUpdate;
+use shougoutaku::orderbook::OrderBook;
+use shougoutaku::price_check::{spread, PriceChecks, Spread};
+
+use common::{fixture, fixtures, streams};
+
+fn book(bid: &str, ask: &str) -> OrderBook {
+    let mut orderbook = OrderBook::new();
+    orderbook.update_with_snapshot(SnapShotUpdate {
+        last_update_id: 1,
+        bids: vec![(bid.parse().unwrap(), "1".parse().unwrap())],
+        asks: vec![(ask.parse().unwrap(), "1".parse().unwrap())],
+    });
+    orderbook
+}
+
+#[test]
+fn classifies_spreads() {
+    assert_eq!(spread(&book("1.0", "1.1")), Spread::Open);
+    assert_eq!(spread(&book("1.1", "1.1")), Spread::Locked);
+    assert_eq!(spread(&book("1.2", "1.1")), Spread::Crossed);
+    assert_eq!(spread(&OrderBook::new()), Spread::OneSided);
+}
+
+#[test]
+fn checks_trades_against_the_book_at_trade_time() {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("price_check", "snapshot.txt")).unwrap();
+    let streams = streams(&fixtures("price_check"), ["depth.txt", "ask_trade.txt", "bid_trade.txt"]);
+    let report = engine::reconcile(&ReplayOptions::new(Exchange::Binance), "", Some(snapshot), streams).unwrap();
+    assert_eq!((report.crossed_books.crossed, report.crossed_books.locked), (1, 1));
+    assert_eq!(report.price_checks, PriceChecks {
+        // 802 sold at 42000.10 while the best bid was 42000.00
+        out_of_book_trades: 1,
+        // 803 happened while the book was crossed
+        unchecked_trades: 1,
+    });
+}
+
+#[test]
+fn checks_trades_read_after_the_book_moved_on_at_their_own_time() {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("price_check_clock", "snapshot.txt")).unwrap();
+    let streams = streams(&fixtures("price_check_clock"), ["depth.txt", "ask_trade.txt", "bid_trade.txt"]);
+    let report = engine::reconcile(&ReplayOptions::new(Exchange::Binance), "", Some(snapshot), streams).unwrap();
+    // 904 is past the three timestamps the ask matcher reads ahead, but happened while
+    // 42000.10 was still the best ask; 905 sold into the best bid from the ask stream
+    assert_eq!(report.mislabelled_trades, 1);
+    assert_eq!(report.price_checks, PriceChecks {
+        // 906 bought at 42000.05 while the best bid was 42000.00
+        out_of_book_trades: 1,
+        unchecked_trades: 0,
+    });
+}
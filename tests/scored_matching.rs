@@ -0,0 +1,58 @@
+mod common;
+
+use shougoutaku::assignment::min_cost_assignment;
+use shougoutaku::engine::ReplayOptions;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::trade_matcher::{Matching, DROPPED, PURGED};
+
+use common::{fixtures, reconcile};
+
+#[test]
+fn assignment_minimises_the_total_cost() {
+    let inf = f64::INFINITY;
+    let costs = vec![
+        vec![1.0, 2.0, inf],
+        vec![1.5, inf, inf],
+        vec![inf, 0.5, 3.0],
+    ];
+    // Greedy would give row 0 column 0 and leave row 1 unassigned
+    assert_eq!(min_cost_assignment(&costs), vec![Some(1), Some(0), Some(2)]);
+}
+
+#[test]
+fn assignment_leaves_rows_without_admissible_columns_unassigned() {
+    let inf = f64::INFINITY;
+    assert_eq!(min_cost_assignment(&[vec![0.3], vec![0.1], vec![inf]]), vec![None, Some(0), None]);
+    assert_eq!(min_cost_assignment(&[vec![inf, inf]]), vec![None]);
+    assert!(min_cost_assignment(&[]).is_empty());
+}
+
+#[test]
+fn first_matching_takes_exact_matches_in_queue_order() {
+    let ask: Vec<_> = reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::First, ..ReplayOptions::new(Exchange::Binance) }).unwrap().ask_results.into_iter().collect();
+    // The last depth event leaves the best ask alone, so the unmatched trades are only purged
+    assert_eq!(ask, vec![
+        (String::from("901"), 1702798596100, 1702798596110),
+        (String::from("902"), 1702798596140, PURGED),
+        (String::from("903"), 1702798596105, PURGED),
+    ]);
+}
+
+#[test]
+fn scored_matching_assigns_candidates_one_to_one() {
+    let report = reconcile(&fixtures("scored"), &ReplayOptions { matching: Matching::Scored, ..ReplayOptions::new(Exchange::Binance) }).unwrap();
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    // 903 is closer to the only exact candidate than 901; 902 takes the larger delta
+    assert_eq!(ask, vec![
+        (String::from("901"), 1702798596100, DROPPED),
+        (String::from("902"), 1702798596140, 1702798596150),
+        (String::from("903"), 1702798596105, 1702798596110),
+    ]);
+    let exact = report.ask_scores["903"];
+    assert!((exact.score - 0.05).abs() < 1e-9);
+    assert!(exact.runner_up.is_some_and(|runner_up| runner_up > exact.score));
+    let residual = report.ask_scores["902"];
+    assert!((residual.score - (0.1 + 0.05 / 0.3)).abs() < 1e-9);
+    assert_eq!(residual.runner_up, None);
+    assert!(!report.ask_scores.contains_key("901"));
+}
@@ -0,0 +1,74 @@
+use std::fs;
+
+use shougoutaku::batch;
+use shougoutaku::engine::{self, ReplayOptions, SymbolReport};
+use shougoutaku::evaluation;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::messages::DepthUpdate;
+use shougoutaku::simulation::{simulate, Simulation, SimulationConfig};
+
+fn replay(simulation: &Simulation) -> SymbolReport {
+    let snapshot = Exchange::Binance.adapter().parse_snapshot(&simulation.snapshot).unwrap();
+    engine::reconcile(&ReplayOptions::new(Exchange::Binance), "", Some(snapshot), simulation.streams()).unwrap()
+}
+
+fn short(config: SimulationConfig) -> SimulationConfig {
+    SimulationConfig { duration: 5_000, ..config }
+}
+
+#[test]
+fn simulation_is_reproducible() {
+    let first = simulate(&short(SimulationConfig::default()));
+    let again = simulate(&short(SimulationConfig::default()));
+    assert_eq!(first.depth, again.depth);
+    assert_eq!(first.ask_trades, again.ask_trades);
+    assert_eq!(first.labels, again.labels);
+    let other = simulate(&short(SimulationConfig { seed: 2, ..Default::default() }));
+    assert_ne!(first.depth, other.depth);
+}
+
+#[test]
+fn every_trade_is_labelled_with_a_depth_event() {
+    let simulation = simulate(&short(SimulationConfig::default()));
+    assert!(!simulation.depth.is_empty());
+    assert_eq!(simulation.labels.len(), simulation.ask_trades.len() + simulation.bid_trades.len());
+    let last: DepthUpdate = serde_json::from_str(simulation.depth.last().unwrap()).unwrap();
+    assert!(simulation.labels.values().flatten().all(|update_id| (1001..=last.final_update_id_in_event).contains(update_id)));
+    let report = replay(&simulation);
+    assert_eq!(report.sequence_gaps, 0);
+    assert_eq!(report.crossed_books.crossed + report.crossed_books.locked, 0);
+}
+
+#[test]
+fn any_latency_jitter_simulates() {
+    let simulation = simulate(&short(SimulationConfig { latency_jitter: u64::MAX, ..Default::default() }));
+    assert!(!simulation.ask_trades.is_empty() || !simulation.bid_trades.is_empty());
+}
+
+#[test]
+fn clean_flow_is_matched_without_errors() {
+    let config = short(SimulationConfig {
+        latency_jitter: 0,
+        sweep_probability: 0.0,
+        iceberg_probability: 0.0,
+        cancel_probability: 0.0,
+        ..Default::default()
+    });
+    let simulation = simulate(&config);
+    let evaluation = evaluation::evaluate(&simulation.labels, &[replay(&simulation)]);
+    assert_eq!(evaluation.precision(), Some(1.0));
+    assert!(evaluation.recall().is_some_and(|recall| recall > 0.9));
+}
+
+#[test]
+fn written_session_replays_like_a_capture() {
+    let simulation = simulate(&short(SimulationConfig::default()));
+    let session = std::env::temp_dir().join(format!("shougoutaku-simulation-{}", std::process::id())).join("btcusdt/1");
+    simulation.write(&session).unwrap();
+    let report = batch::reconcile_session(&session, &ReplayOptions::new(Exchange::Binance)).unwrap();
+    let labels = evaluation::load_labels(&session.join("labels.txt")).unwrap();
+    fs::remove_dir_all(session.parent().unwrap().parent().unwrap()).unwrap();
+    assert_eq!(report.symbol, "BTCUSDT");
+    assert_eq!(labels, simulation.labels);
+    assert_eq!(evaluation::evaluate(&labels, &[report]).unlabelled, 0);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::collections::HashMap;
+
+use shougoutaku::engine::{self, ReplayOptions, Streams, SymbolReport, TradeStreams};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::trade_matcher::PURGED;
+
+use common::fixture;
+
+fn replay(trades: TradeStreams) -> SymbolReport {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("single_stream", "snapshot.txt")).unwrap();
+    let streams = Streams { depth: engine::open_lines(&fixture("single_stream", "depth.txt")).unwrap(), trades };
+    engine::reconcile(&ReplayOptions::new(Exchange::Binance), "", Some(snapshot), streams).unwrap()
+}
+
+#[test]
+fn derives_sides_from_a_single_stream() {
+    let report = replay(TradeStreams::Single(engine::open_lines(&fixture("single_stream", "trades.txt")).unwrap()));
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    assert_eq!(ask, vec![(String::from("701"), 1702798596100, 1702798596120)]);
+    let bid: Vec<_> = report.bid_results.into_iter().collect();
+    assert_eq!(bid, vec![(String::from("702"), 1702798596330, 1702798596340)]);
+    assert_eq!(report.mislabelled_trades, 0);
+}
+
+#[test]
+fn reports_mislabelled_trades_of_side_specific_streams() {
+    let report = replay(TradeStreams::BySide {
+        ask: engine::open_lines(&fixture("single_stream", "mislabelled_ask_trade.txt")).unwrap(),
+        bid: engine::open_lines(&fixture("single_stream", "mislabelled_bid_trade.txt")).unwrap(),
+    });
+    assert_eq!(report.mislabelled_trades, 1);
+    // The mislabelled trade is still matched against the side of its stream
+    let ask: Vec<_> = report.ask_results.into_iter().collect();
+    assert_eq!(ask, vec![
+        (String::from("701"), 1702798596100, 1702798596120),
+        (String::from("702"), 1702798596330, PURGED),
+    ]);
+}
+
+#[test]
+fn routes_a_single_stream_by_symbol() {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("single_stream", "snapshot.txt")).unwrap();
+    let streams = Streams {
+        depth: engine::open_lines(&fixture("single_stream", "depth.txt")).unwrap(),
+        trades: TradeStreams::Single(engine::open_lines(&fixture("single_stream", "trades.txt")).unwrap()),
+    };
+    let reports = engine::reconcile_symbols(&ReplayOptions::new(Exchange::Binance), HashMap::from([(String::from("btcusdt"), snapshot)]), streams).unwrap().reports;
+    assert_eq!(reports.len(), 1);
+    assert_eq!(reports[0].matched(), 2);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use std::fs;
+
+use shougoutaku::batch;
+use shougoutaku::book_state::{self, BookFormat, SNAPSHOTS_DIR};
+use shougoutaku::checkpoint::Checkpointing;
+use shougoutaku::engine::{self, ReplayOptions, Streams, SymbolReport};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::messages::{DepthUpdate, SnapShotUpdate};
+use shougoutaku::orderbook::{CrossRepair, OrderBook};
+
+use common::{CAPTURE_FILES, capture, lines, session, streams};
+
+fn depth_events() -> Vec<DepthUpdate> {
+    lines(&session("depth.txt")).iter().map(|line| serde_json::from_str(line).unwrap()).collect()
+}
+
+fn snapshot() -> SnapShotUpdate {
+    engine::load_snapshot(Exchange::Binance, &session("snapshot.txt")).unwrap()
+}
+
+/// The book the exchange would have sent at `update_id`, from replaying the capture to the
+/// event ending at or before it.
+fn taken_at(update_id: u64) -> SnapShotUpdate {
+    let depth = engine::open_lines(&session("depth.txt")).unwrap();
+    let book_at = book_state::replay_to(Exchange::Binance, CrossRepair::None, snapshot(), depth, update_id).unwrap();
+    SnapShotUpdate { last_update_id: update_id, ..book_at.book.to_snapshot() }
+}
+
+/// The book after the event ending at `final_update_id`, as if taken within that event.
+fn taken_within(final_update_id: u64) -> SnapShotUpdate {
+    SnapShotUpdate { last_update_id: final_update_id - 1, ..taken_at(final_update_id) }
+}
+
+fn replay(options: &ReplayOptions, validation: Vec<SnapShotUpdate>) -> SymbolReport {
+    engine::reconcile_validated(options, "", Some(snapshot()), validation, streams(&capture(), CAPTURE_FILES)).unwrap()
+}
+
+#[test]
+fn a_correct_replay_does_not_drift_from_its_capture_snapshots() {
+    let events = depth_events();
+    let exact = events[20].final_update_id_in_event;
+    let within = events.iter().find(|event| event.first_update_id_in_event < event.final_update_id_in_event && event.final_update_id_in_event > exact).unwrap();
+    let last = events.last().unwrap().final_update_id_in_event;
+    let report = replay(&ReplayOptions::new(Exchange::Binance), vec![taken_within(within.final_update_id_in_event), taken_at(exact), taken_at(last)]);
+
+    let checked: Vec<u64> = report.snapshot_checks.iter().map(|check| check.snapshot_update_id).collect();
+    assert_eq!(checked, vec![exact, within.final_update_id_in_event - 1, last]);
+    assert_eq!(report.drifted_snapshots(), 0);
+    assert!(report.snapshot_checks[0].is_match() && report.snapshot_checks[2].is_match());
+    // The event straddling the second snapshot's update id explains all its mismatches
+    let in_flight = &report.snapshot_checks[1];
+    assert_eq!(in_flight.book_update_id, within.first_update_id_in_event - 1);
+    assert!(!in_flight.is_match());
+    assert!(in_flight.mismatches.iter().all(|mismatch| mismatch.in_flight));
+
+    let unchecked = replay(&ReplayOptions::new(Exchange::Binance), Vec::new());
+    assert_eq!((report.matched(), report.dropped(), report.purged()), (unchecked.matched(), unchecked.dropped(), unchecked.purged()));
+}
+
+#[test]
+fn drift_is_flagged_at_the_mismatched_levels() {
+    let update_id = depth_events()[40].final_update_id_in_event;
+    let mut drifted = taken_at(update_id);
+    let (price, quantity) = drifted.asks[2];
+    drifted.asks[2] = (price, quantity + quantity);
+    let report = replay(&ReplayOptions::new(Exchange::Binance), vec![drifted]);
+    assert_eq!(report.drifted_snapshots(), 1);
+    let check = &report.snapshot_checks[0];
+    assert_eq!(check.mismatches.len(), 1);
+    let mismatch = &check.mismatches[0];
+    assert_eq!((mismatch.price, mismatch.book, mismatch.snapshot, mismatch.in_flight), (price, Some(quantity), Some(quantity + quantity), false));
+}
+
+#[test]
+fn snapshots_outside_the_replay_are_not_checked() {
+    let before = SnapShotUpdate { last_update_id: snapshot().last_update_id - 10, ..snapshot() };
+    let after = SnapShotUpdate { last_update_id: depth_events().last().unwrap().final_update_id_in_event + 1, ..snapshot() };
+    let report = replay(&ReplayOptions::new(Exchange::Binance), vec![after, before]);
+    assert!(report.snapshot_checks.is_empty());
+}
+
+#[test]
+fn resumed_replays_keep_their_snapshot_checks() {
+    let events = depth_events();
+    let validation: Vec<SnapShotUpdate> = [10, 30, 50, 70].iter()
+        .map(|&index| taken_within(events[index].final_update_id_in_event))
+        .collect();
+    let expected = replay(&ReplayOptions::new(Exchange::Binance), validation.clone());
+
+    let path = std::env::temp_dir().join(format!("shougoutaku-validation-{}.json", std::process::id()));
+    let checkpointing = Checkpointing { every: 20, ..Checkpointing::new(&path) };
+    let options = ReplayOptions { checkpointing: Some(checkpointing.clone()), ..ReplayOptions::new(Exchange::Binance) };
+    let depth: Vec<String> = lines(&session("depth.txt")).into_iter().take(45).collect();
+    let streams = Streams { depth: engine::lines_of(depth), ..streams(&capture(), CAPTURE_FILES) };
+    engine::reconcile_validated(&options, "", Some(snapshot()), validation.clone(), streams).unwrap();
+    let options = ReplayOptions { checkpointing: Some(Checkpointing { resume: true, ..checkpointing }), ..options };
+    let resumed = replay(&options, validation);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(resumed.snapshot_checks, expected.snapshot_checks);
+    assert_eq!(resumed.snapshot_checks.len(), 4);
+}
+
+#[test]
+fn batch_sessions_are_checked_against_their_snapshots_folder() {
+    let root = std::env::temp_dir().join(format!("shougoutaku-validation-batch-{}", std::process::id()));
+    let folder = root.join("btcusdt").join("1702798595534677");
+    fs::create_dir_all(folder.join(SNAPSHOTS_DIR)).unwrap();
+    for file in ["snapshot.txt", "depth.txt", "ask_trade.txt", "bid_trade.txt"] {
+        fs::copy(session(file), folder.join(file)).unwrap();
+    }
+    let events = depth_events();
+    let mut drifted = taken_at(events[60].final_update_id_in_event);
+    drifted.bids.remove(0);
+    for (name, snapshot) in [("1702798597000.txt", taken_at(events[30].final_update_id_in_event)), ("1702798599000.txt", drifted)] {
+        let book = OrderBook::from_snapshot(snapshot.clone());
+        book_state::save(&folder.join(SNAPSHOTS_DIR).join(name), &book, BookFormat::Json).unwrap();
+    }
+    let report = batch::reconcile_session(&folder, &ReplayOptions::new(Exchange::Binance));
+    fs::remove_dir_all(&root).unwrap();
+    let report = report.unwrap();
+    assert_eq!(report.snapshot_checks.len(), 2);
+    assert_eq!(report.drifted_snapshots(), 1);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use std::io;
+
+use shougoutaku::engine::{self, LineSource, ReplayOptions, Streams, SymbolReport, TradeStreams};
+use shougoutaku::error::EngineError;
+use shougoutaku::exchange::Exchange;
+use shougoutaku::malformed::MalformedLines;
+use shougoutaku::trade_matcher::{TradeResults, DROPPED, PURGED};
+
+use common::fixture;
+
+/// Lines of a fixture file as read, so that tests can swap read failures in.
+fn read_lines(name: &str) -> Vec<io::Result<String>> {
+    engine::open_lines(&fixture("stream_ends", name)).unwrap().collect()
+}
+
+fn source(lines: Vec<io::Result<String>>) -> LineSource {
+    Box::new(lines.into_iter())
+}
+
+fn replay(depth: Vec<io::Result<String>>, ask: Vec<io::Result<String>>) -> Result<SymbolReport, EngineError> {
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("stream_ends", "snapshot.txt")).unwrap();
+    let streams = Streams {
+        depth: source(depth),
+        trades: TradeStreams::BySide { ask: source(ask), bid: source(read_lines("bid_trade.txt")) },
+    };
+    engine::reconcile(&ReplayOptions::new(Exchange::Binance), "BTCUSDT", Some(snapshot), streams)
+}
+
+fn event_time(results: &TradeResults, trade_id: &str) -> Option<u64> {
+    results.iter().find(|(id, _, _)| id == trade_id).map(|(_, _, event_time)| *event_time)
+}
+
+#[test]
+fn a_side_emptied_by_drops_reads_its_next_trades() {
+    let report = replay(read_lines("depth.txt"), read_lines("ask_trade.txt")).unwrap();
+    for trade_id in ["901", "902", "903"] {
+        assert_eq!(event_time(&report.ask_results, trade_id), Some(DROPPED));
+    }
+    // Read after the first three trades were all dropped without a single match
+    assert_eq!(event_time(&report.ask_results, "904"), Some(1702798596510));
+    assert_eq!(event_time(&report.ask_results, "905"), Some(PURGED));
+}
+
+#[test]
+fn trades_left_unread_at_the_end_of_the_book_stream_are_purged() {
+    let report = replay(read_lines("depth.txt"), read_lines("ask_trade.txt")).unwrap();
+    // The bid side never moves: its preloaded trades and the rest of its stream are all purged
+    assert_eq!(report.bid_results.len(), 5);
+    assert!(report.bid_results.iter().all(|(_, _, event_time)| *event_time == PURGED));
+    assert_eq!(report.matched() + report.dropped() + report.purged(), 10);
+}
+
+#[test]
+fn depth_is_replayed_after_a_trade_stream_ends() {
+    let ask = read_lines("ask_trade.txt").into_iter().take(1).collect();
+    let report = replay(read_lines("depth.txt"), ask).unwrap();
+    assert_eq!(event_time(&report.ask_results, "901"), Some(DROPPED));
+    assert_eq!(report.ask_results.len(), 1);
+}
+
+#[test]
+fn read_failures_stop_the_replay() {
+    let mut ask = read_lines("ask_trade.txt");
+    ask[1] = Err(io::Error::other("disk failure"));
+    match replay(read_lines("depth.txt"), ask).unwrap_err() {
+        EngineError::Io { path: None, source, .. } => {
+            assert_eq!(source.kind(), io::ErrorKind::Other);
+            assert!(source.to_string().starts_with("BTCUSDT ask trades stream line 2"), "{}", source);
+        }
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+
+    let mut depth = read_lines("depth.txt");
+    depth[2] = Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated"));
+    let error = replay(depth, read_lines("ask_trade.txt")).unwrap_err();
+    assert!(error.to_string().starts_with("BTCUSDT depth stream line 3"), "{}", error);
+}
+
+#[test]
+fn lines_that_are_not_text_are_malformed() {
+    let mut ask = read_lines("ask_trade.txt");
+    ask[4] = Err(io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"));
+    let report = replay(read_lines("depth.txt"), ask).unwrap();
+    assert_eq!(report.malformed_lines, MalformedLines { depth: 0, trades: 1 });
+    assert_eq!(event_time(&report.ask_results, "904"), Some(1702798596510));
+    assert_eq!(event_time(&report.ask_results, "905"), None);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use shougoutaku::engine::{self, Aggregation, ReplayOptions};
+use shougoutaku::exchange::Exchange;
+use shougoutaku::messages::TradeIds;
+use shougoutaku::trade_matcher::PURGED;
+
+use common::{fixture, fixtures, streams};
+
+fn ask_results(ask_trade: &str, aggregation: Aggregation) -> Vec<(String, u64, u64)> {
+    let options = ReplayOptions { aggregation, ..ReplayOptions::new(Exchange::Binance) };
+    let snapshot = engine::load_snapshot(Exchange::Binance, &fixture("aggregation", "snapshot.txt")).unwrap();
+    let streams = streams(&fixtures("aggregation"), ["depth.txt", ask_trade, "bid_trade.txt"]);
+    let report = engine::reconcile(&options, "", Some(snapshot), streams).unwrap();
+    report.ask_results.into_iter().collect()
+}
+
+#[test]
+fn agg_trade_keeps_its_trade_id_range() {
+    let line = std::fs::read_to_string(fixture("aggregation", "ask_agg_trade.txt")).unwrap();
+    let trade = Exchange::Binance.adapter().parse_trades(line.trim_end()).unwrap().remove(0);
+    assert_eq!(trade.trade_id, "77");
+    assert_eq!(trade.trade_ids, TradeIds::Range { first: 501, last: 502 });
+    assert_eq!(trade.raw_trade_ids(), vec!["501", "502"]);
+}
+
+#[test]
+fn time_price_aggregation_matches_merged_raw_trades() {
+    assert_eq!(ask_results("ask_trade.txt", Aggregation::TimePrice), vec![
+        (String::from("501"), 1702798596100, 1702798596120),
+        (String::from("502"), 1702798596100, 1702798596120),
+    ]);
+}
+
+#[test]
+fn no_aggregation_matches_raw_trades_alone() {
+    assert_eq!(ask_results("ask_trade.txt", Aggregation::None), vec![
+        (String::from("501"), 1702798596100, PURGED),
+        (String::from("502"), 1702798596100, PURGED),
+    ]);
+}
+
+#[test]
+fn agg_trades_report_the_raw_trades_they_cover() {
+    assert_eq!(ask_results("ask_agg_trade.txt", Aggregation::None), ask_results("ask_trade.txt", Aggregation::TimePrice));
+}
+
+#[test]
+fn parses_aggregation_names() {
+    assert_eq!("none".parse::<Aggregation>(), Ok(Aggregation::None));
+    assert_eq!("time-price".parse::<Aggregation>(), Ok(Aggregation::TimePrice));
+    assert!("price".parse::<Aggregation>().is_err());
+}